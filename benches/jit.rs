@@ -0,0 +1,50 @@
+//! Times the interpreter against the block-compiling backend on an ALU-heavy
+//! loop.
+//!
+//! Run with `cargo bench --features jit`.
+
+use criterion::{criterion_group, criterion_main, Criterion};
+use riscv_emu::core::Core;
+
+#[rustfmt::skip]
+const PROGRAM: [u32; 16] = [
+    0x00000513, // li    a0, 0
+    0x000015b7, // lui   a1, 1
+    0x00058593, // addi  a1, a1, 0
+    0x00012637, // lui   a2, 18
+    0x34560613, // addi  a2, a2, 837
+    // loop:
+    0x00350513, // addi  a0, a0, 3
+    0x00a64633, // xor   a2, a2, a0
+    0x00361693, // slli  a3, a2, 3
+    0x00565713, // srli  a4, a2, 5
+    0x00e68633, // add   a2, a3, a4
+    0x40a607b3, // sub   a5, a2, a0
+    0x0ff7f793, // andi  a5, a5, 255
+    0x00f56533, // or    a0, a0, a5
+    0xfff58593, // addi  a1, a1, -1
+    0xfc059ee3, // bnez  a1, loop
+    0x00100073, // ebreak
+];
+
+fn bench_loop(c: &mut Criterion) {
+    let image: Vec<u8> = PROGRAM.iter().flat_map(|inst| inst.to_le_bytes()).collect();
+    let mut group = c.benchmark_group("alu_loop");
+    for (name, jit) in [("interpreter", false), ("jit", true)] {
+        let mut core = Core::new();
+        core.config.jit = jit;
+        core.load_image(image.clone()).unwrap();
+        group.bench_function(name, |b| {
+            b.iter(|| {
+                // The program doesn't write memory, so a reset is enough to
+                // run it again
+                core.reset();
+                core.run()
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_loop);
+criterion_main!(benches);
@@ -0,0 +1,53 @@
+//! Runs a few instructions with a trace sink attached and checks the commit
+//! log against the lines Spike's `--log-commits` prints for them.
+//!
+//! Run with `cargo run --example commit_log`.
+
+use riscv_emu::core::Core;
+use std::cell::RefCell;
+use std::io::{self, Write};
+use std::rc::Rc;
+
+#[rustfmt::skip]
+const PROGRAM: [u32; 5] = [
+    0x02a00513, // li    a0, 42
+    0x800015b7, // lui   a1, 0x80001
+    0x00a5a023, // sw    a0, 0(a1)
+    0x0005a603, // lw    a2, 0(a1)
+    0x00100073, // ebreak
+];
+
+const EXPECTED: &str = "\
+core   0: 3 0x80000000 (0x02a00513) x10 0x0000002a
+core   0: 3 0x80000004 (0x800015b7) x11 0x80001000
+core   0: 3 0x80000008 (0x00a5a023) mem 0x80001000 0x0000002a
+core   0: 3 0x8000000c (0x0005a603) x12 0x0000002a mem 0x80001000
+core   0: 3 0x80000010 (0x00100073)
+";
+
+/// Collects the log in memory.
+#[derive(Clone, Default)]
+struct Sink(Rc<RefCell<Vec<u8>>>);
+
+impl Write for Sink {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+fn main() {
+    let image: Vec<u8> = PROGRAM.iter().flat_map(|inst| inst.to_le_bytes()).collect();
+    let mut core = Core::new();
+    let sink = Sink::default();
+    core.set_trace_sink(Box::new(sink.clone()));
+    core.load_image(image).unwrap();
+    core.run();
+    let log = String::from_utf8(sink.0.borrow().clone()).unwrap();
+    print!("{}", log);
+    assert_eq!(log, EXPECTED);
+}
@@ -0,0 +1,51 @@
+//! Times a tight loop with and without `Config::decode_cache`, and checks
+//! that both end in the same state.
+//!
+//! Run with `cargo run --release --example decode_bench`.
+
+use riscv_emu::core::Core;
+use std::time::Instant;
+
+#[rustfmt::skip]
+const PROGRAM: [u32; 11] = [
+    0x00000513, // li    a0, 0
+    0x002dc5b7, // lui   a1, 732
+    0x80001637, // lui   a2, 0x80001
+    // loop:
+    0x00062683, // lw    a3, 0(a2)
+    0x00368693, // addi  a3, a3, 3
+    0x00d54533, // xor   a0, a0, a3
+    0x00151713, // slli  a4, a0, 1
+    0x00d62023, // sw    a3, 0(a2)
+    0xfff58593, // addi  a1, a1, -1
+    0xfe0594e3, // bnez  a1, loop
+    0x00100073, // ebreak
+];
+
+fn run(decode_cache: bool) -> Core {
+    let image: Vec<u8> = PROGRAM.iter().flat_map(|inst| inst.to_le_bytes()).collect();
+    let mut core = Core::new();
+    core.config.decode_cache = decode_cache;
+    core.load_image(image).unwrap();
+    let start = Instant::now();
+    core.run();
+    eprintln!(
+        "{}: {} instructions in {:?}",
+        if decode_cache {
+            "decode cache"
+        } else {
+            "no cache"
+        },
+        core.cycle_count,
+        start.elapsed()
+    );
+    core
+}
+
+fn main() {
+    let uncached = run(false);
+    let cached = run(true);
+    assert_eq!(uncached.regs(), cached.regs());
+    assert_eq!(uncached.pc, cached.pc);
+    assert_eq!(uncached.cycle_count, cached.cycle_count);
+}
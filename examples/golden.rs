@@ -0,0 +1,138 @@
+//! Runs each fixture program in `test/golden` to completion and compares the
+//! final state against the `.json` golden file next to it. Every fixture has
+//! a UART at `UART_BASE`, which shares a console with HTIF and SBI, and if anything
+//! is written to it the output is compared against a `.out` file too.
+//!
+//! Build the fixtures with `make -C test/golden`, then run
+//! `cargo run --release --example golden > /dev/null`. Set `GOLDEN_REGEN=1`
+//! to rewrite the golden files from the current behavior instead.
+
+use riscv_emu::clint::{Clint, CLINT_BASE, CLINT_SIZE};
+use riscv_emu::config::{MisalignedAccess, Xlen};
+use riscv_emu::machine::Machine;
+use riscv_emu::mem::MmioDevice;
+use riscv_emu::uart::{Uart, UART_BASE, UART_SIZE};
+use std::cell::RefCell;
+use std::fs;
+use std::io::{self, Write};
+use std::path::Path;
+use std::rc::Rc;
+
+/// Sets up anything a fixture needs beyond the default config.
+fn configure(name: &str, machine: &mut Machine) {
+    let (mem, core) = (&mut machine.mem, &mut machine.harts[0]);
+    match name {
+        "misaligned" => core.config.misaligned = MisalignedAccess::Trap,
+        "mmio" => mem.map_device(0x8000_1000, 16, Box::new(Latch::default())),
+        "lenient_unmapped" => core.config.lenient_unmapped = true,
+        "htif" => core.config.tohost = Some(0x8000_1000),
+        "sbi" => core.config.sbi = true,
+        "wfi" => core.config.wfi_fast_forward = true,
+        "decode_cache" => core.config.decode_cache = true,
+        "pmp" => core.config.pmp = true,
+        "rv64" => core.config.xlen = Xlen::Rv64,
+        _ => {}
+    }
+    if matches!(name, "clint" | "timer_irq" | "wfi") {
+        let clint = Clint::new(std::slice::from_ref(core));
+        mem.map_device(CLINT_BASE, CLINT_SIZE, Box::new(clint));
+    }
+}
+
+/// A device that reads back the complement of the last value written to it,
+/// so the `mmio` fixture can tell it apart from RAM.
+#[derive(Default)]
+struct Latch(u32);
+
+impl MmioDevice for Latch {
+    fn read(&mut self, _offset: u32, width: u32) -> u32 {
+        !self.0 & (u32::MAX >> (32 - 8 * width))
+    }
+
+    fn write(&mut self, _offset: u32, _width: u32, val: u32) {
+        self.0 = val;
+    }
+}
+
+/// Collects what a fixture writes to its UART.
+#[derive(Clone, Default)]
+struct Console(Rc<RefCell<Vec<u8>>>);
+
+impl Write for Console {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Runs the fixture, returning its final state and console output.
+fn run_fixture(name: &str, image: &Path) -> (String, String) {
+    let mut machine = Machine::single_hart();
+    let console = Console::default();
+    let uart = Uart::new(Box::new(console.clone()));
+    machine.map_device(UART_BASE, UART_SIZE, Box::new(uart));
+    machine.harts[0].set_console(Box::new(console.clone()));
+    configure(name, &mut machine);
+    machine.load_image(&fs::read(image).unwrap()).unwrap();
+    machine.run_until_halt();
+    let output = String::from_utf8_lossy(&console.0.borrow()).into_owned();
+    let mut state = Vec::new();
+    machine.harts[0].dump_json(&mut state).unwrap();
+    (String::from_utf8(state).unwrap(), output)
+}
+
+/// Compares `actual` against the golden file at `path`, or rewrites it.
+/// Returns whether they matched.
+fn check(path: &Path, actual: &str, regen: bool) -> bool {
+    if regen {
+        fs::write(path, actual).unwrap();
+        return true;
+    }
+    fs::read_to_string(path).ok().as_deref() == Some(actual)
+}
+
+fn main() {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("test/golden");
+    let regen = std::env::var_os("GOLDEN_REGEN").is_some();
+
+    let mut images: Vec<_> = fs::read_dir(&dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "bin"))
+        .collect();
+    images.sort();
+    if images.is_empty() {
+        eprintln!("no fixtures built, run `make -C test/golden` first");
+        std::process::exit(1);
+    }
+
+    let mut failed = 0;
+    for image in &images {
+        let name = image.file_stem().unwrap().to_string_lossy();
+        let (state, output) = run_fixture(&name, image);
+        let out_file = image.with_extension("out");
+        let mut ok = check(&image.with_extension("json"), &state, regen);
+        if !output.is_empty() || out_file.exists() {
+            ok &= check(&out_file, &output, regen);
+        }
+        if regen {
+            eprintln!("{}: regenerated", name);
+        } else if ok {
+            eprintln!("{}: ok", name);
+        } else {
+            eprintln!("{}: MISMATCH, got:\n{}", name, state);
+            if !output.is_empty() {
+                eprintln!("and output:\n{}", output);
+            }
+            failed += 1;
+        }
+    }
+    if failed > 0 {
+        eprintln!("{} of {} fixtures failed", failed, images.len());
+        std::process::exit(1);
+    }
+}
@@ -0,0 +1,51 @@
+//! Times sequential word and halfword accesses to RAM through `Mem`, first
+//! with nothing else mapped and then with a device mapped over the top page
+//! of RAM, which takes every access off the fast path. Both runs must read
+//! back what they wrote.
+//!
+//! Run with `cargo run --release --example mem_bench`.
+
+use riscv_emu::mem::{Mem, MmioDevice, MEMORY_SIZE, RAM_BASE};
+use std::time::Instant;
+
+/// Passes over the first `SPAN` bytes of RAM
+const PASSES: u32 = 64;
+const SPAN: u32 = 1 << 20;
+
+struct Null;
+
+impl MmioDevice for Null {
+    fn read(&mut self, _offset: u32, _width: u32) -> u32 {
+        0
+    }
+
+    fn write(&mut self, _offset: u32, _width: u32, _val: u32) {}
+}
+
+fn run(name: &str, mem: &mut Mem) -> u32 {
+    let start = Instant::now();
+    for addr in (RAM_BASE..RAM_BASE + SPAN).step_by(4) {
+        mem.sw(addr, addr);
+    }
+    let mut sum = 0u32;
+    for _ in 0..PASSES {
+        for addr in (RAM_BASE..RAM_BASE + SPAN).step_by(4) {
+            sum = sum.wrapping_add(mem.lw(addr));
+        }
+        for addr in (RAM_BASE..RAM_BASE + SPAN).step_by(2) {
+            sum = sum.wrapping_add(mem.lh(addr) as u32);
+        }
+    }
+    let accesses = SPAN / 4 + PASSES * (SPAN / 4 + SPAN / 2);
+    eprintln!("{}: {} accesses in {:?}", name, accesses, start.elapsed());
+    sum
+}
+
+fn main() {
+    let plain = run("ram only", &mut Mem::default());
+    let mut mem = Mem::default();
+    let top_page = RAM_BASE + MEMORY_SIZE as u32 - 0x1000;
+    mem.map_device(top_page, 0x1000, Box::new(Null));
+    let with_device = run("device over ram", &mut mem);
+    assert_eq!(plain, with_device);
+}
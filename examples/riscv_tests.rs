@@ -0,0 +1,74 @@
+//! Runs every RV32 ELF in a directory as a riscv-tests ISA test and reports
+//! which passed, going by the code each one writes to `tohost`.
+//!
+//! Build the suite with `./configure --with-xlen=32 && make -C isa` in a
+//! riscv-tests checkout, then run
+//! `cargo run --release --example riscv_tests -- <riscv-tests>/isa`. Files
+//! that aren't ELF, like the `.dump` disassemblies, are skipped, so adding a
+//! test is just a matter of dropping its binary into the directory.
+
+use riscv_emu::core::{Core, StepOutcome};
+use riscv_emu::elf;
+use std::fs;
+use std::path::Path;
+
+/// The tests are short, so this is only hit by one that never finishes
+const MAX_STEPS: usize = 1_000_000;
+
+/// Runs the test in `data`, returning why it failed if it did.
+fn run_test(data: &[u8]) -> Result<(), String> {
+    let mut core = Core::new();
+    core.load_elf(data).map_err(|err| err.to_string())?;
+    if core.config.tohost.is_none() {
+        return Err("no tohost symbol".to_string());
+    }
+    match core.run_for(MAX_STEPS) {
+        StepOutcome::HtifExit(0) => Ok(()),
+        StepOutcome::HtifExit(test) => Err(format!("test {} failed", test)),
+        StepOutcome::Continue => Err(format!("still running after {} steps", MAX_STEPS)),
+        outcome => Err(format!("stopped with {:?} at {:#010x}", outcome, core.pc)),
+    }
+}
+
+fn main() {
+    let dir = match std::env::args().nth(1) {
+        Some(dir) => dir,
+        None => {
+            eprintln!("usage: riscv_tests <dir>");
+            std::process::exit(1);
+        }
+    };
+
+    let mut tests: Vec<_> = fs::read_dir(Path::new(&dir))
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.is_file())
+        .collect();
+    tests.sort();
+
+    let mut ran = 0;
+    let mut failed = 0;
+    for path in &tests {
+        let data = fs::read(path).unwrap();
+        if !elf::is_elf(&data) {
+            continue;
+        }
+        ran += 1;
+        let name = path.file_name().unwrap().to_string_lossy();
+        match run_test(&data) {
+            Ok(()) => eprintln!("{}: ok", name),
+            Err(reason) => {
+                eprintln!("{}: FAILED, {}", name, reason);
+                failed += 1;
+            }
+        }
+    }
+    if ran == 0 {
+        eprintln!("no ELF files in {}", dir);
+        std::process::exit(1);
+    }
+    eprintln!("{} of {} tests passed", ran - failed, ran);
+    if failed > 0 {
+        std::process::exit(1);
+    }
+}
@@ -0,0 +1,41 @@
+//! Times a tight loop with instruction tracing on and off, to show what the
+//! trace costs.
+//!
+//! The traced run prints every instruction, so run with stdout redirected:
+//! `cargo run --release --example trace_bench > /dev/null`
+
+use riscv_emu::core::Core;
+use std::time::Instant;
+
+#[rustfmt::skip]
+const PROGRAM: [u32; 6] = [
+    0x00000513, // li    a0, 0
+    0x000195b7, // lui   a1, 25
+    // loop:
+    0x00150513, // addi  a0, a0, 1
+    0xfff58593, // addi  a1, a1, -1
+    0xfe059ce3, // bnez  a1, loop
+    0x00100073, // ebreak
+];
+
+fn run(trace: bool) -> Core {
+    let image: Vec<u8> = PROGRAM.iter().flat_map(|inst| inst.to_le_bytes()).collect();
+    let mut core = Core::new();
+    core.set_trace(trace);
+    core.load_image(image).unwrap();
+    let start = Instant::now();
+    core.run();
+    eprintln!(
+        "tracing {}: {} instructions in {:?}",
+        if trace { "on" } else { "off" },
+        core.cycle_count,
+        start.elapsed()
+    );
+    core
+}
+
+fn main() {
+    let traced = run(true);
+    let quiet = run(false);
+    assert_eq!(traced.regs(), quiet.regs());
+}
@@ -0,0 +1,99 @@
+use crate::core::Core;
+use crate::irq::{InterruptController, Timer, MSIP};
+use crate::mem::MmioDevice;
+
+pub const CLINT_BASE: u32 = 0x02000000;
+pub const CLINT_SIZE: u32 = 0x10000;
+/// The CLINT's device tree `compatible` string
+pub const CLINT_COMPATIBLE: &str = "riscv,clint0";
+
+const MSIP_BASE: u32 = 0;
+const MTIMECMP_BASE: u32 = 0x4000;
+const MTIME: u32 = 0xBFF8;
+
+/// The SiFive-style core-local interruptor: a software interrupt register
+/// for each hart at `msip`, and the machine timer's `mtimecmp` and `mtime`.
+///
+/// Every hart keeps its own `mtime`, which it advances as it executes. In a
+/// `Machine` they stay within a round of each other, so reads come from hart
+/// 0's and a write sets them all.
+pub struct Clint {
+    harts: Vec<(InterruptController, Timer)>,
+}
+
+impl Clint {
+    /// A CLINT for `harts`, in hart ID order.
+    pub fn new(harts: &[Core]) -> Clint {
+        Clint {
+            harts: harts
+                .iter()
+                .map(|hart| (hart.irq.clone(), hart.timer.clone()))
+                .collect(),
+        }
+    }
+
+    fn mtime(&self) -> u64 {
+        self.harts.first().map_or(0, |(_, timer)| timer.mtime())
+    }
+}
+
+/// Replaces the 32-bit half of `reg` that `offset` selects.
+fn set_half(reg: u64, offset: u32, val: u32) -> u64 {
+    if offset & 4 == 0 {
+        (reg & !0xFFFFFFFF) | val as u64
+    } else {
+        (reg & 0xFFFFFFFF) | (val as u64) << 32
+    }
+}
+
+fn half(reg: u64, offset: u32) -> u32 {
+    (reg >> ((offset & 4) * 8)) as u32
+}
+
+impl MmioDevice for Clint {
+    fn read(&mut self, offset: u32, _width: u32) -> u32 {
+        let hart = |base: u32, stride: u32| self.harts.get(((offset - base) / stride) as usize);
+        match offset {
+            MTIME..=0xBFFF => half(self.mtime(), offset),
+            MTIMECMP_BASE..=0xBFF7 => {
+                hart(MTIMECMP_BASE, 8).map_or(0, |(_, timer)| half(timer.mtimecmp(), offset))
+            }
+            MSIP_BASE..=0x3FFF => {
+                hart(MSIP_BASE, 4).map_or(0, |(irq, _)| irq.is_raised(MSIP) as u32)
+            }
+            _ => 0,
+        }
+    }
+
+    fn write(&mut self, offset: u32, _width: u32, val: u32) {
+        match offset {
+            MTIME..=0xBFFF => {
+                let mtime = set_half(self.mtime(), offset, val);
+                for (_, timer) in &self.harts {
+                    timer.set_mtime(mtime);
+                }
+            }
+            MTIMECMP_BASE..=0xBFF7 => {
+                let hart = ((offset - MTIMECMP_BASE) / 8) as usize;
+                if let Some((_, timer)) = self.harts.get(hart) {
+                    timer.set_mtimecmp(set_half(timer.mtimecmp(), offset, val));
+                }
+            }
+            MSIP_BASE..=0x3FFF => {
+                if let Some((irq, _)) = self.harts.get((offset / 4) as usize) {
+                    // Only bit 0 is implemented
+                    if val & 1 != 0 {
+                        irq.raise(MSIP);
+                    } else {
+                        irq.lower(MSIP);
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn compatible(&self) -> Option<&'static str> {
+        Some(CLINT_COMPATIBLE)
+    }
+}
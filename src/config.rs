@@ -0,0 +1,156 @@
+use crate::clint::CLINT_BASE;
+use crate::mem::{MEMORY_SIZE, RAM_BASE};
+use crate::uart::UART_BASE;
+
+/// What to do with a load or store that isn't naturally aligned.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MisalignedAccess {
+    /// Perform the access byte by byte as if nothing were wrong.
+    Allow,
+    /// Raise an address-misaligned exception for the guest to handle.
+    Trap,
+    /// Raise the exception, but service it with a built-in M-mode handler
+    /// that performs the access byte by byte and returns, as firmware on many
+    /// real cores does. The guest sees the access succeed, but pays for the
+    /// trap in cycles and it is counted in `Stats::misaligned_traps`.
+    Emulate,
+}
+
+/// How wide the integer registers are.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Xlen {
+    Rv32,
+    /// RV64I and M. The physical address space is still 32 bits, so an
+    /// access or jump above 4 GiB faults, and CSRs stay 32 bits wide, reading
+    /// zero-extended. A, F, C, Zba, Zbb, S mode and Sv32 are left to 32-bit
+    /// harts.
+    Rv64,
+}
+
+#[derive(Debug, Clone)]
+pub struct Config {
+    /// Pick this before running anything: the registers only keep their
+    /// low halves across a switch
+    pub xlen: Xlen,
+    /// Where RAM starts and how many bytes of it there are, for
+    /// `Machine::with_config` and `MemoryMap::from_config`
+    pub ram_base: u32,
+    pub ram_size: usize,
+    /// Where `Machine::with_config` maps a UART and a CLINT, if anywhere
+    pub uart_base: Option<u32>,
+    pub clint_base: Option<u32>,
+    pub misaligned: MisalignedAccess,
+    /// Enforce the spec wherever the emulator would otherwise be lenient:
+    /// `MisalignedAccess::Allow` traps instead, `lenient_unmapped` is
+    /// ignored, WARL fields reject illegal values, and reading `cycle`,
+    /// `time` or `instret` below M mode needs its bit in `mcounteren`, and
+    /// in U mode `scounteren` too.
+    pub strict: bool,
+    /// Make explicit data accesses big-endian in every privilege mode, as
+    /// reported by mstatus.UBE and mstatush.SBE/MBE. Instruction fetches and
+    /// page table walks are always little-endian.
+    pub big_endian: bool,
+    /// Value of the read-only `mconfigptr` CSR
+    pub mconfigptr: u32,
+    /// If the emulator itself panics partway through an instruction, print
+    /// the hart's state and its last few instructions first, to stderr or
+    /// wherever `Core::set_panic_dump_sink` says.
+    pub panic_dump: bool,
+    /// How many of the most recently executed instructions to keep for
+    /// `Core::recent_instructions`. `panic_dump` keeps a few even if this is
+    /// zero.
+    pub history_len: usize,
+    /// Count how many times each basic block is entered, for
+    /// `Core::hot_blocks`.
+    pub profile_blocks: bool,
+    /// Warn about guest reads from device registers that are still in their
+    /// reset state, which usually means a driver forgot to set its device up.
+    /// The warnings go to `Core::set_warning_sink`, and each one is counted in
+    /// `Stats::uninit_mmio_reads`.
+    pub warn_uninit_mmio: bool,
+    /// Let loads from addresses with nothing mapped at them read as zero, and
+    /// drop stores to them, instead of raising an access fault. Accesses that
+    /// are only partly unmapped, and writes to ROM, still fault.
+    pub lenient_unmapped: bool,
+    /// Physical address of the HTIF `tohost` word, usually from the
+    /// `tohost` symbol. Commands written there end the run with
+    /// `StepOutcome::HtifExit` or print to the HTIF console.
+    pub tohost: Option<u32>,
+    /// Act as SBI firmware: `ecall`s from S mode are serviced by the
+    /// emulator, covering the legacy console, timer and shutdown calls and
+    /// the base, TIME and SRST extensions, instead of trapping to M mode.
+    /// The timer raises STIP rather than MTIP, as firmware forwarding it
+    /// would, so delegate it in `mideleg` as firmware would too.
+    pub sbi: bool,
+    /// Make `wfi` with nothing pending jump `mtime` ahead to `mtimecmp` if
+    /// the timer interrupt is enabled, so idle loops waiting on the timer
+    /// don't spin. Only this hart's timer moves, so harts sharing a CLINT
+    /// drift apart. Without it `wfi` does nothing.
+    pub wfi_fast_forward: bool,
+    /// Implement the C extension: 16-bit compressed instructions are
+    /// expanded and executed, and instructions only need to be 2-byte
+    /// aligned. Without it, a jump or taken branch to an address that isn't a
+    /// multiple of 4 raises instruction-address-misaligned.
+    pub compressed: bool,
+    /// Implement the Zicbom cache management instructions (`cbo.clean`,
+    /// `cbo.flush` and `cbo.inval`). There's no cache, so they do nothing.
+    /// Below M mode they also have to be enabled in `menvcfg`, and in U mode
+    /// in `senvcfg` too.
+    pub zicbom: bool,
+    /// Implement `cbo.zero` from Zicboz, subject to `menvcfg` and `senvcfg`
+    /// like Zicbom.
+    pub zicboz: bool,
+    /// Implement the Zba address generation instructions (`sh1add`,
+    /// `sh2add` and `sh3add`).
+    pub zba: bool,
+    /// Implement the Zbb basic bit-manipulation instructions, such as
+    /// `andn`, `clz`, `min`, `rol` and `rev8`.
+    pub zbb: bool,
+    /// Implement 16 PMP entries and enforce them on every physical access.
+    /// As the spec requires, S and U mode then can't access anything until
+    /// M mode has set up an entry allowing it, so this is off by default for
+    /// guests that don't expect to have to.
+    pub pmp: bool,
+    /// Keep decoded instructions by physical address, so code that runs
+    /// again skips fetching and decoding it. Guest stores and `fence.i` keep
+    /// it up to date, but host writes straight to `mem` aren't noticed, so
+    /// call `Core::flush_decode_cache` after patching guest code.
+    pub decode_cache: bool,
+    /// Run through `Core::step_block`, executing straight-line code as
+    /// compiled blocks instead of one instruction at a time.
+    #[cfg(feature = "jit")]
+    pub jit: bool,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            xlen: Xlen::Rv32,
+            ram_base: RAM_BASE,
+            ram_size: MEMORY_SIZE,
+            uart_base: Some(UART_BASE),
+            clint_base: Some(CLINT_BASE),
+            misaligned: MisalignedAccess::Allow,
+            strict: false,
+            big_endian: false,
+            mconfigptr: 0,
+            panic_dump: false,
+            history_len: 0,
+            profile_blocks: false,
+            warn_uninit_mmio: false,
+            lenient_unmapped: false,
+            tohost: None,
+            sbi: false,
+            wfi_fast_forward: false,
+            compressed: false,
+            zicbom: true,
+            zicboz: true,
+            zba: true,
+            zbb: true,
+            pmp: false,
+            decode_cache: false,
+            #[cfg(feature = "jit")]
+            jit: false,
+        }
+    }
+}
@@ -1,326 +1,4588 @@
-use crate::mem::{Mem, MEMORY_SIZE};
+use crate::config::{Config, MisalignedAccess, Xlen};
+use crate::csr;
+use crate::decode::{self, DecodeCache, Decoded, Opcode};
+use crate::disasm::{disassemble, reg_index, DecodedInst, REG_NAMES};
+use crate::elf::{self, Elf, ElfError};
+use crate::fpu::{self, Rounding};
+use crate::htif;
+use crate::irq::{self, InterruptController, Timer};
+#[cfg(feature = "jit")]
+use crate::jit::BlockCache;
+use crate::mem::{AccessFault, Mem, RamImage};
+use crate::mmu::{self, AccessType, Leaf, PageWalk, PTE_A, PTE_D, SATP_MODE_SV32};
+use crate::pmp;
+use crate::rvc;
+use crate::sbi;
+use crate::symbols::SymbolTable;
+use crate::trap::Exception;
+use std::cmp::Ordering;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::fmt;
+use std::io;
+use std::panic::{self, AssertUnwindSafe};
+use std::path::Path;
 
-fn read_imm_i(inst: u32) -> u32 {
+/// Physical address of byte `i` of an access starting at `addr` that
+/// continues at `next` once it crosses a page boundary.
+fn split_byte(addr: u32, next: u32, i: u32) -> u32 {
+    let before_boundary = mmu::PAGE_SIZE - addr % mmu::PAGE_SIZE;
+    if i < before_boundary {
+        addr.wrapping_add(i)
+    } else {
+        next.wrapping_add(i - before_boundary)
+    }
+}
+
+/// The access fault an access of type `access` raises.
+fn access_fault(access: AccessType) -> Exception {
+    match access {
+        AccessType::Fetch => Exception::InstructionAccessFault,
+        AccessType::Load => Exception::LoadAccessFault,
+        AccessType::Store => Exception::StoreAccessFault,
+    }
+}
+
+pub(crate) fn read_imm_i(inst: u32) -> u32 {
     (inst as i32 >> 20) as u32
 }
 
-fn read_imm_s(inst: u32) -> u32 {
+pub(crate) fn read_imm_s(inst: u32) -> u32 {
     (read_imm_i(inst) & !0b11111) | ((inst >> 7) & 0b11111)
 }
 
-fn read_imm_b(inst: u32) -> u32 {
-    let low = (inst >> 7) & 0b11111 & !1;
-    let mid = (inst << 4) & (1 << 11);
-    let high = read_imm_i(inst) & !0b11111 & !(1 << 11);
-    low | mid | high
+/// Sign-extends the low `bits` bits of `val`.
+fn sign_extend(val: u32, bits: u32) -> u32 {
+    ((val << (32 - bits)) as i32 >> (32 - bits)) as u32
+}
+
+/// imm[12|10:5] is in inst[31:25] and imm[4:1|11] in inst[11:7].
+pub(crate) fn read_imm_b(inst: u32) -> u32 {
+    let imm = ((inst >> 31) & 1) << 12
+        | ((inst >> 7) & 1) << 11
+        | ((inst >> 25) & 0b111111) << 5
+        | ((inst >> 8) & 0b1111) << 1;
+    sign_extend(imm, 13)
 }
 
-fn read_imm_u(inst: u32) -> u32 {
+pub(crate) fn read_imm_u(inst: u32) -> u32 {
     inst & !(0xFFF)
 }
 
-fn read_imm_j(inst: u32) -> u32 {
-    let a = read_imm_i(inst) & 0xFFF007FE;
-    let b = inst & 0x000FF000;
-    let c = (inst & (1 << 20)) >> 9;
-    a | b | c
+/// imm[20|10:1|11|19:12] is in inst[31:12].
+pub(crate) fn read_imm_j(inst: u32) -> u32 {
+    let imm = ((inst >> 31) & 1) << 20
+        | inst & 0xFF000
+        | ((inst >> 20) & 1) << 11
+        | ((inst >> 21) & 0x3FF) << 1;
+    sign_extend(imm, 21)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StepOutcome {
+    Continue,
+    Ebreak,
+    /// A debugging stop requested by the host, e.g. through
+    /// `Core::trap_on_csr`. Holds the pc of the instruction that caused it,
+    /// which hasn't executed yet, and will when the core is next stepped.
+    Break(u32),
+    /// The pc reached an address breakpoint. The instruction there hasn't
+    /// executed yet, and will when the core is next stepped.
+    Breakpoint(u32),
+    /// The instruction stored to this watched physical address, from
+    /// `Core::set_watchpoint`. The store has completed.
+    Watchpoint(u32),
+    /// The guest asked to exit through HTIF's `tohost`, with 0 for success
+    /// or the number of the failing test.
+    HtifExit(u32),
+    /// The guest asked `Config::sbi` firmware to shut the machine down.
+    Shutdown,
+    /// The instruction raised an exception, which has been taken.
+    Trap(Exception),
+    /// An interrupt with this cause code was taken instead of executing an
+    /// instruction.
+    Interrupt(u32),
+}
+
+impl StepOutcome {
+    /// Whether a run loop should hand control back to the caller.
+    pub fn stops(self) -> bool {
+        !matches!(
+            self,
+            StepOutcome::Continue | StepOutcome::Trap(_) | StepOutcome::Interrupt(_)
+        )
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum PrivMode {
+    User = 0,
+    Supervisor = 1,
+    Machine = 3,
+}
+
+impl PrivMode {
+    /// Decodes a two-bit privilege field such as mstatus.MPP. The reserved
+    /// encoding is treated as User.
+    fn from_bits(bits: u32) -> PrivMode {
+        match bits & 0b11 {
+            3 => PrivMode::Machine,
+            1 => PrivMode::Supervisor,
+            _ => PrivMode::User,
+        }
+    }
+}
+
+/// The argument registers at an `ecall`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SyscallArgs {
+    /// `a0` through `a7`. By convention `a7` holds the syscall number.
+    pub regs: [u32; 8],
+}
+
+impl SyscallArgs {
+    pub fn number(&self) -> u32 {
+        self.regs[7]
+    }
 }
 
+/// Why `Core::load_and_run` couldn't start the program.
 #[derive(Debug)]
-enum Opcode {
-    OpImm,
-    Lui,
-    Auipc,
-    Op,
-    Jal,
-    Jalr,
-    Branch,
-    Load,
-    Store,
-    MiscMem,
-    System,
-    Amo,
+pub enum LoadError {
+    Io(io::Error),
+    Elf(ElfError),
+    /// A flat binary bigger than RAM
+    TooBig,
+}
+
+impl From<io::Error> for LoadError {
+    fn from(err: io::Error) -> Self {
+        LoadError::Io(err)
+    }
+}
+
+impl From<ElfError> for LoadError {
+    fn from(err: ElfError) -> Self {
+        LoadError::Elf(err)
+    }
+}
+
+impl From<AccessFault> for LoadError {
+    fn from(_: AccessFault) -> Self {
+        LoadError::TooBig
+    }
+}
+
+/// How a run from `Core::load_and_run` ended.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RunReport {
+    /// What stopped the core, or `None` if it ran out of budget.
+    pub outcome: Option<StepOutcome>,
+    /// Instructions stepped, including any that trapped
+    pub instructions: usize,
+    pub cycles: usize,
+}
+
+/// A hart's state and the contents of its RAM, from `Core::snapshot`, to
+/// return to later with `Core::restore`. Devices, hooks and the config
+/// aren't part of it.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Snapshot {
+    pc: u32,
+    priv_mode: PrivMode,
+    reg: [u32; 32],
+    reg_high: [u32; 32],
+    fregs: [u32; 32],
+    csrs: Box<[u32; 4096]>,
+    cycle_count: usize,
+    mtime: u64,
+    mtimecmp: u64,
+    reservation: Option<u32>,
+    ram: RamImage,
+}
+
+/// A piece of architectural state, as named by a `Diff`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StateField {
+    Pc,
+    Priv,
+    Reg(usize),
+    Csr(u32),
+}
+
+/// A field that differs between two cores, with its value in each.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Diff {
+    pub field: StateField,
+    pub ours: u32,
+    pub theirs: u32,
+}
+
+impl fmt::Display for Diff {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.field {
+            StateField::Pc => write!(f, "pc")?,
+            StateField::Priv => write!(f, "priv")?,
+            StateField::Reg(i) => write!(f, "x{} ({})", i, REG_NAMES[i])?,
+            StateField::Csr(addr) => write!(f, "csr {:#05x}", addr)?,
+        }
+        write!(f, ": {:08x} != {:08x}", self.ours, self.theirs)
+    }
+}
+
+const ECALL: u32 = 0x00000073;
+
+/// Sign bit of a single-precision value
+const FP_SIGN: u32 = 1 << 31;
+
+// Auxiliary vector entry types
+const AT_NULL: u32 = 0;
+const AT_PAGESZ: u32 = 6;
+
+/// Rough cost of trapping into firmware that splits up a misaligned access.
+const MISALIGNED_FIXUP_CYCLES: usize = 30;
+/// Size of the block `cbo.zero` clears
+const CACHE_BLOCK_SIZE: u32 = 64;
+/// The CSRs `Core::dump_state` shows, four to a line
+const DUMP_CSRS: [u32; 8] = [
+    csr::MSTATUS,
+    csr::MEPC,
+    csr::MCAUSE,
+    csr::MTVAL,
+    csr::SATP,
+    csr::SEPC,
+    csr::SCAUSE,
+    csr::STVAL,
+];
+
+/// How many instructions `Config::panic_dump` keeps for the dump, at least
+const RECENT_INSTS: usize = 16;
+
+/// Counters for events that are interesting when debugging or profiling a
+/// guest.
+#[derive(Debug, Clone, Default)]
+pub struct Stats {
+    /// Misaligned accesses serviced by `MisalignedAccess::Emulate`.
+    pub misaligned_traps: usize,
+    pub sc_successes: usize,
+    pub sc_failures: usize,
+    /// Reads flagged by `Config::warn_uninit_mmio`.
+    pub uninit_mmio_reads: usize,
+}
+
+/// Called after a guest store commits, with its physical address, size and
+/// value.
+pub type MemHook = Box<dyn FnMut(&Core, u32, u32, u32)>;
+/// Called after an instruction writes a watched register, with the new value.
+pub type RegWatch = Box<dyn FnMut(&Core, u32)>;
+/// Called around each executed instruction, with its encoding.
+pub type StepHook = Box<dyn FnMut(&Core, u32)>;
+/// Services an `ecall` in the host, returning the value for `a0`.
+pub type EcallHandler = Box<dyn FnMut(&mut Core, SyscallArgs) -> u32>;
+
+/// Recent values written to one register.
+struct RegHistory {
+    reg: usize,
+    capacity: usize,
+    entries: Vec<(u64, u32)>,
+}
+
+/// The effects of one instruction, as `Core::set_trace_sink` logs them
+#[derive(Default)]
+struct Commit {
+    /// `(prefix, register, value)` for each register written, `x` or `f`
+    regs: Vec<(char, usize, u64)>,
+    /// Virtual addresses loaded from
+    loads: Vec<u32>,
+    /// `(address, size, value)` for each store
+    stores: Vec<(u32, u32, u64)>,
+}
+
+impl Commit {
+    fn clear(&mut self) {
+        self.regs.clear();
+        self.loads.clear();
+        self.stores.clear();
+    }
 }
 
 pub struct Core {
     pub mem: Mem,
     pub csrs: [u32; 4096],
-    pub reg: [u32; 32],
+    /// x0 is always zero, which `set_reg` upholds
+    reg: [u32; 32],
+    /// The upper halves of the registers on a 64-bit hart, zero otherwise.
+    /// Keeping them apart leaves `reg` as the low halves, which is all the
+    /// code shared with RV32 needs.
+    reg_high: [u32; 32],
+    /// The F extension's registers, as raw single-precision bits. With FLEN
+    /// 32 they're exactly as wide as the values, so there's no NaN-boxing to
+    /// check until D comes along.
+    fregs: [u32; 32],
     pub pc: u32,
     pub cycle_count: usize,
+    /// Where `reset` sends the pc
+    pub reset_pc: u32,
+    pub priv_mode: PrivMode,
+    pub config: Config,
+    pub stats: Stats,
+    pub symbols: SymbolTable,
+    /// Interrupt lines into this hart, for devices to raise
+    pub irq: InterruptController,
+    pub timer: Timer,
+    csr_traps: HashSet<u32>,
+    /// Where the last step stopped for `csr_traps`, so stepping again there
+    /// carries the instruction out
+    csr_break: Option<u32>,
+    breakpoints: HashSet<u32>,
+    /// Physical addresses to stop after a store to
+    watchpoints: HashSet<u32>,
+    /// The first watched address the current instruction stored to
+    watch_hit: Option<u32>,
+    /// Set when the current instruction asked to exit through HTIF
+    htif_exit: Option<u32>,
+    /// The counter CSR the current instruction wrote, whose own count the
+    /// write replaces
+    counter_written: Option<u32>,
+    console: Box<dyn io::Write>,
+    panic_sink: Box<dyn io::Write>,
+    warning_sink: Box<dyn io::Write>,
+    mem_hook: Option<MemHook>,
+    pre_hook: Option<StepHook>,
+    post_hook: Option<StepHook>,
+    reg_watches: Vec<(usize, RegWatch)>,
+    reg_histories: Vec<RegHistory>,
+    ecall_handlers: HashMap<u32, EcallHandler>,
+    /// Print each instruction to stdout before executing it
+    trace: bool,
+    /// Where `set_trace_sink` sends the commit log
+    trace_sink: Option<Box<dyn io::Write>>,
+    /// What the current instruction has changed so far, for `trace_sink`
+    commit: Commit,
+    /// Physical address of the word reserved by the last LR
+    reservation: Option<u32>,
+    /// Times each basic block was entered, by start address, for
+    /// `Config::profile_blocks`
+    block_counts: HashMap<u32, u64>,
+    /// Whether the next instruction starts a basic block
+    block_start: bool,
+    /// `(pc, inst)` of the last few instructions, for `Config::history_len`
+    /// and `panic_dump`
+    recent: VecDeque<(u32, u32)>,
+    /// Size in bytes of the instruction being executed, 2 if it was
+    /// compressed
+    inst_len: u32,
+    /// Physical address and size of the store made by the instruction just
+    /// stepped, so a `Machine` can break other harts' reservations
+    pub(crate) last_store: Option<(u32, u32)>,
+    /// The instruction the last `step` executed, for `Machine::lockstep`'s
+    /// trace. `None` if it took an interrupt or the fetch faulted instead.
+    pub(crate) last_inst: Option<u32>,
+    /// For `Config::decode_cache`
+    decode_cache: DecodeCache,
+    #[cfg(feature = "jit")]
+    jit: BlockCache,
+}
+
+impl Default for Core {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Core {
     pub fn new() -> Core {
+        Core::with_mem(Default::default())
+    }
+
+    /// A core without any memory of its own, for use as a hart in a `Machine`.
+    pub fn detached() -> Core {
+        Core::with_mem(Mem::empty())
+    }
+
+    /// A core with `mem` as its memory, such as `Mem::new` with a
+    /// different RAM size or base. It starts at, and resets to, the start of
+    /// RAM.
+    pub fn with_mem(mem: Mem) -> Core {
+        let ram_base = mem.ram_base();
         Core {
-            mem: Default::default(),
+            mem,
             csrs: [0; 4096],
             reg: [0; 32],
-            pc: 0x80000000,
+            reg_high: [0; 32],
+            fregs: [0; 32],
+            pc: ram_base,
             cycle_count: 0,
+            reset_pc: ram_base,
+            priv_mode: PrivMode::Machine,
+            config: Default::default(),
+            stats: Default::default(),
+            symbols: Default::default(),
+            irq: Default::default(),
+            timer: Default::default(),
+            csr_traps: HashSet::new(),
+            csr_break: None,
+            breakpoints: HashSet::new(),
+            watchpoints: HashSet::new(),
+            watch_hit: None,
+            htif_exit: None,
+            counter_written: None,
+            console: Box::new(io::stdout()),
+            panic_sink: Box::new(io::stderr()),
+            warning_sink: Box::new(io::stderr()),
+            mem_hook: None,
+            pre_hook: None,
+            post_hook: None,
+            reg_watches: Vec::new(),
+            reg_histories: Vec::new(),
+            ecall_handlers: HashMap::new(),
+            trace: false,
+            trace_sink: None,
+            commit: Default::default(),
+            reservation: None,
+            block_counts: HashMap::new(),
+            block_start: true,
+            recent: VecDeque::new(),
+            inst_len: 4,
+            last_store: None,
+            last_inst: None,
+            decode_cache: Default::default(),
+            #[cfg(feature = "jit")]
+            jit: Default::default(),
+        }
+    }
+
+    /// Captures everything needed to carry on from this point later.
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            pc: self.pc,
+            priv_mode: self.priv_mode,
+            reg: self.reg,
+            reg_high: self.reg_high,
+            fregs: self.fregs,
+            csrs: Box::new(self.csrs),
+            cycle_count: self.cycle_count,
+            mtime: self.timer.mtime(),
+            mtimecmp: self.timer.mtimecmp(),
+            reservation: self.reservation,
+            ram: self.mem.ram_image(),
         }
     }
 
+    /// Returns to the state `snapshot` was taken in. The history kept for
+    /// `recent_instructions` and the block counts aren't rolled back.
+    pub fn restore(&mut self, snapshot: &Snapshot) {
+        self.pc = snapshot.pc;
+        self.priv_mode = snapshot.priv_mode;
+        self.reg = snapshot.reg;
+        self.reg_high = snapshot.reg_high;
+        self.fregs = snapshot.fregs;
+        self.csrs = *snapshot.csrs;
+        self.cycle_count = snapshot.cycle_count;
+        self.timer.set_mtime(snapshot.mtime);
+        self.timer.set_mtimecmp(snapshot.mtimecmp);
+        self.reservation = snapshot.reservation;
+        self.mem.restore_ram(&snapshot.ram);
+        self.block_start = true;
+        self.flush_code();
+    }
+
+    /// Sends the core to `reset_pc`, which `load_elf` sets to the entry
+    /// point, in M mode with the registers, CSRs, cycle count and any LR
+    /// reservation cleared, so one core can run program after program.
+    /// The hart's `mtime` and `mtimecmp` are reset and its interrupt lines
+    /// lowered too, though devices can raise them again. `mhartid` keeps its
+    /// value. Memory, including the rest of any device state, is left as it
+    /// is.
     pub fn reset(&mut self) {
+        let hartid = self.csrs[csr::MHARTID as usize];
+        self.csrs = [0; 4096];
+        self.csrs[csr::MHARTID as usize] = hartid;
         self.reg = [0; 32];
-        self.pc = 0x80000000;
+        self.reg_high = [0; 32];
+        self.fregs = [0; 32];
+        self.pc = self.reset_pc;
+        self.cycle_count = 0;
+        self.priv_mode = PrivMode::Machine;
+        self.reservation = None;
+        self.timer.reset();
+        self.irq.reset();
+        self.recent.clear();
+        self.block_start = true;
+        self.flush_code();
+    }
+
+    /// Carries on executing from `pc`, for a harness that calls straight
+    /// into a guest routine. Nothing else changes, unlike `reset`.
+    pub fn set_pc(&mut self, pc: u32) {
+        self.pc = pc;
+        self.block_start = true;
+    }
+
+    /// Resets the registers and starts executing at `entry`, which also
+    /// becomes the reset vector. Memory is left as it is.
+    pub fn reset_to_entry(&mut self, entry: u32) {
+        self.reset_pc = entry;
+        self.reset();
+    }
+
+    /// Copies `data` into RAM at `addr` without touching the rest of memory,
+    /// so several blobs can be placed before starting the core.
+    pub fn load_image_at(&mut self, addr: u32, data: &[u8]) -> Result<(), AccessFault> {
+        self.flush_code();
+        self.mem.load_image_at(addr, data)
+    }
+
+    /// Reads guest memory at physical address `addr` into `buf`, for
+    /// debuggers and test harnesses. Unlike the guest's own loads, only RAM
+    /// and ROM can be read, so looking never disturbs a device. See
+    /// `Mem::peek`.
+    pub fn read_mem(&self, addr: u32, buf: &mut [u8]) -> Result<(), AccessFault> {
+        self.mem.peek(addr, buf)
+    }
+
+    /// Writes `data` to guest RAM at physical address `addr`, all or
+    /// nothing. Cached decodes and compiled code for it are dropped, so this
+    /// is safe for patching code too. See `Mem::poke`.
+    pub fn write_mem(&mut self, addr: u32, data: &[u8]) -> Result<(), AccessFault> {
+        self.mem.poke(addr, data)?;
+        // Invalidation is cheap for an access-sized write, but the jit only
+        // looks at the pages either end, so anything longer flushes
+        if data.len() <= 4 {
+            self.invalidate_code(addr, data.len() as u32);
+        } else {
+            self.flush_code();
+        }
+        Ok(())
     }
 
-    pub fn load_image(&mut self, mut data: Vec<u8>) {
-        assert!(data.len() <= MEMORY_SIZE, "Image does not fit in memory!");
+    /// Borrows `len` bytes of guest RAM at physical address `base`, for
+    /// parsing guest data structures in place. `None` if the range isn't
+    /// entirely plain RAM.
+    pub fn memory_view(&self, base: u32, len: u32) -> Option<&[u8]> {
+        self.mem.view(base, len)
+    }
+
+    /// Maps `data` as a read-only region at `base`, such as a boot ROM.
+    /// Guest stores to it raise a store access fault.
+    pub fn load_raw_to_rom(&mut self, base: u32, data: Vec<u8>) {
+        self.mem.map_rom(base, data);
+    }
 
+    /// Clears RAM, copies the flat binary `data` to the start of it and
+    /// resets the core. Fails, changing nothing, if it doesn't fit.
+    pub fn load_image(&mut self, data: Vec<u8>) -> Result<(), AccessFault> {
+        self.mem.load_image(&data)?;
         self.reset();
-        data.extend(std::iter::repeat(0).take(MEMORY_SIZE - data.len()));
-        self.mem.mem.clone_from_slice(data.as_slice());
+        Ok(())
+    }
+
+    /// Like `load_image`, but for an image linked to run at `base` rather
+    /// than the start of RAM. Nothing is relocated: the image is placed at
+    /// `base` and the core resets to it. RAM is cleared first either way.
+    pub fn load_image_with_base(&mut self, base: u32, data: Vec<u8>) -> Result<(), AccessFault> {
+        self.mem.clear_ram();
+        self.load_image_at(base, &data)?;
+        self.reset_to_entry(base);
+        Ok(())
+    }
+
+    /// Loads an ELF32 executable: RAM is cleared, each loadable segment is
+    /// copied to its physical address and the core resets to the entry point.
+    /// The file's symbols replace `symbols` if it has any, and a `tohost`
+    /// symbol sets `Config::tohost`.
+    pub fn load_elf(&mut self, data: &[u8]) -> Result<(), ElfError> {
+        let elf = Elf::parse(data)?;
+        self.mem.clear_ram();
+        for seg in &elf.segments {
+            // The part past the file data is already zero
+            let fits =
+                seg.data.len() <= seg.mem_size as usize && self.mem.is_ram(seg.paddr, seg.mem_size);
+            if !fits || self.load_image_at(seg.paddr, &seg.data).is_err() {
+                return Err(ElfError::BadSegment(seg.paddr));
+            }
+        }
+        if let Some(tohost) = elf.symbols.address_of("tohost") {
+            self.config.tohost = Some(tohost);
+        }
+        if !elf.symbols.is_empty() {
+            self.symbols = elf.symbols;
+        }
+        self.reset_to_entry(elf.entry);
+        Ok(())
+    }
+
+    /// Loads the ELF or flat binary at `path`, telling them apart by the ELF
+    /// magic number, and runs it for at most `max` instructions.
+    pub fn load_and_run(
+        &mut self,
+        path: impl AsRef<Path>,
+        max: usize,
+    ) -> Result<RunReport, LoadError> {
+        let data = std::fs::read(path)?;
+        if elf::is_elf(&data) {
+            self.load_elf(&data)?;
+        } else {
+            self.load_image(data)?;
+        }
+        let start = self.cycle_count;
+        let mut report = RunReport {
+            outcome: None,
+            instructions: 0,
+            cycles: 0,
+        };
+        while report.instructions < max {
+            let outcome = self.step();
+            report.instructions += 1;
+            if outcome.stops() {
+                report.outcome = Some(outcome);
+                break;
+            }
+        }
+        report.cycles = self.cycle_count - start;
+        Ok(report)
+    }
+
+    /// Stop with `StepOutcome::Break` at any instruction that reads or
+    /// writes the CSR at `addr`, before it does.
+    pub fn trap_on_csr(&mut self, addr: u32) {
+        self.csr_traps.insert(addr);
+    }
+
+    pub fn clear_csr_trap(&mut self, addr: u32) {
+        self.csr_traps.remove(&addr);
+    }
+
+    /// Print every instruction to stdout, with its pc, symbol and
+    /// disassembly, as it executes. Off by default.
+    pub fn set_trace(&mut self, trace: bool) {
+        self.trace = trace;
+    }
+
+    /// Writes a line to `sink` for every instruction that retires, in the
+    /// format of Spike's `--log-commits`, so a run can be diffed against
+    /// Spike's. Each line has the hart, privilege mode, pc and instruction,
+    /// then any register written and the virtual address of each load and
+    /// each store along with the value stored. CSR writes aren't logged.
+    /// Instructions that trap don't retire, so they're left out.
+    pub fn set_trace_sink(&mut self, sink: Box<dyn io::Write>) {
+        self.trace_sink = Some(sink);
+    }
+
+    pub fn clear_trace_sink(&mut self) {
+        self.trace_sink = None;
+    }
+
+    /// Stop with `StepOutcome::Breakpoint` whenever the pc arrives at `addr`.
+    pub fn add_breakpoint(&mut self, addr: u32) {
+        self.breakpoints.insert(addr);
+    }
+
+    pub fn remove_breakpoint(&mut self, addr: u32) {
+        self.breakpoints.remove(&addr);
+    }
+
+    /// Stop with `StepOutcome::Watchpoint` after any instruction that stores
+    /// to the byte at physical address `addr`.
+    pub fn set_watchpoint(&mut self, addr: u32) {
+        self.watchpoints.insert(addr);
+    }
+
+    pub fn clear_watchpoint(&mut self, addr: u32) {
+        self.watchpoints.remove(&addr);
+    }
+
+    /// Where characters the guest prints through HTIF or SBI go, stdout by
+    /// default.
+    pub fn set_console(&mut self, out: Box<dyn io::Write>) {
+        self.console = out;
+    }
+
+    /// Where `Config::panic_dump` writes the hart's state, stderr by default.
+    pub fn set_panic_dump_sink(&mut self, out: Box<dyn io::Write>) {
+        self.panic_sink = out;
+    }
+
+    /// Where warnings such as `Config::warn_uninit_mmio` go, stderr by
+    /// default.
+    pub fn set_warning_sink(&mut self, out: Box<dyn io::Write>) {
+        self.warning_sink = out;
+    }
+
+    /// Sets a breakpoint on the entry of the symbol `name`, returning its
+    /// address. Fails if there's no such symbol.
+    #[allow(clippy::result_unit_err)]
+    pub fn set_breakpoint_on_symbol(&mut self, name: &str) -> Result<u32, ()> {
+        let addr = self.symbols.address_of(name).ok_or(())?;
+        self.add_breakpoint(addr);
+        Ok(addr)
+    }
+
+    pub fn set_mem_hook(&mut self, hook: impl FnMut(&Core, u32, u32, u32) + 'static) {
+        self.mem_hook = Some(Box::new(hook));
+    }
+
+    pub fn clear_mem_hook(&mut self) {
+        self.mem_hook = None;
+    }
+
+    /// Calls `hook` just before each instruction executes.
+    pub fn set_pre_hook(&mut self, hook: impl FnMut(&Core, u32) + 'static) {
+        self.pre_hook = Some(Box::new(hook));
+    }
+
+    /// Calls `hook` once each instruction has fully retired.
+    pub fn set_post_hook(&mut self, hook: impl FnMut(&Core, u32) + 'static) {
+        self.post_hook = Some(Box::new(hook));
+    }
+
+    pub fn clear_step_hooks(&mut self) {
+        self.pre_hook = None;
+        self.post_hook = None;
+    }
+
+    pub fn watch_reg(&mut self, reg: usize, watch: impl FnMut(&Core, u32) + 'static) {
+        self.reg_watches.push((reg, Box::new(watch)));
+    }
+
+    pub fn clear_reg_watches(&mut self) {
+        self.reg_watches.clear();
+    }
+
+    /// Services `ecall`s with syscall number `num` (in `a7`) by calling
+    /// `handler` instead of trapping into the guest. Its return value is
+    /// written to `a0` and execution carries on after the `ecall`. Numbers
+    /// without a handler trap as usual.
+    pub fn register_ecall_handler(
+        &mut self,
+        num: u32,
+        handler: impl FnMut(&mut Core, SyscallArgs) -> u32 + 'static,
+    ) {
+        self.ecall_handlers.insert(num, Box::new(handler));
+    }
+
+    pub fn unregister_ecall_handler(&mut self, num: u32) {
+        self.ecall_handlers.remove(&num);
+    }
+
+    /// Starts recording the last `capacity` values written to `reg`, along
+    /// with the cycle that wrote them.
+    pub fn track_reg_history(&mut self, reg: usize, capacity: usize) {
+        assert!(capacity > 0, "History needs room for at least one value");
+        self.reg_histories.retain(|h| h.reg != reg);
+        self.reg_histories.push(RegHistory {
+            reg,
+            capacity,
+            entries: Vec::with_capacity(capacity),
+        });
+    }
+
+    pub fn untrack_reg_history(&mut self, reg: usize) {
+        self.reg_histories.retain(|h| h.reg != reg);
+    }
+
+    /// `(cycle, value)` for each recorded write to `reg`, oldest first. Empty
+    /// if the register isn't tracked.
+    pub fn reg_history(&self, reg: usize) -> &[(u64, u32)] {
+        self.reg_histories
+            .iter()
+            .find(|h| h.reg == reg)
+            .map_or(&[], |h| &h.entries)
     }
 
-    pub fn run(&mut self) {
+    /// Runs until the core stops by itself, returning why. Loops forever if
+    /// it never does, so use `run_for` where that matters.
+    pub fn run(&mut self) -> StepOutcome {
         loop {
-            self.step();
+            #[cfg(feature = "jit")]
+            let outcome = if self.config.jit {
+                self.step_block()
+            } else {
+                self.step()
+            };
+            #[cfg(not(feature = "jit"))]
+            let outcome = self.step();
+            if outcome.stops() {
+                return outcome;
+            }
         }
     }
 
-    pub fn step(&mut self) {
-        let inst = self.mem.lw(self.pc);
+    /// Steps at most `max` times, returning the outcome the core stopped
+    /// with, or `StepOutcome::Continue` if it used up the budget first. Each
+    /// step is one instruction even with the JIT, so the count is exact.
+    pub fn run_for(&mut self, max: usize) -> StepOutcome {
+        for _ in 0..max {
+            let outcome = self.step();
+            if outcome.stops() {
+                return outcome;
+            }
+        }
+        StepOutcome::Continue
+    }
 
-        let rs1_raw = (inst >> 15) & 0b11111;
-        let rs2_raw = (inst >> 20) & 0b11111;
-        let rd_raw = (inst >> 7) & 0b11111;
-        let funct3 = (inst >> 12) & 0b111;
-        let funct7 = (inst >> 25) & 0b1111111;
+    /// Steps at most `max` times, calling `f` after each step so the host
+    /// can model peripherals or check for events. Stops early if `f` returns
+    /// false. Returns the outcome if the core stopped by itself, or `None` if
+    /// `f` stopped it or the budget ran out.
+    pub fn run_callback(
+        &mut self,
+        max: usize,
+        mut f: impl FnMut(&mut Core) -> bool,
+    ) -> Option<StepOutcome> {
+        for _ in 0..max {
+            let outcome = self.step();
+            if outcome.stops() {
+                return Some(outcome);
+            }
+            if !f(self) {
+                return None;
+            }
+        }
+        None
+    }
 
-        let rs1 = self.reg[rs1_raw as usize];
-        let rs2 = self.reg[rs2_raw as usize];
-        let rd = &mut self.reg[rd_raw as usize];
-
-        let opcode = match (inst & 0b1111100) >> 2 {
-            0b00000 => Opcode::Load,
-            0b01000 => Opcode::Store,
-            0b11000 => Opcode::Branch,
-            0b11001 => Opcode::Jalr,
-            0b11011 => Opcode::Jal,
-            0b00011 => Opcode::MiscMem,
-            0b00100 => Opcode::OpImm,
-            0b01100 => Opcode::Op,
-            0b11100 => Opcode::System,
-            0b00101 => Opcode::Auipc,
-            0b01101 => Opcode::Lui,
-            0b01011 => Opcode::Amo,
-            x => panic!("Hit invalid opcode: {:05b}", x),
-        };
-
-        println!(
-            "Inst: {:032b}, pc: {:08x}, Opcode: {:?}",
-            inst, self.pc, opcode
-        );
+    /// Runs until the next `ecall` is about to execute, for at most `max`
+    /// instructions. The `ecall` itself is not executed, so the caller can
+    /// inspect or service it. Returns `None` if the budget ran out or the
+    /// core stopped for some other reason first.
+    pub fn run_to_syscall(&mut self, max: usize) -> Option<SyscallArgs> {
+        for _ in 0..max {
+            if self.fetch() == Ok(ECALL) {
+                return Some(self.syscall_args());
+            }
+            if self.step().stops() {
+                return None;
+            }
+        }
+        None
+    }
 
-        match opcode {
-            Opcode::Load => {
-                let addr = rs1 + read_imm_i(inst);
-                *rd = match funct3 {
-                    0 => self.mem.lb(addr) as i8 as i32 as u32,
-                    1 => self.mem.lh(addr) as i32 as u32,
-                    2 => self.mem.lw(addr),
-                    4 => self.mem.lb(addr) as u32,
-                    5 => self.mem.lh(addr) as u32,
-                    x => {
-                        println!("Invalid load width: {}", x);
-                        return;
-                    }
-                };
-                self.pc += 4;
+    fn syscall_args(&self) -> SyscallArgs {
+        let mut regs = [0; 8];
+        regs.copy_from_slice(&self.reg[10..18]);
+        SyscallArgs { regs }
+    }
+
+    /// Lays out the initial stack a Linux userspace program expects at the
+    /// top of RAM: the argument and environment strings, then below them
+    /// argc, the argv and envp pointer arrays and the auxiliary vector. `sp`
+    /// is left pointing at argc.
+    pub fn setup_user_stack(&mut self, args: &[&str], env: &[&str]) {
+        let mut top = self.mem.ram_base().wrapping_add(self.mem.ram_size() as u32);
+        let mut push_str = |mem: &mut Mem, s: &str| {
+            top = top.wrapping_sub(s.len() as u32 + 1);
+            for (i, &b) in s.as_bytes().iter().enumerate() {
+                mem.sb(top.wrapping_add(i as u32), b);
             }
-            Opcode::Store => {
-                let addr = rs1 + read_imm_s(inst);
-                match funct3 {
-                    0 => self.mem.sb(addr, rs2 as u8),
-                    1 => self.mem.sh(addr, rs2 as u16),
-                    2 => self.mem.sw(addr, rs2 as u32),
-                    x => {
-                        println!("Invalid store width: {}", x);
-                        return;
-                    }
+            mem.sb(top.wrapping_add(s.len() as u32), 0);
+            top
+        };
+        let argv: Vec<u32> = args.iter().map(|a| push_str(&mut self.mem, a)).collect();
+        let envp: Vec<u32> = env.iter().map(|e| push_str(&mut self.mem, e)).collect();
+
+        let mut words = vec![args.len() as u32];
+        words.extend(&argv);
+        words.push(0);
+        words.extend(&envp);
+        words.push(0);
+        words.extend(&[AT_PAGESZ, mmu::PAGE_SIZE, AT_NULL, 0]);
+
+        let sp = top.wrapping_sub(4 * words.len() as u32) & !0xf;
+        for (i, &word) in words.iter().enumerate() {
+            self.mem.sw(sp.wrapping_add(4 * i as u32), word);
+        }
+        self.reg[2] = sp;
+    }
+
+    /// Reads a CSR as an instruction would see it.
+    pub fn read_csr(&self, addr: u32) -> u32 {
+        match addr {
+            // Lines raised by devices and the timers show up alongside the
+            // software bits
+            csr::MIP => {
+                // SBI firmware would forward the machine timer to S mode
+                let mtimer = if self.config.sbi {
+                    irq::STIP
+                } else {
+                    irq::MTIP
                 };
-                self.pc += 4;
+                let timer = (self.timer.is_pending() as u32) << mtimer
+                    | (self.stimecmp_pending() as u32) << irq::STIP;
+                self.csrs[csr::MIP as usize] | self.irq.pending() | timer
             }
-            Opcode::Branch => {
-                let cond = match funct3 {
-                    0 => rs1 == rs2,
-                    1 => rs1 != rs2,
-                    4 => (rs1 as i32) < (rs2 as i32),
-                    5 => (rs1 as i32) >= (rs2 as i32),
-                    6 => rs1 < rs2,
-                    7 => rs1 >= rs2,
-                    x => {
-                        println!("Invalid branch condition: {}", x);
-                        return;
-                    }
-                };
-                if cond {
-                    self.pc += read_imm_b(inst);
-                } else {
-                    self.pc += 4;
+            csr::SIP => self.read_csr(csr::MIP) & self.csrs[csr::MIDELEG as usize],
+            csr::SIE => self.csrs[csr::MIE as usize] & self.csrs[csr::MIDELEG as usize],
+            csr::SSTATUS => self.read_csr(csr::MSTATUS) & csr::SSTATUS_MASK,
+            csr::TIME => self.mtime() as u32,
+            csr::TIMEH => (self.mtime() >> 32) as u32,
+            // The unprivileged counters are read-only shadows of the
+            // machine ones
+            csr::CYCLE => self.csrs[csr::MCYCLE as usize],
+            csr::CYCLEH => self.csrs[csr::MCYCLEH as usize],
+            csr::INSTRET => self.csrs[csr::MINSTRET as usize],
+            csr::INSTRETH => self.csrs[csr::MINSTRETH as usize],
+            csr::MSTATUS => {
+                let mut status = self.csrs[csr::MSTATUS as usize];
+                if self.config.big_endian {
+                    status |= csr::MSTATUS_UBE;
                 }
+                if status & csr::MSTATUS_FS == csr::MSTATUS_FS {
+                    status |= csr::MSTATUS_SD;
+                }
+                status
             }
-            Opcode::Jalr => {
-                *rd = self.pc + 4;
-                self.pc = rs1 + (read_imm_i(inst) & !1);
+            csr::FFLAGS => self.csrs[csr::FCSR as usize] & csr::FCSR_FFLAGS,
+            csr::FRM => (self.csrs[csr::FCSR as usize] & csr::FCSR_FRM) >> csr::FCSR_FRM_SHIFT,
+            csr::MSTATUSH if self.config.big_endian => csr::MSTATUSH_SBE | csr::MSTATUSH_MBE,
+            csr::MSTATUSH => 0,
+            csr::MCONFIGPTR => self.config.mconfigptr,
+            csr::MISA => self.misa(),
+            _ => self.csrs[addr as usize],
+        }
+    }
+
+    /// The ISA this hart implements, as `misa` reports it. It's fixed, so
+    /// writes to `misa` are ignored.
+    fn misa(&self) -> u32 {
+        if self.rv64() {
+            // MXL sits in bits 63:62, which `misa64` adds
+            return csr::misa_ext('I') | csr::misa_ext('M') | csr::misa_ext('U');
+        }
+        let mut misa = csr::MISA_MXL_32
+            | csr::misa_ext('I')
+            | csr::misa_ext('M')
+            | csr::misa_ext('A')
+            | csr::misa_ext('F')
+            | csr::misa_ext('S')
+            | csr::misa_ext('U');
+        if self.compressed() {
+            misa |= csr::misa_ext('C');
+        }
+        misa
+    }
+
+    /// Writes a CSR as an instruction would.
+    pub fn write_csr(&mut self, addr: u32, val: u32) {
+        match addr {
+            // Only the supervisor bits are software-writable, the rest
+            // follow the interrupt lines
+            csr::MIP => {
+                let mask = (1 << irq::SSIP) | (1 << irq::STIP) | (1 << irq::SEIP);
+                self.csrs[addr as usize] = val & mask;
             }
-            Opcode::Jal => {
-                *rd = self.pc + 4;
-                self.pc += read_imm_j(inst);
+            // Fields we don't implement are read-only zero
+            csr::MSTATUS => {
+                let mut val = val & csr::MSTATUS_WRITABLE;
+                // MPP is WARL, and 2 isn't a privilege mode
+                let mpp = (val & csr::MSTATUS_MPP) >> csr::MSTATUS_MPP_SHIFT;
+                if self.config.strict && mpp == 2 {
+                    val = (val & !csr::MSTATUS_MPP) | (self.csrs[addr as usize] & csr::MSTATUS_MPP);
+                }
+                self.csrs[addr as usize] = val;
             }
-            Opcode::MiscMem => {
-                // No need for fencing: only one hart
-                self.pc += 4;
+            // Only delegated interrupts are visible from S mode, and of
+            // those only SSIP is writable
+            csr::SIP => {
+                let mask = self.csrs[csr::MIDELEG as usize] & (1 << irq::SSIP);
+                let mip = self.csrs[csr::MIP as usize];
+                self.csrs[csr::MIP as usize] = (mip & !mask) | (val & mask);
             }
-            Opcode::OpImm => {
-                let imm = read_imm_i(inst);
-                *rd = match funct3 {
-                    0 => rs1 + imm,
-                    2 => ((rs1 as i32) < (imm as i32)) as u32,
-                    3 => (rs1 < imm) as u32,
-                    4 => rs1 ^ imm,
-                    6 => rs1 | imm,
-                    7 => rs1 & imm,
-                    1 => rs1 << (imm & 0b11111),
-                    5 => {
-                        let shamt = imm & 0b11111;
-                        if (imm & (1 << 10)) == 0 {
-                            rs1 >> shamt
-                        } else {
-                            ((rs1 as i32) >> shamt) as u32
-                        }
-                    }
-                    _ => unreachable!(),
-                };
-                self.pc += 4;
+            csr::SIE => {
+                let mask = self.csrs[csr::MIDELEG as usize];
+                let mie = self.csrs[csr::MIE as usize];
+                self.csrs[csr::MIE as usize] = (mie & !mask) | (val & mask);
             }
-            Opcode::Op => {
-                *rd = match funct3 {
-                    0 => {
-                        if (funct7 & (1 << 5)) == 0 {
-                            rs1 + rs2
-                        } else {
-                            rs1 - rs2
-                        }
-                    }
-                    2 => ((rs1 as i32) < (rs2 as i32)) as u32,
-                    3 => (rs1 < rs2) as u32,
-                    4 => rs1 ^ rs2,
-                    6 => rs1 | rs2,
-                    7 => rs1 & rs2,
-                    1 => rs1 << (rs2 & 0b11111),
-                    5 => {
-                        let shamt = rs2 & 0b11111;
-                        if (funct7 & (1 << 5)) == 0 {
-                            rs1 >> shamt
-                        } else {
-                            ((rs1 as i32) >> shamt) as u32
-                        }
-                    }
-                    _ => unreachable!(),
-                };
-                self.pc += 4;
+            csr::SSTATUS => {
+                let mstatus = self.csrs[csr::MSTATUS as usize];
+                let mask = csr::SSTATUS_MASK;
+                self.write_csr(csr::MSTATUS, (mstatus & !mask) | (val & mask));
             }
-            Opcode::System => {
-                let funct12 = inst >> 20;
-                match funct3 {
-                    0b000 => {
-                        if funct12 == 0 {
-                            todo!("ECALL");
-                        } else if funct12 == 1 {
-                            println!("Hit EBREAK");
-                            println!("Cycle count: {}", self.cycle_count);
-                            println!("Register state:");
-                            for (i, &val) in self.reg.iter().enumerate() {
-                                println!(" x{}: {:x} ({})", i, val, val);
-                            }
-                            std::process::exit(0);
-                        } else {
-                            panic!();
-                        }
-                    }
-                    0b001 => {
-                        let temp = self.csrs[funct12 as usize];
-                        self.csrs[funct12 as usize] = rs1;
-                        *rd = temp;
-                    }
-                    0b010 => {
-                        let temp = self.csrs[funct12 as usize];
-                        self.csrs[funct12 as usize] |= rs1;
-                        *rd = temp;
-                    }
-                    0b011 => {
-                        let temp = self.csrs[funct12 as usize];
-                        self.csrs[funct12 as usize] = temp & !rs1;
-                        *rd = temp;
-                    }
-                    0b101 => {
-                        let temp = self.csrs[funct12 as usize];
-                        self.csrs[funct12 as usize] = rs1_raw;
-                        *rd = temp;
-                    }
-                    0b110 => {
-                        let temp = self.csrs[funct12 as usize];
-                        self.csrs[funct12 as usize] |= rs1_raw;
-                        *rd = temp;
-                    }
-                    0b111 => {
-                        let temp = self.csrs[funct12 as usize];
-                        self.csrs[funct12 as usize] = temp & !rs1_raw;
-                        *rd = temp;
-                    }
-                    _ => panic!()
-                }
-                self.pc += 4;
+            // fflags and frm are views of fcsr
+            csr::FFLAGS => {
+                let fcsr = self.csrs[csr::FCSR as usize] & !csr::FCSR_FFLAGS;
+                self.write_csr(csr::FCSR, fcsr | (val & csr::FCSR_FFLAGS));
             }
-            Opcode::Auipc => {
-                *rd = self.pc + read_imm_u(inst);
-                self.pc += 4;
+            csr::FRM => {
+                let fcsr = self.csrs[csr::FCSR as usize] & !csr::FCSR_FRM;
+                self.write_csr(
+                    csr::FCSR,
+                    fcsr | (val << csr::FCSR_FRM_SHIFT & csr::FCSR_FRM),
+                );
             }
-            Opcode::Lui => {
-                *rd = read_imm_u(inst);
-                self.pc += 4;
+            csr::FCSR => {
+                self.csrs[addr as usize] = val & (csr::FCSR_FRM | csr::FCSR_FFLAGS);
+                self.csrs[csr::MSTATUS as usize] |= csr::MSTATUS_FS;
             }
-            Opcode::Amo => {
-                assert!(funct3 == 0b010, "Invalid width for AMO");
-                match funct7 >> 2 {
-                    0b00010 => {
-                        *rd = self.mem.lw(rs1);
-                    }
-                    0b00011 => {
-                        self.mem.sw(rs1, rs2);
-                        *rd = 0;
-                    }
-                    0b00001 => {
-                        let temp = self.mem.lw(rs1);
-                        self.mem.sw(rs1, rs2);
-                        *rd = temp;
-                    }
-                    x => {
-                        let temp = self.mem.lw(rs1);
-                        *rd = temp;
-                        let new = match x {
-                            0b00000 => temp + rs2,
-                            0b00100 => temp ^ rs2,
-                            0b01100 => temp & rs2,
-                            0b01000 => temp | rs2,
-                            0b10000 => (temp as i32).min(rs2 as i32) as u32,
-                            0b10100 => (temp as i32).max(rs2 as i32) as u32,
-                            0b11000 => temp.min(rs2),
-                            0b11100 => temp.max(rs2),
-                            x => panic!("{}", x),
-                        };
-                        self.mem.sw(rs1, new);
-                    }
+            csr::PMPCFG0..=csr::PMPCFG3 if self.config.pmp => {
+                self.csrs[addr as usize] = pmp::write_cfg(self.csrs[addr as usize], val);
+            }
+            csr::PMPADDR0..=csr::PMPADDR15 if self.config.pmp => {
+                if !pmp::addr_locked(&self.csrs, (addr - csr::PMPADDR0) as usize) {
+                    self.csrs[addr as usize] = val;
                 }
-                self.pc += 4;
             }
+            // Without PMP there are no entries, so they read as zero
+            csr::PMPCFG0..=csr::PMPCFG3
+            | csr::PMPADDR0..=csr::PMPADDR15
+            | csr::MSTATUSH
+            | csr::MHARTID
+            | csr::MCONFIGPTR
+            | csr::MISA
+            | csr::CYCLE
+            | csr::CYCLEH
+            | csr::TIME
+            | csr::TIMEH
+            | csr::INSTRET
+            | csr::INSTRETH => {}
+            _ => self.csrs[addr as usize] = val,
         }
+    }
 
-        self.cycle_count += 1;
-        // writes to x0 are discarded
-        self.reg[0] = 0;
+    /// Walks the Sv32 page table for `vaddr` without changing anything, for
+    /// debugging translation. Returns `None` if paging is off in `satp`.
+    pub fn inspect_page_table(&mut self, vaddr: u32) -> Option<PageWalk> {
+        let satp = self.csrs[csr::SATP as usize];
+        if satp & SATP_MODE_SV32 == 0 {
+            return None;
+        }
+        Some(mmu::walk(&mut self.mem, satp, vaddr))
+    }
+
+    /// The privilege mode an access is translated and checked in. MPRV makes
+    /// M-mode loads and stores act as if they were made in MPP, while
+    /// fetches always use the current mode.
+    fn access_priv(&self, access: AccessType) -> PrivMode {
+        let mstatus = self.csrs[csr::MSTATUS as usize];
+        if access != AccessType::Fetch
+            && self.priv_mode == PrivMode::Machine
+            && mstatus & csr::MSTATUS_MPRV != 0
+        {
+            PrivMode::from_bits(mstatus >> csr::MSTATUS_MPP_SHIFT)
+        } else {
+            self.priv_mode
+        }
+    }
+
+    /// Whether PMP lets an access of `size` bytes at physical address `addr`
+    /// through, checked in the mode `access_priv` gives. Always true with
+    /// `Config::pmp` off.
+    fn pmp_allows(&self, addr: u32, size: u32, access: AccessType) -> bool {
+        !self.config.pmp || pmp::allows(&self.csrs, addr, size, access, self.access_priv(access))
+    }
+
+    /// Translates a virtual address in the mode `access_priv` gives.
+    fn translate(&mut self, vaddr: u32, access: AccessType) -> Result<u32, Exception> {
+        match self.check_translation(vaddr, access)? {
+            Some(leaf) => {
+                self.mark_accessed(&leaf, access);
+                Ok(leaf.paddr)
+            }
+            None => Ok(vaddr),
+        }
+    }
+
+    /// Finds and permission-checks the leaf mapping `vaddr`, without updating
+    /// it. `None` if translation is off.
+    fn check_translation(
+        &mut self,
+        vaddr: u32,
+        access: AccessType,
+    ) -> Result<Option<Leaf>, Exception> {
+        let satp = self.csrs[csr::SATP as usize];
+        let priv_mode = self.access_priv(access);
+        if priv_mode == PrivMode::Machine || satp & SATP_MODE_SV32 == 0 {
+            return Ok(None);
+        }
+
+        let fault = match access {
+            AccessType::Fetch => Exception::InstructionPageFault,
+            AccessType::Load => Exception::LoadPageFault,
+            AccessType::Store => Exception::StorePageFault,
+        };
+        let walk = mmu::walk(&mut self.mem, satp, vaddr);
+        // The walk's own reads are S-mode loads as far as PMP is concerned,
+        // and one it can't make faults the access it was for
+        if self.config.pmp {
+            let allowed = |&(pte_addr, _): &(u32, u32)| {
+                pmp::allows(
+                    &self.csrs,
+                    pte_addr,
+                    4,
+                    AccessType::Load,
+                    PrivMode::Supervisor,
+                )
+            };
+            if !walk.ptes.iter().all(allowed) {
+                return Err(access_fault(access));
+            }
+        }
+        let leaf = walk.result.map_err(|_| fault)?;
+        let mstatus = self.csrs[csr::MSTATUS as usize];
+        let allowed = match access {
+            AccessType::Fetch => leaf.executable(),
+            // MXR makes execute-only pages readable
+            AccessType::Load => {
+                leaf.readable() || (mstatus & csr::MSTATUS_MXR != 0 && leaf.executable())
+            }
+            AccessType::Store => leaf.writable(),
+        };
+        let user_ok = if priv_mode == PrivMode::User {
+            leaf.user()
+        } else {
+            // S-mode can only touch user pages with SUM set, and can never
+            // execute from them
+            !leaf.user() || (access != AccessType::Fetch && mstatus & csr::MSTATUS_SUM != 0)
+        };
+        if !allowed || !user_ok {
+            return Err(fault);
+        }
+        Ok(Some(leaf))
+    }
+
+    /// Updates A and D ourselves rather than making the guest handle a fault.
+    fn mark_accessed(&mut self, leaf: &Leaf, access: AccessType) {
+        let mut pte = leaf.pte | PTE_A;
+        if access == AccessType::Store {
+            pte |= PTE_D;
+        }
+        if pte != leaf.pte {
+            self.mem.sw(leaf.pte_addr, pte);
+        }
+    }
+
+    /// Translates a data access of `size` bytes at `vaddr`. Returns the
+    /// physical address of the first byte and, if the access runs into a page
+    /// that isn't physically contiguous with the first, the physical address
+    /// the rest of it continues at.
+    ///
+    /// Both pages are checked before either is marked accessed, first page
+    /// first, so the fault reported is the lowest-addressed one and comes with
+    /// the address it was hit at.
+    fn translate_data(
+        &mut self,
+        vaddr: u32,
+        size: u32,
+        access: AccessType,
+    ) -> Result<(u32, Option<u32>), (Exception, u32)> {
+        let first = self
+            .check_translation(vaddr, access)
+            .map_err(|cause| (cause, vaddr))?;
+        let before_boundary = mmu::PAGE_SIZE - vaddr % mmu::PAGE_SIZE;
+        let second = if size > before_boundary {
+            let next = vaddr.wrapping_add(before_boundary);
+            self.check_translation(next, access)
+                .map_err(|cause| (cause, next))?
+        } else {
+            None
+        };
+
+        let addr = match first {
+            Some(leaf) => {
+                self.mark_accessed(&leaf, access);
+                leaf.paddr
+            }
+            None => vaddr,
+        };
+        let split = match second {
+            Some(leaf) => {
+                self.mark_accessed(&leaf, access);
+                if leaf.paddr == addr.wrapping_add(before_boundary) {
+                    None
+                } else {
+                    Some(leaf.paddr)
+                }
+            }
+            None => None,
+        };
+        let allowed = match split {
+            None => self.pmp_allows(addr, size, access),
+            Some(next) => {
+                self.pmp_allows(addr, before_boundary, access)
+                    && self.pmp_allows(next, size - before_boundary, access)
+            }
+        };
+        if !allowed {
+            return Err((access_fault(access), vaddr));
+        }
+        Ok((addr, split))
+    }
+
+    pub(crate) fn fetch(&mut self) -> Result<u32, Exception> {
+        self.fetch_raw(true)
+    }
+
+    /// Fetches the instruction at `pc` like `fetch`, along with its decoded
+    /// form if `Config::decode_cache` is on. A miss decodes it and fills the
+    /// cache. Translation is still done every time, so the cache holds
+    /// across changes to the page tables.
+    fn fetch_decoded(&mut self) -> Result<(u32, Option<Decoded>), Exception> {
+        if !self.config.decode_cache {
+            return Ok((self.fetch()?, None));
+        }
+        if self.pc & (self.inst_align() - 1) != 0 {
+            return Err(Exception::InstructionAddressMisaligned);
+        }
+        let addr = match self.check_translation(self.pc, AccessType::Fetch)? {
+            Some(leaf) => {
+                self.mark_accessed(&leaf, AccessType::Fetch);
+                leaf.paddr
+            }
+            None => self.pc,
+        };
+        if let Some(decoded) = self.decode_cache.get(addr) {
+            if !self.pmp_allows(addr, decoded.len, AccessType::Fetch) {
+                return Err(Exception::InstructionAccessFault);
+            }
+            return Ok((decoded.raw, Some(decoded)));
+        }
+        let inst = self.fetch()?;
+        let decoded = self.decode(inst).ok();
+        if let Some(decoded) = decoded {
+            // Only RAM is sure to read back the same, and the second half of
+            // an instruction straddling two pages could be remapped
+            let one_page = addr % mmu::PAGE_SIZE + decoded.len <= mmu::PAGE_SIZE;
+            if one_page && self.mem.is_ram(addr, decoded.len) {
+                self.decode_cache.insert(addr, decoded);
+            }
+        }
+        Ok((inst, decoded))
+    }
+
+    /// Fetches the instruction at `pc`: just its first 16 bits if it's a
+    /// compressed instruction and `Config::compressed` is on, all 32
+    /// otherwise. With `mark` unset, page table A bits aren't updated.
+    fn fetch_raw(&mut self, mark: bool) -> Result<u32, Exception> {
+        // Jumps check their targets, but xRET and the host can still leave
+        // the pc misaligned
+        if self.pc & (self.inst_align() - 1) != 0 {
+            return Err(Exception::InstructionAddressMisaligned);
+        }
+        if !self.compressed() {
+            return self.fetch_parcel(self.pc, 4, mark);
+        }
+        // The two halves of a 32-bit instruction can be on different pages
+        let low = self.fetch_parcel(self.pc, 2, mark)?;
+        if self.is_compressed(low) {
+            return Ok(low);
+        }
+        let high = self.fetch_parcel(self.pc.wrapping_add(2), 2, mark)?;
+        Ok(high << 16 | low)
+    }
+
+    fn fetch_parcel(&mut self, vaddr: u32, size: u32, mark: bool) -> Result<u32, Exception> {
+        let addr = match self.check_translation(vaddr, AccessType::Fetch)? {
+            Some(leaf) => {
+                if mark {
+                    self.mark_accessed(&leaf, AccessType::Fetch);
+                }
+                leaf.paddr
+            }
+            None => vaddr,
+        };
+        if !self.pmp_allows(addr, size, AccessType::Fetch) {
+            return Err(Exception::InstructionAccessFault);
+        }
+        self.mem
+            .read(addr, size)
+            .map_err(|_| Exception::InstructionAccessFault)
+    }
+
+    /// Whether a fetched instruction is a 16-bit compressed one.
+    fn is_compressed(&self, inst: u32) -> bool {
+        self.compressed() && inst & 0b11 != 0b11
+    }
+
+    /// Whether compressed instructions are on, which they never are on a
+    /// 64-bit hart.
+    fn compressed(&self) -> bool {
+        self.config.compressed && !self.rv64()
+    }
+
+    fn rv64(&self) -> bool {
+        self.config.xlen == Xlen::Rv64
+    }
+
+    /// How many hex digits an XLEN-bit value takes.
+    fn xlen_digits(&self) -> usize {
+        if self.rv64() {
+            16
+        } else {
+            8
+        }
+    }
+
+    /// The value of register `x{i}`, or its low half on a 64-bit hart.
+    pub fn reg(&self, i: usize) -> u32 {
+        self.reg[i]
+    }
+
+    /// The full value of register `x{i}`.
+    pub fn reg64(&self, i: usize) -> u64 {
+        (self.reg_high[i] as u64) << 32 | self.reg[i] as u64
+    }
+
+    /// All 32 registers, indexed by number.
+    pub fn regs(&self) -> &[u32; 32] {
+        &self.reg
+    }
+
+    /// Sets register `x{i}` from the host. Like an instruction's write, a
+    /// write to x0 is dropped. Watches and histories only see writes made by
+    /// the guest.
+    ///
+    /// On a 64-bit hart `val` is sign-extended, as a 32-bit result would be.
+    pub fn set_reg(&mut self, i: usize, val: u32) {
+        self.set_reg64(i, val as i32 as u64);
+    }
+
+    /// Sets all of register `x{i}` like `set_reg`. A 32-bit hart only keeps
+    /// the low half.
+    pub fn set_reg64(&mut self, i: usize, val: u64) {
+        if i != 0 {
+            self.reg[i] = val as u32;
+            if self.rv64() {
+                self.reg_high[i] = (val >> 32) as u32;
+            }
+        }
+    }
+
+    /// The bits of floating-point register `f{i}`.
+    pub fn freg(&self, i: usize) -> u32 {
+        self.fregs[i]
+    }
+
+    /// Sets floating-point register `f{i}` from the host, without marking
+    /// the FP state dirty.
+    pub fn set_freg(&mut self, i: usize, val: u32) {
+        self.fregs[i] = val;
+    }
+
+    /// Writes back an FP instruction's result, which dirties the FP state.
+    fn set_frd(&mut self, rd: usize, val: u32) {
+        self.fregs[rd] = val;
+        self.csrs[csr::MSTATUS as usize] |= csr::MSTATUS_FS;
+        if self.trace_sink.is_some() {
+            self.commit.regs.push(('f', rd, val as u64));
+        }
+    }
+
+    /// The value of the register called `name`, such as `x5`, `t0` or `fp`.
+    pub fn read_reg(&self, name: &str) -> Option<u32> {
+        reg_index(name).map(|i| self.reg[i])
+    }
+
+    /// Sets the register called `name` like `set_reg`. Returns false if
+    /// there's no such register.
+    pub fn write_reg(&mut self, name: &str, val: u32) -> bool {
+        match reg_index(name) {
+            Some(i) => {
+                self.set_reg(i, val);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Reads the CSR called `name`, such as `mstatus`, as an instruction
+    /// would see it.
+    pub fn csr_by_name(&self, name: &str) -> Option<u32> {
+        csr::by_name(name).map(|addr| self.read_csr(addr))
+    }
+
+    /// Fetches and decodes the instruction at `pc` without executing it. A
+    /// fetch that would fault returns the exception instead of taking it, and
+    /// page table A bits are left alone.
+    ///
+    /// A compressed instruction is decoded as the instruction it expands to.
+    pub fn current_instruction(&mut self) -> Result<DecodedInst, Exception> {
+        let raw = self.fetch_raw(false)?;
+        let inst = if self.is_compressed(raw) {
+            rvc::expand(raw as u16).unwrap_or(raw)
+        } else {
+            raw
+        };
+        Ok(DecodedInst::new(inst, self.pc))
+    }
+
+    /// `(pc, inst)` for each of the last `Config::history_len` instructions
+    /// executed, oldest first.
+    pub fn recent_instructions(&self) -> impl Iterator<Item = (u32, u32)> + '_ {
+        self.recent.iter().copied()
+    }
+
+    /// The `n` most often entered basic blocks as `(start, count)`, hottest
+    /// first, as counted with `Config::profile_blocks`. A block runs from an
+    /// instruction reached by a jump, branch or trap, or just after a jump or
+    /// branch, up to the next one.
+    pub fn hot_blocks(&self, n: usize) -> Vec<(u32, u64)> {
+        let mut blocks: Vec<(u32, u64)> = self
+            .block_counts
+            .iter()
+            .map(|(&pc, &count)| (pc, count))
+            .collect();
+        blocks.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        blocks.truncate(n);
+        blocks
+    }
+
+    pub fn clear_block_counts(&mut self) {
+        self.block_counts.clear();
+    }
+
+    /// How many instructions `recent` should hold.
+    fn history_capacity(&self) -> usize {
+        if self.config.panic_dump {
+            self.config.history_len.max(RECENT_INSTS)
+        } else {
+            self.config.history_len
+        }
+    }
+
+    /// The hart's timer. It ticks once per cycle, so it keeps pace with
+    /// `cycle_count` unless the guest sets it through the CLINT.
+    pub fn mtime(&self) -> u64 {
+        self.timer.mtime()
+    }
+
+    /// Charges `cycles` to the instruction being executed.
+    fn tick(&mut self, cycles: usize) {
+        self.cycle_count += cycles;
+        self.timer.advance(cycles as u64);
+        self.bump_counter(csr::MCYCLE, csr::MCYCLEH, cycles as u64);
+    }
+
+    /// The 64-bit counter whose halves are the CSRs `lo` and `hi`.
+    fn counter(&self, lo: u32, hi: u32) -> u64 {
+        (self.csrs[hi as usize] as u64) << 32 | self.csrs[lo as usize] as u64
+    }
+
+    fn bump_counter(&mut self, lo: u32, hi: u32, by: u64) {
+        let val = self.counter(lo, hi).wrapping_add(by);
+        self.csrs[lo as usize] = val as u32;
+        self.csrs[hi as usize] = (val >> 32) as u32;
+    }
+
+    /// Moves `mtime` straight to `mtimecmp` for a `wfi` that would otherwise
+    /// wait for the timer: nothing enabled in `mie` is pending yet, but the
+    /// timer interrupt is enabled.
+    fn skip_to_timer(&mut self) {
+        let mtimer = if self.config.sbi {
+            irq::STIP
+        } else {
+            irq::MTIP
+        };
+        let mie = self.csrs[csr::MIE as usize];
+        let waiting = self.read_csr(csr::MIP) & mie == 0;
+        let cmp = self.timer.mtimecmp();
+        if waiting && mie & (1 << mtimer) != 0 && cmp != u64::MAX {
+            self.timer.set_mtime(cmp);
+        }
+    }
+
+    /// Whether the Sstc timer is enabled and `mtime` has reached `stimecmp`.
+    fn stimecmp_pending(&self) -> bool {
+        if self.csrs[csr::MENVCFGH as usize] & csr::MENVCFGH_STCE == 0 {
+            return false;
+        }
+        let cmp = (self.csrs[csr::STIMECMPH as usize] as u64) << 32
+            | self.csrs[csr::STIMECMP as usize] as u64;
+        self.mtime() >= cmp
+    }
+
+    /// The highest priority interrupt that is pending, enabled and allowed to
+    /// preempt the current privilege mode, if any.
+    fn pending_interrupt(&self) -> Option<u32> {
+        let pending = self.read_csr(csr::MIP) & self.csrs[csr::MIE as usize];
+        if pending == 0 {
+            return None;
+        }
+        let mideleg = self.csrs[csr::MIDELEG as usize];
+        let status = self.csrs[csr::MSTATUS as usize];
+        let mut takeable = 0;
+        if self.priv_mode < PrivMode::Machine || status & csr::MSTATUS_MIE != 0 {
+            takeable |= pending & !mideleg;
+        }
+        if self.priv_mode < PrivMode::Supervisor
+            || (self.priv_mode == PrivMode::Supervisor && status & csr::MSTATUS_SIE != 0)
+        {
+            takeable |= pending & mideleg;
+        }
+        [
+            irq::MEIP,
+            irq::MSIP,
+            irq::MTIP,
+            irq::SEIP,
+            irq::SSIP,
+            irq::STIP,
+        ]
+        .iter()
+        .copied()
+        .find(|&source| takeable & (1 << source) != 0)
+    }
+
+    /// Takes a synchronous exception for the instruction at `pc`.
+    fn trap(&mut self, cause: Exception, tval: u32) -> StepOutcome {
+        self.enter_trap(cause as u32, tval);
+        StepOutcome::Trap(cause)
+    }
+
+    /// Raises an illegal-instruction exception for `inst`. `mtval` (or
+    /// `stval`) is given the instruction's encoding, so the handler can
+    /// decode and emulate it without reading it back from memory.
+    fn illegal(&mut self, inst: u32) -> StepOutcome {
+        self.trap(Exception::IllegalInstruction, inst)
+    }
+
+    /// Enters the trap handler for `cause`, an exception code or an interrupt
+    /// code with `CAUSE_INTERRUPT` set. The trap goes to S mode if it's
+    /// delegated and we aren't in M mode, and to M mode otherwise.
+    ///
+    /// `xepc` is left pointing at the instruction that trapped (or, for an
+    /// interrupt, the one that would have run next), not past it, so a
+    /// handler that wants to resume after an `ecall` has to advance it by 4
+    /// itself before returning.
+    fn enter_trap(&mut self, cause: u32, tval: u32) {
+        // A reservation must not survive into or out of a handler
+        self.reservation = None;
+        let interrupt = cause & csr::CAUSE_INTERRUPT != 0;
+        let code = cause & !csr::CAUSE_INTERRUPT;
+        let deleg = if interrupt {
+            self.csrs[csr::MIDELEG as usize]
+        } else {
+            self.csrs[csr::MEDELEG as usize]
+        };
+        let mut status = self.csrs[csr::MSTATUS as usize];
+        let tvec = if self.priv_mode != PrivMode::Machine && deleg & (1 << code) != 0 {
+            let sie = status & csr::MSTATUS_SIE != 0;
+            status &= !(csr::MSTATUS_SPP | csr::MSTATUS_SPIE | csr::MSTATUS_SIE);
+            if self.priv_mode == PrivMode::Supervisor {
+                status |= csr::MSTATUS_SPP;
+            }
+            if sie {
+                status |= csr::MSTATUS_SPIE;
+            }
+            self.priv_mode = PrivMode::Supervisor;
+            self.csrs[csr::SEPC as usize] = self.pc;
+            self.csrs[csr::SCAUSE as usize] = cause;
+            self.csrs[csr::STVAL as usize] = tval;
+            self.csrs[csr::STVEC as usize]
+        } else {
+            let mie = status & csr::MSTATUS_MIE != 0;
+            status &= !(csr::MSTATUS_MPP | csr::MSTATUS_MPIE | csr::MSTATUS_MIE);
+            status |= (self.priv_mode as u32) << csr::MSTATUS_MPP_SHIFT;
+            if mie {
+                status |= csr::MSTATUS_MPIE;
+            }
+            self.priv_mode = PrivMode::Machine;
+            self.csrs[csr::MEPC as usize] = self.pc;
+            self.csrs[csr::MCAUSE as usize] = cause;
+            self.csrs[csr::MTVAL as usize] = tval;
+            self.csrs[csr::MTVEC as usize]
+        };
+        self.csrs[csr::MSTATUS as usize] = status;
+        // Vectored mode sends interrupts to base + 4 * cause
+        self.pc = if interrupt && tvec & 0b11 == 1 {
+            (tvec & !0b11).wrapping_add(4 * code)
+        } else {
+            tvec & !0b11
+        };
+    }
+
+    /// Applies `config.misaligned` to an access of `size` bytes at `addr`.
+    /// Strict mode doesn't allow silently performing the access. Returns the
+    /// trap taken, if any.
+    fn check_alignment(&mut self, addr: u32, size: u32, cause: Exception) -> Option<StepOutcome> {
+        if addr & (size - 1) == 0 {
+            return None;
+        }
+        match self.config.misaligned {
+            MisalignedAccess::Allow if !self.config.strict => None,
+            MisalignedAccess::Allow => Some(self.trap(cause, addr)),
+            MisalignedAccess::Trap => Some(self.trap(cause, addr)),
+            MisalignedAccess::Emulate => {
+                self.stats.misaligned_traps += 1;
+                self.tick(MISALIGNED_FIXUP_CYCLES);
+                None
+            }
+        }
+    }
+
+    /// Whether the current privilege mode may access the CSR at `addr`. Bits
+    /// 9:8 of the address give the lowest privilege level allowed to, and
+    /// bits 11:10 are 0b11 for read-only CSRs.
+    fn csr_accessible(&self, addr: u32, write: bool) -> bool {
+        let min_priv = (addr >> 8) & 0b11;
+        let read_only = (addr >> 10) & 0b11 == 0b11;
+        // The floating-point CSRs go away along with the registers when FS
+        // is off
+        let fp_off = matches!(addr, csr::FFLAGS | csr::FRM | csr::FCSR) && !self.fp_enabled();
+        if addr == csr::SATP && !self.vm_accessible() {
+            return false;
+        }
+        if self.config.strict && !self.counter_enabled(addr) {
+            return false;
+        }
+        self.priv_mode as u32 >= min_priv && !(write && read_only) && !fp_off
+    }
+
+    /// Whether mcounteren and scounteren let the current mode read the
+    /// counter at `addr`. True for anything that isn't a counter.
+    fn counter_enabled(&self, addr: u32) -> bool {
+        if !matches!(addr, csr::CYCLE..=csr::INSTRET | csr::CYCLEH..=csr::INSTRETH) {
+            return true;
+        }
+        let bit = 1 << (addr & 0x1f);
+        let enabled = |counteren: u32| self.csrs[counteren as usize] & bit != 0;
+        match self.priv_mode {
+            PrivMode::Machine => true,
+            PrivMode::Supervisor => enabled(csr::MCOUNTEREN),
+            PrivMode::User => enabled(csr::MCOUNTEREN) && enabled(csr::SCOUNTEREN),
+        }
+    }
+
+    /// Whether the current mode may manage address translation, through
+    /// satp and SFENCE.VMA. U mode never can, and mstatus.TVM keeps S mode
+    /// from it.
+    fn vm_accessible(&self) -> bool {
+        let tvm = self.csrs[csr::MSTATUS as usize] & csr::MSTATUS_TVM != 0;
+        match self.priv_mode {
+            PrivMode::User => false,
+            PrivMode::Supervisor => !tvm,
+            PrivMode::Machine => true,
+        }
+    }
+
+    /// Whether mstatus.FS allows floating-point instructions.
+    fn fp_enabled(&self) -> bool {
+        self.csrs[csr::MSTATUS as usize] & csr::MSTATUS_FS != 0
+    }
+
+    /// Checks that a jump or branch target is instruction-aligned. That's 4
+    /// bytes, or 2 with `Config::compressed`, in which case only an odd target
+    /// can fault. Returns the trap taken, if any, before the jump has any
+    /// other effect.
+    fn check_target(&mut self, target: u32) -> Option<StepOutcome> {
+        if target & (self.inst_align() - 1) == 0 {
+            return None;
+        }
+        Some(self.trap(Exception::InstructionAddressMisaligned, target))
+    }
+
+    /// The alignment instructions need, in bytes.
+    fn inst_align(&self) -> u32 {
+        if self.compressed() {
+            2
+        } else {
+            4
+        }
+    }
+
+    /// Writes back an instruction's result, recording it for any history or
+    /// watch on `rd`. Writes to x0 are discarded without touching either.
+    /// A 64-bit hart sign-extends it.
+    fn set_rd(&mut self, rd: usize, val: u32) {
+        self.set_rd64(rd, val as i32 as u64);
+    }
+
+    /// Writes back a full-width result like `set_rd`. Histories and watches
+    /// see its low half.
+    fn set_rd64(&mut self, rd: usize, val64: u64) {
+        if rd == 0 {
+            return;
+        }
+        self.set_reg64(rd, val64);
+        let val = val64 as u32;
+        if self.trace_sink.is_some() {
+            let val = self.reg64(rd);
+            self.commit.regs.push(('x', rd, val));
+        }
+        for history in &mut self.reg_histories {
+            if history.reg == rd {
+                if history.entries.len() == history.capacity {
+                    history.entries.remove(0);
+                }
+                history.entries.push((self.cycle_count as u64, val));
+            }
+        }
+        if !self.reg_watches.is_empty() {
+            let mut watches = std::mem::take(&mut self.reg_watches);
+            for (reg, watch) in &mut watches {
+                if *reg == rd {
+                    watch(self, val);
+                }
+            }
+            self.reg_watches = watches;
+        }
+    }
+
+    /// Writes back the old value of a CSR for a CSR instruction. CSRs are 32
+    /// bits wide even on a 64-bit hart, where they're zero-extended, except
+    /// that `misa` reports MXL in its top bits.
+    fn set_csr_rd(&mut self, rd: usize, addr: u32, val: u32) {
+        if !self.rv64() {
+            return self.set_rd(rd, val);
+        }
+        let mut val = val as u64;
+        if addr == csr::MISA {
+            val |= csr::MISA_MXL_64;
+        }
+        self.set_rd64(rd, val);
+    }
+
+    /// Puts a data value in memory byte order.
+    fn data_order(&self, val: u32, size: u32) -> u32 {
+        match size {
+            2 if self.config.big_endian => (val as u16).swap_bytes() as u32,
+            4 if self.config.big_endian => val.swap_bytes(),
+            _ => val,
+        }
+    }
+
+    /// Performs an explicit data load. `split` is where the access continues
+    /// once it crosses into the next page, as given by `translate_data`.
+    fn load(&mut self, addr: u32, size: u32, split: Option<u32>) -> Result<u32, AccessFault> {
+        if self.config.warn_uninit_mmio && self.mem.is_uninitialized_device(addr) {
+            let _ = writeln!(
+                self.warning_sink,
+                "warning: pc {:08x}: read from device register {:08x} before the device was set up",
+                self.pc, addr
+            );
+            self.stats.uninit_mmio_reads += 1;
+        }
+        let val = match split {
+            None => self.mem.read(addr, size)?,
+            Some(next) => {
+                let mut bytes = [0; 4];
+                for (i, byte) in bytes[..size as usize].iter_mut().enumerate() {
+                    *byte = self.mem.read(split_byte(addr, next, i as u32), 1)? as u8;
+                }
+                u32::from_le_bytes(bytes)
+            }
+        };
+        Ok(self.data_order(val, size))
+    }
+
+    /// Whether `config.lenient_unmapped` lets a failed access of `size` bytes
+    /// at `addr` through, because there's nothing at all mapped there.
+    /// `split` is as for `load`.
+    fn ignores_unmapped(&self, addr: u32, size: u32, split: Option<u32>) -> bool {
+        if !self.config.lenient_unmapped || self.config.strict {
+            return false;
+        }
+        match split {
+            None => self.mem.is_unmapped(addr, size),
+            Some(next) => (0..size).all(|i| self.mem.is_unmapped(split_byte(addr, next, i), 1)),
+        }
+    }
+
+    /// Commits a store to memory and tells the memory hook about it. `split`
+    /// is as for `load`.
+    fn store(
+        &mut self,
+        addr: u32,
+        size: u32,
+        val: u32,
+        split: Option<u32>,
+    ) -> Result<(), AccessFault> {
+        let ordered = self.data_order(val, size);
+        match split {
+            None => self.mem.write(addr, size, ordered)?,
+            Some(next) => {
+                for (i, &byte) in ordered.to_le_bytes()[..size as usize].iter().enumerate() {
+                    self.mem
+                        .write(split_byte(addr, next, i as u32), 1, byte as u32)?;
+                }
+            }
+        }
+        self.invalidate_code(addr, size);
+        if let Some(next) = split {
+            self.invalidate_code(next, size);
+        }
+        self.last_store = Some((addr, size));
+        if !self.watchpoints.is_empty() && self.watch_hit.is_none() {
+            self.watch_hit = (0..size)
+                .map(|i| match split {
+                    Some(next) => split_byte(addr, next, i),
+                    None => addr.wrapping_add(i),
+                })
+                .find(|byte| self.watchpoints.contains(byte));
+        }
+        if let Some(tohost) = self.config.tohost {
+            // The guest writes the low word first, so a command is complete
+            // once the high word lands
+            if addr.wrapping_sub(tohost.wrapping_add(4)) < 4 {
+                self.service_htif(tohost);
+            }
+        }
+        if let Some(mut hook) = self.mem_hook.take() {
+            hook(self, addr, size, val);
+            self.mem_hook = Some(hook);
+        }
+        Ok(())
+    }
+
+    /// Carries out the command in `tohost` and clears it, which tells the
+    /// guest it was taken.
+    fn service_htif(&mut self, tohost: u32) {
+        let word = |mem: &mut Mem, addr: u32| mem.read(addr, 4).unwrap_or(0) as u64;
+        let val = word(&mut self.mem, tohost.wrapping_add(4)) << 32 | word(&mut self.mem, tohost);
+        match htif::Command::decode(val) {
+            Some(htif::Command::Exit(code)) => self.htif_exit = Some(code),
+            Some(htif::Command::Putchar(byte)) => self.putchar(byte),
+            None => return,
+        }
+        let _ = self.mem.write(tohost, 4, 0);
+        let _ = self.mem.write(tohost.wrapping_add(4), 4, 0);
+    }
+
+    fn putchar(&mut self, byte: u8) {
+        // Nothing the guest can do about a host that can't print
+        let _ = self.console.write_all(&[byte]);
+        let _ = self.console.flush();
+    }
+
+    /// Services the SBI call the `ecall` being executed makes, for
+    /// `Config::sbi`. The `ecall` completes like any other instruction.
+    fn sbi_call(&mut self) -> StepOutcome {
+        let (ext, func) = (self.reg[17], self.reg[16]);
+        let (a0, a1) = (self.reg[10], self.reg[11]);
+        let mut outcome = StepOutcome::Continue;
+        let (error, value) = match (ext, func) {
+            (sbi::LEGACY_SET_TIMER, _) => {
+                self.timer.set_mtimecmp((a1 as u64) << 32 | a0 as u64);
+                (sbi::SUCCESS, None)
+            }
+            (sbi::LEGACY_CONSOLE_PUTCHAR, _) => {
+                self.putchar(a0 as u8);
+                (sbi::SUCCESS, None)
+            }
+            // There's no console input
+            (sbi::LEGACY_CONSOLE_GETCHAR, _) => (u32::MAX, None),
+            (sbi::LEGACY_SHUTDOWN, _) => {
+                outcome = StepOutcome::Shutdown;
+                (sbi::SUCCESS, None)
+            }
+            (sbi::EXT_BASE, sbi::BASE_GET_SPEC_VERSION) => (sbi::SUCCESS, Some(sbi::SPEC_VERSION)),
+            (sbi::EXT_BASE, sbi::BASE_GET_IMPL_ID) => (sbi::SUCCESS, Some(sbi::IMPL_ID)),
+            (sbi::EXT_BASE, sbi::BASE_PROBE_EXTENSION) => {
+                (sbi::SUCCESS, Some(sbi::is_supported(a0) as u32))
+            }
+            (
+                sbi::EXT_BASE,
+                sbi::BASE_GET_IMPL_VERSION
+                | sbi::BASE_GET_MVENDORID
+                | sbi::BASE_GET_MARCHID
+                | sbi::BASE_GET_MIMPID,
+            ) => (sbi::SUCCESS, Some(0)),
+            (sbi::EXT_TIME, sbi::TIME_SET_TIMER) => {
+                self.timer.set_mtimecmp((a1 as u64) << 32 | a0 as u64);
+                (sbi::SUCCESS, Some(0))
+            }
+            // Rebooting isn't supported, only shutting down
+            (sbi::EXT_SRST, sbi::SRST_SYSTEM_RESET) if a0 == sbi::SRST_TYPE_SHUTDOWN => {
+                outcome = StepOutcome::Shutdown;
+                (sbi::SUCCESS, Some(0))
+            }
+            _ => (sbi::ERR_NOT_SUPPORTED, Some(0)),
+        };
+        // Legacy calls only return a0
+        self.set_rd(10, error);
+        if let Some(value) = value {
+            self.set_rd(11, value);
+        }
+        self.pc = self.pc.wrapping_add(self.inst_len);
+        outcome
+    }
+
+    /// Whether `menvcfg`, and in U mode `senvcfg`, enable all of `bits` for
+    /// the current privilege mode. M mode is never restricted.
+    fn envcfg_allows(&self, bits: u32) -> bool {
+        let enabled = |addr: u32| self.csrs[addr as usize] & bits == bits;
+        match self.priv_mode {
+            PrivMode::Machine => true,
+            PrivMode::Supervisor => enabled(csr::MENVCFG),
+            PrivMode::User => enabled(csr::MENVCFG) && enabled(csr::SENVCFG),
+        }
+    }
+
+    /// Zeroes the cache block containing `vaddr`, for `cbo.zero`. Returns the
+    /// trap taken, if any. The block is never split across pages, so it's
+    /// translated once.
+    fn cbo_zero(&mut self, vaddr: u32) -> Option<StepOutcome> {
+        let base = match self.translate(vaddr & !(CACHE_BLOCK_SIZE - 1), AccessType::Store) {
+            Ok(base) => base,
+            Err(cause) => return Some(self.trap(cause, vaddr)),
+        };
+        if !self.pmp_allows(base, CACHE_BLOCK_SIZE, AccessType::Store) {
+            return Some(self.trap(Exception::StoreAccessFault, vaddr));
+        }
+        self.note_store(base, CACHE_BLOCK_SIZE);
+        for offset in (0..CACHE_BLOCK_SIZE).step_by(4) {
+            if self.store(base + offset, 4, 0, None).is_err() {
+                return Some(self.trap(Exception::StoreAccessFault, vaddr));
+            }
+        }
+        self.last_store = Some((base, CACHE_BLOCK_SIZE));
+        None
+    }
+
+    /// Sees a store made by another hart.
+    pub(crate) fn snoop_store(&mut self, addr: u32, size: u32) {
+        self.note_store(addr, size);
+        self.invalidate_code(addr, size);
+    }
+
+    /// Drops the LR reservation if a store of `size` bytes at `addr` touches
+    /// the reserved word. Apart from traps and trap returns, only a real
+    /// conflicting write breaks a reservation, so an uncontended LR/SC
+    /// sequence always succeeds.
+    fn note_store(&mut self, addr: u32, size: u32) {
+        if let Some(reserved) = self.reservation {
+            if addr < reserved.wrapping_add(4) && reserved < addr.wrapping_add(size) {
+                self.reservation = None;
+            }
+        }
+    }
+
+    /// Every difference in pc, privilege mode, registers and CSRs between
+    /// this core and `other`, in that order.
+    pub fn reg_diff(&self, other: &Core) -> Vec<Diff> {
+        let mut diffs = Vec::new();
+        let mut compare = |field, ours, theirs| {
+            if ours != theirs {
+                diffs.push(Diff {
+                    field,
+                    ours,
+                    theirs,
+                });
+            }
+        };
+        compare(StateField::Pc, self.pc, other.pc);
+        compare(
+            StateField::Priv,
+            self.priv_mode as u32,
+            other.priv_mode as u32,
+        );
+        for i in 0..32 {
+            compare(StateField::Reg(i), self.reg[i], other.reg[i]);
+        }
+        for addr in 0..self.csrs.len() {
+            compare(
+                StateField::Csr(addr as u32),
+                self.csrs[addr],
+                other.csrs[addr],
+            );
+        }
+        diffs
+    }
+
+    /// Writes out the hart's pc, registers, the CSRs that say how it got
+    /// there and any recorded recent instructions, oldest first.
+    pub fn dump_state(&self, out: &mut impl io::Write) -> io::Result<()> {
+        writeln!(
+            out,
+            "pc: {:08x}, priv: {:?}, cycle: {}",
+            self.pc, self.priv_mode, self.cycle_count
+        )?;
+        let width = self.xlen_digits();
+        for (i, name) in REG_NAMES.iter().enumerate() {
+            let val = self.reg64(i);
+            writeln!(
+                out,
+                " x{:<2} {:>4}: {:0width$x}",
+                i,
+                name,
+                val,
+                width = width
+            )?;
+        }
+        for line in DUMP_CSRS.chunks(4) {
+            for &addr in line {
+                let name = csr::name(addr).unwrap();
+                write!(out, " {:>8}: {:08x}", name, self.read_csr(addr))?;
+            }
+            writeln!(out)?;
+        }
+        if !self.recent.is_empty() {
+            writeln!(out, "Recent instructions:")?;
+            for &(pc, inst) in &self.recent {
+                writeln!(out, " {:08x}: {:08x}  {}", pc, inst, disassemble(inst, pc))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes out the architectural state a run ends in, as JSON: pc,
+    /// privilege mode, cycle count and registers.
+    pub fn dump_json(&self, out: &mut impl io::Write) -> io::Result<()> {
+        let width = self.xlen_digits() + 2;
+        let regs: Vec<String> = (0..32)
+            .map(|i| format!("\"{:#0width$x}\"", self.reg64(i), width = width))
+            .collect();
+        writeln!(out, "{{")?;
+        writeln!(out, "  \"pc\": \"{:#010x}\",", self.pc)?;
+        writeln!(out, "  \"priv\": \"{:?}\",", self.priv_mode)?;
+        writeln!(out, "  \"cycle_count\": {},", self.cycle_count)?;
+        writeln!(out, "  \"regs\": [{}]", regs.join(", "))?;
+        writeln!(out, "}}")
+    }
+
+    /// Runs a whole compiled block of straight-line instructions followed by
+    /// the instruction that ends it, which is interpreted as usual. Interrupts
+    /// are only taken between blocks.
+    ///
+    /// Falls back to a single `step` whenever something needs to see each
+    /// instruction individually: hooks, breakpoints, watchpoints, register
+    /// watches or history, instruction history (including the panic dump's),
+    /// block profiling, compressed instructions, a 64-bit hart, or address
+    /// translation being on. Host writes straight to `mem` aren't noticed, so
+    /// call `flush_jit` after patching guest code.
+    #[cfg(feature = "jit")]
+    pub fn step_block(&mut self) -> StepOutcome {
+        let satp = self.csrs[csr::SATP as usize];
+        let translating = self.priv_mode != PrivMode::Machine && satp & SATP_MODE_SV32 != 0;
+        if translating
+            || self.pre_hook.is_some()
+            || self.post_hook.is_some()
+            || self.mem_hook.is_some()
+            || !self.reg_watches.is_empty()
+            || !self.reg_histories.is_empty()
+            || !self.breakpoints.is_empty()
+            || !self.watchpoints.is_empty()
+            || self.history_capacity() > 0
+            || self.trace
+            || self.trace_sink.is_some()
+            || self.config.profile_blocks
+            || self.config.pmp
+            || self.config.compressed
+            || self.rv64()
+            || self.pc & 0b11 != 0
+            || self.pending_interrupt().is_some()
+        {
+            return self.step();
+        }
+        let block = self.jit.get(&mut self.mem, self.pc);
+        block.run(&mut self.reg);
+        self.pc = self.pc.wrapping_add(4 * block.len() as u32);
+        self.tick(block.len());
+        self.bump_counter(csr::MINSTRET, csr::MINSTRETH, block.len() as u64);
+        self.step()
+    }
+
+    /// Whether `inst` is a CSR instruction `trap_on_csr` asked to stop at.
+    fn traps_on_csr(&self, inst: u32) -> bool {
+        let system = inst & 0b1111111 == 0b1110011 && (inst >> 12) & 0b111 != 0;
+        system && self.csr_traps.contains(&(inst >> 20))
+    }
+
+    /// Throws away all compiled blocks.
+    #[cfg(feature = "jit")]
+    pub fn flush_jit(&mut self) {
+        self.jit.clear();
+    }
+
+    /// Throws away every instruction `Config::decode_cache` has kept.
+    pub fn flush_decode_cache(&mut self) {
+        self.decode_cache.clear();
+    }
+
+    /// Drops cached decodes and compiled code that a store of `size` bytes
+    /// at `addr` may have overwritten.
+    fn invalidate_code(&mut self, addr: u32, size: u32) {
+        self.decode_cache.invalidate(addr, size);
+        #[cfg(feature = "jit")]
+        self.jit.invalidate(addr, size);
+    }
+
+    /// Drops all cached decodes and compiled code.
+    pub(crate) fn flush_code(&mut self) {
+        self.decode_cache.clear();
+        #[cfg(feature = "jit")]
+        self.jit.clear();
+    }
+
+    /// Executes one instruction.
+    ///
+    /// The instruction's effects are applied in a fixed order, and hooks fire
+    /// as each one lands: memory and CSR writes commit first, then `rd` is
+    /// written, then `pc` moves on, and finally `cycle_count` is incremented.
+    /// A hook therefore sees the old `pc` and cycle count, and a register
+    /// watch sees any memory the instruction wrote.
+    pub fn step(&mut self) -> StepOutcome {
+        self.last_store = None;
+        self.last_inst = None;
+        let resuming = self.csr_break.take() == Some(self.pc);
+        if let Some(code) = self.pending_interrupt() {
+            self.enter_trap(csr::CAUSE_INTERRUPT | code, 0);
+            self.block_start = true;
+            return self.check_breakpoint(StepOutcome::Interrupt(code));
+        }
+        let (inst, cached) = match self.fetch_decoded() {
+            Ok(fetched) => fetched,
+            Err(cause) => {
+                let outcome = self.trap(cause, self.pc);
+                self.tick(1);
+                self.block_start = true;
+                return self.check_breakpoint(outcome);
+            }
+        };
+        self.last_inst = Some(inst);
+        if !resuming && self.traps_on_csr(inst) {
+            self.csr_break = Some(self.pc);
+            return StepOutcome::Break(self.pc);
+        }
+
+        if let Some(mut hook) = self.pre_hook.take() {
+            hook(self, inst);
+            self.pre_hook = Some(hook);
+        }
+        let pc = self.pc;
+        if self.config.profile_blocks && self.block_start {
+            *self.block_counts.entry(pc).or_insert(0) += 1;
+        }
+        let capacity = self.history_capacity();
+        if capacity > 0 {
+            while self.recent.len() >= capacity {
+                self.recent.pop_front();
+            }
+            self.recent.push_back((self.pc, inst));
+        }
+        self.watch_hit = None;
+        self.htif_exit = None;
+        let priv_mode = self.priv_mode;
+        let outcome = if self.config.panic_dump {
+            match panic::catch_unwind(AssertUnwindSafe(|| self.decode_and_execute(inst, cached))) {
+                Ok(outcome) => outcome,
+                Err(payload) => {
+                    let mut sink = std::mem::replace(&mut self.panic_sink, Box::new(io::sink()));
+                    let _ = self.dump_state(&mut sink);
+                    let _ = sink.flush();
+                    self.panic_sink = sink;
+                    panic::resume_unwind(payload);
+                }
+            }
+        } else {
+            self.decode_and_execute(inst, cached)
+        };
+        // A write to a counter leaves the value written for the next
+        // instruction to see, without this one's count on top
+        let written = self.counter_written.take();
+        if matches!(written, Some(csr::MCYCLE | csr::MCYCLEH)) {
+            self.cycle_count += 1;
+            self.timer.advance(1);
+        } else {
+            self.tick(1);
+        }
+        // An instruction that raised an exception didn't retire
+        if !matches!(outcome, StepOutcome::Trap(_)) {
+            if !matches!(written, Some(csr::MINSTRET | csr::MINSTRETH)) {
+                self.bump_counter(csr::MINSTRET, csr::MINSTRETH, 1);
+            }
+            if self.trace_sink.is_some() {
+                self.log_commit(priv_mode, pc, inst);
+            }
+        }
+        self.commit.clear();
+        if self.config.profile_blocks {
+            // Branches end a block whether or not they're taken
+            let opcode = if self.is_compressed(inst) {
+                rvc::expand(inst as u16).unwrap_or(inst)
+            } else {
+                inst
+            } & 0b1111111;
+            let control_flow = matches!(opcode, 0b1100011 | 0b1101111 | 0b1100111);
+            self.block_start = control_flow || self.pc != pc.wrapping_add(self.inst_len);
+        }
+        // writes to x0 are discarded
+        self.reg[0] = 0;
+        if let Some(mut hook) = self.post_hook.take() {
+            hook(self, inst);
+            self.post_hook = Some(hook);
+        }
+        let outcome = match (self.htif_exit.take(), self.watch_hit.take()) {
+            (Some(code), _) => StepOutcome::HtifExit(code),
+            (None, Some(addr)) if !outcome.stops() => StepOutcome::Watchpoint(addr),
+            _ => outcome,
+        };
+        self.check_breakpoint(outcome)
+    }
+
+    /// Turns `outcome` into a breakpoint stop if the pc has landed on one.
+    fn check_breakpoint(&self, outcome: StepOutcome) -> StepOutcome {
+        if !outcome.stops() && self.breakpoints.contains(&self.pc) {
+            StepOutcome::Breakpoint(self.pc)
+        } else {
+            outcome
+        }
+    }
+
+    /// Decodes `inst`, expanding it first if it's compressed. `Err` is the
+    /// word to report as illegal.
+    fn decode(&self, inst: u32) -> Result<Decoded, u32> {
+        if !self.is_compressed(inst) {
+            return decode::decode(inst, inst, 4).ok_or(inst);
+        }
+        let expanded = rvc::expand(inst as u16).ok_or(inst)?;
+        decode::decode(inst, expanded, 2).ok_or(expanded)
+    }
+
+    /// Executes `inst`, going by `cached` instead if the decode cache
+    /// already had it.
+    fn decode_and_execute(&mut self, inst: u32, cached: Option<Decoded>) -> StepOutcome {
+        let decoded = match cached {
+            Some(decoded) => Ok(decoded),
+            None => self.decode(inst),
+        };
+        self.inst_len = if self.is_compressed(inst) { 2 } else { 4 };
+        match decoded {
+            Ok(decoded) => self.execute(decoded),
+            Err(word) => self.illegal(word),
+        }
+    }
+
+    /// Loads `size` bytes from `vaddr` for a load instruction, through
+    /// translation and the alignment and unmapped access policies. `Err` is
+    /// the trap taken instead.
+    fn load_data(&mut self, vaddr: u32, size: u32) -> Result<u32, StepOutcome> {
+        if let Some(trap) = self.check_alignment(vaddr, size, Exception::LoadAddressMisaligned) {
+            return Err(trap);
+        }
+        let val = self.load_aligned(vaddr, size)?;
+        if self.trace_sink.is_some() {
+            self.commit.loads.push(vaddr);
+        }
+        Ok(val)
+    }
+
+    /// Loads a doubleword for LD as two words, the first of which holds the
+    /// high half in big-endian mode.
+    fn load_double(&mut self, vaddr: u32) -> Result<u64, StepOutcome> {
+        if let Some(trap) = self.check_alignment(vaddr, 8, Exception::LoadAddressMisaligned) {
+            return Err(trap);
+        }
+        let first = self.load_aligned(vaddr, 4)? as u64;
+        let second = self.load_aligned(vaddr.wrapping_add(4), 4)? as u64;
+        if self.trace_sink.is_some() {
+            self.commit.loads.push(vaddr);
+        }
+        Ok(if self.config.big_endian {
+            first << 32 | second
+        } else {
+            second << 32 | first
+        })
+    }
+
+    /// The part of `load_data` after the alignment check.
+    fn load_aligned(&mut self, vaddr: u32, size: u32) -> Result<u32, StepOutcome> {
+        let (addr, split) = match self.translate_data(vaddr, size, AccessType::Load) {
+            Ok(addr) => addr,
+            Err((cause, tval)) => return Err(self.trap(cause, tval)),
+        };
+        let val = match self.load(addr, size, split) {
+            Ok(val) => val,
+            Err(_) if self.ignores_unmapped(addr, size, split) => 0,
+            Err(_) => return Err(self.trap(Exception::LoadAccessFault, vaddr)),
+        };
+        Ok(val)
+    }
+
+    /// Stores the low `size` bytes of `val` to `vaddr` for a store
+    /// instruction, like `load_data`.
+    fn store_data(&mut self, vaddr: u32, size: u32, val: u32) -> Result<(), StepOutcome> {
+        if let Some(trap) = self.check_alignment(vaddr, size, Exception::StoreAddressMisaligned) {
+            return Err(trap);
+        }
+        self.store_aligned(vaddr, size, val)?;
+        if self.trace_sink.is_some() {
+            self.commit.stores.push((vaddr, size, val as u64));
+        }
+        Ok(())
+    }
+
+    /// Stores a doubleword for SD, like `load_double`.
+    fn store_double(&mut self, vaddr: u32, val: u64) -> Result<(), StepOutcome> {
+        if let Some(trap) = self.check_alignment(vaddr, 8, Exception::StoreAddressMisaligned) {
+            return Err(trap);
+        }
+        let (low, high) = (val as u32, (val >> 32) as u32);
+        let (first, second) = if self.config.big_endian {
+            (high, low)
+        } else {
+            (low, high)
+        };
+        self.store_aligned(vaddr, 4, first)?;
+        self.store_aligned(vaddr.wrapping_add(4), 4, second)?;
+        if self.trace_sink.is_some() {
+            self.commit.stores.push((vaddr, 8, val));
+        }
+        Ok(())
+    }
+
+    /// The part of `store_data` after the alignment check.
+    fn store_aligned(&mut self, vaddr: u32, size: u32, val: u32) -> Result<(), StepOutcome> {
+        let (addr, split) = match self.translate_data(vaddr, size, AccessType::Store) {
+            Ok(addr) => addr,
+            Err((cause, tval)) => return Err(self.trap(cause, tval)),
+        };
+        self.note_store(addr, size);
+        if let Some(next) = split {
+            self.note_store(next, size);
+        }
+        if self.store(addr, size, val, split).is_err() && !self.ignores_unmapped(addr, size, split)
+        {
+            return Err(self.trap(Exception::StoreAccessFault, vaddr));
+        }
+        Ok(())
+    }
+
+    /// Writes the commit log line for `inst`, which ran at `pc` in
+    /// `priv_mode`.
+    fn log_commit(&mut self, priv_mode: PrivMode, pc: u32, inst: u32) {
+        // Addresses and x registers are printed XLEN bits wide
+        let xlen_digits = self.xlen_digits();
+        let mut line = format!(
+            "core{:4}: {} 0x{:0width$x} (",
+            self.csrs[csr::MHARTID as usize],
+            priv_mode as u32,
+            pc,
+            width = xlen_digits
+        );
+        if self.inst_len == 2 {
+            line += &format!("0x{:04x})", inst);
+        } else {
+            line += &format!("0x{:08x})", inst);
+        }
+        for &(prefix, reg, val) in &self.commit.regs {
+            let width = if prefix == 'x' { xlen_digits } else { 8 };
+            line += &format!(" {}{:<2} 0x{:0width$x}", prefix, reg, val, width = width);
+        }
+        for &addr in &self.commit.loads {
+            line += &format!(" mem 0x{:0width$x}", addr, width = xlen_digits);
+        }
+        for &(addr, size, val) in &self.commit.stores {
+            line += &format!(
+                " mem 0x{:0addr_width$x} 0x{:0width$x}",
+                addr,
+                val,
+                addr_width = xlen_digits,
+                width = 2 * size as usize
+            );
+        }
+        line.push('\n');
+        if let Some(sink) = &mut self.trace_sink {
+            let _ = sink.write_all(line.as_bytes());
+        }
+    }
+
+    fn trace_inst(&self, inst: u32, opcode: Opcode) {
+        match self.symbols.describe(self.pc) {
+            Some(sym) => println!(
+                "Inst: {:032b}, pc: {:08x} <{}>, Opcode: {:?}, {}",
+                inst,
+                self.pc,
+                sym,
+                opcode,
+                disassemble(inst, self.pc)
+            ),
+            None => println!(
+                "Inst: {:032b}, pc: {:08x}, Opcode: {:?}, {}",
+                inst,
+                self.pc,
+                opcode,
+                disassemble(inst, self.pc)
+            ),
+        }
+    }
+
+    /// Executes an F extension instruction, all of which are illegal while
+    /// mstatus.FS is off. Any exceptions the operation raises accrue in
+    /// fflags.
+    fn execute_fp(&mut self, decoded: Decoded) -> StepOutcome {
+        let Decoded {
+            inst,
+            opcode,
+            rs1: rs1_raw,
+            rs2: rs2_raw,
+            funct3,
+            imm,
+            ..
+        } = decoded;
+        if !self.fp_enabled() {
+            return self.illegal(inst);
+        }
+        let rd = decoded.rd as usize;
+        let funct5 = inst >> 27;
+        let rs1 = self.reg[rs1_raw as usize];
+
+        match opcode {
+            Opcode::LoadFp | Opcode::StoreFp if funct3 != 0b010 => return self.illegal(inst),
+            Opcode::LoadFp => {
+                let vaddr = rs1.wrapping_add(imm);
+                match self.load_data(vaddr, 4) {
+                    Ok(val) => self.set_frd(rd, val),
+                    Err(trap) => return trap,
+                }
+                self.pc = self.pc.wrapping_add(self.inst_len);
+                return StepOutcome::Continue;
+            }
+            Opcode::StoreFp => {
+                let vaddr = rs1.wrapping_add(imm);
+                if let Err(trap) = self.store_data(vaddr, 4, self.fregs[rs2_raw as usize]) {
+                    return trap;
+                }
+                self.pc = self.pc.wrapping_add(self.inst_len);
+                return StepOutcome::Continue;
+            }
+            _ => {}
+        }
+
+        // Only single precision is implemented
+        if (inst >> 25) & 0b11 != 0 {
+            return self.illegal(inst);
+        }
+        // The arithmetic and conversions round as the rm field says, which
+        // can defer to frm. The other ops use the field as a funct3.
+        let rounds =
+            !matches!(opcode, Opcode::OpFp) || matches!(funct5, 0x00..=0x03 | 0x0b | 0x18 | 0x1a);
+        let rm = if funct3 == 0b111 {
+            self.read_csr(csr::FRM)
+        } else {
+            funct3
+        };
+        let rm = match Rounding::from_bits(rm) {
+            Some(rm) => rm,
+            None if rounds => return self.illegal(inst),
+            None => Rounding::NearestEven,
+        };
+
+        let a = self.fregs[rs1_raw as usize];
+        let b = self.fregs[rs2_raw as usize];
+        let c = self.fregs[(inst >> 27) as usize];
+        let mut flags = 0;
+        match opcode {
+            Opcode::Madd => self.set_frd(
+                rd,
+                fpu::fused_mul_add(a, b, c, false, false, rm, &mut flags),
+            ),
+            Opcode::Msub => {
+                self.set_frd(rd, fpu::fused_mul_add(a, b, c, false, true, rm, &mut flags))
+            }
+            Opcode::Nmsub => {
+                self.set_frd(rd, fpu::fused_mul_add(a, b, c, true, false, rm, &mut flags))
+            }
+            Opcode::Nmadd => {
+                self.set_frd(rd, fpu::fused_mul_add(a, b, c, true, true, rm, &mut flags))
+            }
+            _ => match (funct5, funct3, rs2_raw) {
+                (0x00, _, _) => self.set_frd(rd, fpu::add(a, b, rm, &mut flags)),
+                (0x01, _, _) => self.set_frd(rd, fpu::sub(a, b, rm, &mut flags)),
+                (0x02, _, _) => self.set_frd(rd, fpu::mul(a, b, rm, &mut flags)),
+                (0x03, _, _) => self.set_frd(rd, fpu::div(a, b, rm, &mut flags)),
+                (0x0b, _, 0) => self.set_frd(rd, fpu::sqrt(a, rm, &mut flags)),
+                // FSGNJ, FSGNJN and FSGNJX
+                (0x04, 0, _) => self.set_frd(rd, a & !FP_SIGN | b & FP_SIGN),
+                (0x04, 1, _) => self.set_frd(rd, a & !FP_SIGN | !b & FP_SIGN),
+                (0x04, 2, _) => self.set_frd(rd, a ^ (b & FP_SIGN)),
+                (0x05, 0 | 1, _) => self.set_frd(rd, fpu::min_max(a, b, funct3 == 1, &mut flags)),
+                (0x18, _, 0 | 1) => self.set_rd(rd, fpu::to_int(a, rs2_raw == 0, rm, &mut flags)),
+                (0x1a, _, 0 | 1) => {
+                    self.set_frd(rd, fpu::from_int(rs1, rs2_raw == 0, rm, &mut flags))
+                }
+                // FLE, FLT and FEQ
+                (0x14, 0..=2, _) => {
+                    let ord = fpu::compare(a, b, funct3 == 2, &mut flags);
+                    let holds = match funct3 {
+                        0 => matches!(ord, Some(Ordering::Less | Ordering::Equal)),
+                        1 => ord == Some(Ordering::Less),
+                        _ => ord == Some(Ordering::Equal),
+                    };
+                    self.set_rd(rd, holds as u32);
+                }
+                // FMV.X.W and FMV.W.X move the bits untouched
+                (0x1c, 0, 0) => self.set_rd(rd, a),
+                (0x1c, 1, 0) => self.set_rd(rd, fpu::classify(a)),
+                (0x1e, 0, 0) => self.set_frd(rd, rs1),
+                _ => return self.illegal(inst),
+            },
+        }
+        if flags != 0 {
+            let fcsr = self.csrs[csr::FCSR as usize];
+            self.write_csr(csr::FCSR, fcsr | flags);
+        }
+        self.pc = self.pc.wrapping_add(self.inst_len);
+        StepOutcome::Continue
+    }
+
+    /// Executes the instructions a 64-bit hart runs differently, with the
+    /// full registers. `None` leaves the instruction to `execute`, which
+    /// works on the low halves and sign-extends what it writes back.
+    fn execute_rv64(&mut self, decoded: Decoded) -> Option<StepOutcome> {
+        let Decoded {
+            inst,
+            opcode,
+            rs1,
+            rs2,
+            rd,
+            funct3,
+            funct7,
+            imm,
+            ..
+        } = decoded;
+
+        let rs1 = self.reg64(rs1 as usize);
+        let rs2 = self.reg64(rs2 as usize);
+        let rd = rd as usize;
+        let imm = imm as i32 as u64;
+        let next = self.pc.wrapping_add(self.inst_len);
+
+        match opcode {
+            Opcode::LoadFp
+            | Opcode::StoreFp
+            | Opcode::Madd
+            | Opcode::Msub
+            | Opcode::Nmsub
+            | Opcode::Nmadd
+            | Opcode::OpFp
+            | Opcode::Amo => return Some(self.illegal(inst)),
+            Opcode::Auipc => self.set_rd64(rd, (self.pc as u64).wrapping_add(imm)),
+            Opcode::Branch | Opcode::Jal | Opcode::Jalr => {
+                let target = match opcode {
+                    Opcode::Jalr => rs1.wrapping_add(imm) & !1,
+                    _ => (self.pc as u64).wrapping_add(imm),
+                };
+                let taken = match (opcode, funct3) {
+                    (Opcode::Branch, 0) => rs1 == rs2,
+                    (Opcode::Branch, 1) => rs1 != rs2,
+                    (Opcode::Branch, 4) => (rs1 as i64) < (rs2 as i64),
+                    (Opcode::Branch, 5) => (rs1 as i64) >= (rs2 as i64),
+                    (Opcode::Branch, 6) => rs1 < rs2,
+                    (Opcode::Branch, 7) => rs1 >= rs2,
+                    (Opcode::Branch, _) => return Some(self.illegal(inst)),
+                    _ => true,
+                };
+                if !taken {
+                    self.pc = next;
+                    return Some(StepOutcome::Continue);
+                }
+                // There's nothing to fetch above 4 GiB
+                if target > u32::MAX as u64 {
+                    return Some(self.trap(Exception::InstructionAccessFault, target as u32));
+                }
+                if let Some(trap) = self.check_target(target as u32) {
+                    return Some(trap);
+                }
+                if !matches!(opcode, Opcode::Branch) {
+                    self.set_rd64(rd, next as u64);
+                }
+                self.pc = target as u32;
+                return Some(StepOutcome::Continue);
+            }
+            Opcode::Load | Opcode::Store => {
+                let store = matches!(opcode, Opcode::Store);
+                let vaddr = rs1.wrapping_add(imm);
+                if vaddr > u32::MAX as u64 {
+                    let cause = if store {
+                        Exception::StoreAccessFault
+                    } else {
+                        Exception::LoadAccessFault
+                    };
+                    return Some(self.trap(cause, vaddr as u32));
+                }
+                let vaddr = vaddr as u32;
+                if store {
+                    let result = match funct3 {
+                        0 => self.store_data(vaddr, 1, rs2 as u32),
+                        1 => self.store_data(vaddr, 2, rs2 as u32),
+                        2 => self.store_data(vaddr, 4, rs2 as u32),
+                        3 => self.store_double(vaddr, rs2),
+                        _ => return Some(self.illegal(inst)),
+                    };
+                    if let Err(trap) = result {
+                        return Some(trap);
+                    }
+                } else {
+                    let val = match funct3 {
+                        0 | 4 => self.load_data(vaddr, 1).map(u64::from),
+                        1 | 5 => self.load_data(vaddr, 2).map(u64::from),
+                        2 | 6 => self.load_data(vaddr, 4).map(u64::from),
+                        3 => self.load_double(vaddr),
+                        _ => return Some(self.illegal(inst)),
+                    };
+                    let val = match val {
+                        Ok(val) => val,
+                        Err(trap) => return Some(trap),
+                    };
+                    let val = match funct3 {
+                        0 => val as i8 as u64,
+                        1 => val as i16 as u64,
+                        2 => val as i32 as u64,
+                        _ => val,
+                    };
+                    self.set_rd64(rd, val);
+                }
+            }
+            Opcode::OpImm => {
+                // The shifts take a 6-bit shamt, leaving a funct6 above it
+                let shamt = imm as u32 & 0b111111;
+                let val = match (funct3, funct7 >> 1) {
+                    (0, _) => rs1.wrapping_add(imm),
+                    (2, _) => ((rs1 as i64) < (imm as i64)) as u64,
+                    (3, _) => (rs1 < imm) as u64,
+                    (4, _) => rs1 ^ imm,
+                    (6, _) => rs1 | imm,
+                    (7, _) => rs1 & imm,
+                    (1, 0) => rs1 << shamt,
+                    (5, 0) => rs1 >> shamt,
+                    (5, 0b010000) => ((rs1 as i64) >> shamt) as u64,
+                    _ => return Some(self.illegal(inst)),
+                };
+                self.set_rd64(rd, val);
+            }
+            Opcode::Op => {
+                let shamt = rs2 as u32 & 0b111111;
+                let val = match (funct7, funct3) {
+                    (0, 0) => rs1.wrapping_add(rs2),
+                    (0b0100000, 0) => rs1.wrapping_sub(rs2),
+                    (0, 2) => ((rs1 as i64) < (rs2 as i64)) as u64,
+                    (0, 3) => (rs1 < rs2) as u64,
+                    (0, 4) => rs1 ^ rs2,
+                    (0, 6) => rs1 | rs2,
+                    (0, 7) => rs1 & rs2,
+                    (0, 1) => rs1 << shamt,
+                    (0, 5) => rs1 >> shamt,
+                    (0b0100000, 5) => ((rs1 as i64) >> shamt) as u64,
+                    (1, 0) => rs1.wrapping_mul(rs2),
+                    (1, 1) => ((rs1 as i64 as i128 * rs2 as i64 as i128) >> 64) as u64,
+                    (1, 2) => ((rs1 as i64 as i128 * rs2 as i128) >> 64) as u64,
+                    (1, 3) => ((rs1 as u128 * rs2 as u128) >> 64) as u64,
+                    // The same division rules as RV32, at 64 bits
+                    (1, 4) if rs2 == 0 => u64::MAX,
+                    (1, 4) => (rs1 as i64).wrapping_div(rs2 as i64) as u64,
+                    (1, 5) if rs2 == 0 => u64::MAX,
+                    (1, 5) => rs1 / rs2,
+                    (1, 6) if rs2 == 0 => rs1,
+                    (1, 6) => (rs1 as i64).wrapping_rem(rs2 as i64) as u64,
+                    (1, 7) if rs2 == 0 => rs1,
+                    (1, 7) => rs1 % rs2,
+                    _ => return Some(self.illegal(inst)),
+                };
+                self.set_rd64(rd, val);
+            }
+            // The word forms work on the low halves and sign-extend the
+            // 32-bit result, which `set_rd` does
+            Opcode::OpImm32 => {
+                let (rs1, shamt) = (rs1 as u32, imm as u32 & 0b11111);
+                let val = match (funct3, funct7) {
+                    (0, _) => rs1.wrapping_add(imm as u32),
+                    (1, 0) => rs1 << shamt,
+                    (5, 0) => rs1 >> shamt,
+                    (5, 0b0100000) => ((rs1 as i32) >> shamt) as u32,
+                    _ => return Some(self.illegal(inst)),
+                };
+                self.set_rd(rd, val);
+            }
+            Opcode::Op32 => {
+                let (rs1, rs2) = (rs1 as u32, rs2 as u32);
+                let shamt = rs2 & 0b11111;
+                let val = match (funct7, funct3) {
+                    (0, 0) => rs1.wrapping_add(rs2),
+                    (0b0100000, 0) => rs1.wrapping_sub(rs2),
+                    (0, 1) => rs1 << shamt,
+                    (0, 5) => rs1 >> shamt,
+                    (0b0100000, 5) => ((rs1 as i32) >> shamt) as u32,
+                    (1, 0) => rs1.wrapping_mul(rs2),
+                    (1, 4) if rs2 == 0 => u32::MAX,
+                    (1, 4) => (rs1 as i32).wrapping_div(rs2 as i32) as u32,
+                    (1, 5) if rs2 == 0 => u32::MAX,
+                    (1, 5) => rs1 / rs2,
+                    (1, 6) if rs2 == 0 => rs1,
+                    (1, 6) => (rs1 as i32).wrapping_rem(rs2 as i32) as u32,
+                    (1, 7) if rs2 == 0 => rs1,
+                    (1, 7) => rs1 % rs2,
+                    _ => return Some(self.illegal(inst)),
+                };
+                self.set_rd(rd, val);
+            }
+            _ => return None,
+        }
+        self.pc = next;
+        Some(StepOutcome::Continue)
+    }
+
+    fn execute(&mut self, decoded: Decoded) -> StepOutcome {
+        let Decoded {
+            inst,
+            opcode,
+            rs1: rs1_raw,
+            rs2: rs2_raw,
+            rd: rd_raw,
+            funct3,
+            funct7,
+            imm,
+            ..
+        } = decoded;
+
+        let rs1 = self.reg[rs1_raw as usize];
+        let rs2 = self.reg[rs2_raw as usize];
+        let rd = rd_raw as usize;
+
+        if self.trace {
+            self.trace_inst(inst, opcode);
+        }
+        if self.rv64() {
+            if let Some(outcome) = self.execute_rv64(decoded) {
+                return outcome;
+            }
+        }
+
+        match opcode {
+            // Only a 64-bit hart has the word forms
+            Opcode::OpImm32 | Opcode::Op32 => return self.illegal(inst),
+            Opcode::LoadFp
+            | Opcode::StoreFp
+            | Opcode::Madd
+            | Opcode::Msub
+            | Opcode::Nmsub
+            | Opcode::Nmadd
+            | Opcode::OpFp => return self.execute_fp(decoded),
+            Opcode::Load => {
+                let vaddr = rs1.wrapping_add(imm);
+                let size = match funct3 {
+                    0 | 4 => 1,
+                    1 | 5 => 2,
+                    2 => 4,
+                    _ => return self.illegal(inst),
+                };
+                let val = match self.load_data(vaddr, size) {
+                    Ok(val) => val,
+                    Err(trap) => return trap,
+                };
+                let val = match funct3 {
+                    0 => val as i8 as i32 as u32,
+                    1 => val as i16 as i32 as u32,
+                    _ => val,
+                };
+                self.set_rd(rd, val);
+                self.pc = self.pc.wrapping_add(self.inst_len);
+            }
+            Opcode::Store => {
+                let vaddr = rs1.wrapping_add(imm);
+                let size = match funct3 {
+                    0 => 1,
+                    1 => 2,
+                    2 => 4,
+                    _ => return self.illegal(inst),
+                };
+                if let Err(trap) = self.store_data(vaddr, size, rs2) {
+                    return trap;
+                }
+                self.pc = self.pc.wrapping_add(self.inst_len);
+            }
+            Opcode::Branch => {
+                let target = self.pc.wrapping_add(imm);
+                let cond = match funct3 {
+                    0 => rs1 == rs2,
+                    1 => rs1 != rs2,
+                    4 => (rs1 as i32) < (rs2 as i32),
+                    5 => (rs1 as i32) >= (rs2 as i32),
+                    6 => rs1 < rs2,
+                    7 => rs1 >= rs2,
+                    _ => return self.illegal(inst),
+                };
+                if cond {
+                    if let Some(trap) = self.check_target(target) {
+                        return trap;
+                    }
+                    self.pc = target;
+                } else {
+                    self.pc = self.pc.wrapping_add(self.inst_len);
+                }
+            }
+            Opcode::Jalr => {
+                let target = rs1.wrapping_add(imm) & !1;
+                if let Some(trap) = self.check_target(target) {
+                    return trap;
+                }
+                self.set_rd(rd, self.pc.wrapping_add(self.inst_len));
+                self.pc = target;
+            }
+            Opcode::Jal => {
+                let target = self.pc.wrapping_add(imm);
+                if let Some(trap) = self.check_target(target) {
+                    return trap;
+                }
+                self.set_rd(rd, self.pc.wrapping_add(self.inst_len));
+                self.pc = target;
+            }
+            Opcode::MiscMem => {
+                match funct3 {
+                    // FENCE. No need for fencing: only one hart.
+                    0 => {}
+                    // FENCE.I. Stores already keep cached decodes and compiled
+                    // code in sync, but a guest can't know that.
+                    1 => self.flush_code(),
+                    // CBO.*, which are told apart by the immediate
+                    0b010 if rd == 0 => match inst >> 20 {
+                        // Whether CBIE asks for a flush or a real invalidate,
+                        // CBO.INVAL does nothing here
+                        0 if self.config.zicbom && self.envcfg_allows(csr::ENVCFG_CBIE) => {}
+                        1 | 2 if self.config.zicbom && self.envcfg_allows(csr::ENVCFG_CBCFE) => {}
+                        4 if self.config.zicboz && self.envcfg_allows(csr::ENVCFG_CBZE) => {
+                            if let Some(trap) = self.cbo_zero(rs1) {
+                                return trap;
+                            }
+                        }
+                        _ => return self.illegal(inst),
+                    },
+                    _ => {}
+                }
+                self.pc = self.pc.wrapping_add(self.inst_len);
+            }
+            Opcode::OpImm => {
+                let shamt = imm & 0b11111;
+                let zbb = self.config.zbb;
+                // The top bits are part of the immediate everywhere except
+                // the shifts, which have a funct7 like OP. There's no SUBI for
+                // them to encode: ADDI takes negative immediates. Zbb's unary
+                // ops are shifts with the shamt field picking the op.
+                let val = match (funct3, funct7) {
+                    // An ADDI with bit 30 set looks like OP's SUB, but that bit
+                    // is imm[10], so there's no SUBI encoding to reject
+                    (0, _) => rs1.wrapping_add(imm),
+                    (2, _) => ((rs1 as i32) < (imm as i32)) as u32,
+                    (3, _) => (rs1 < imm) as u32,
+                    (4, _) => rs1 ^ imm,
+                    (6, _) => rs1 | imm,
+                    (7, _) => rs1 & imm,
+                    (1, 0) => rs1 << shamt,
+                    (5, 0) => rs1 >> shamt,
+                    (5, 0b0100000) => ((rs1 as i32) >> shamt) as u32,
+                    (1, 0b0110000) if zbb => match rs2_raw {
+                        0 => rs1.leading_zeros(),
+                        1 => rs1.trailing_zeros(),
+                        2 => rs1.count_ones(),
+                        // SEXT.B and SEXT.H
+                        4 => rs1 as i8 as i32 as u32,
+                        5 => rs1 as i16 as i32 as u32,
+                        _ => return self.illegal(inst),
+                    },
+                    // RORI
+                    (5, 0b0110000) if zbb => rs1.rotate_right(shamt),
+                    // ORC.B sets each byte to all ones if any bit of it is set
+                    (5, 0b0010100) if zbb && rs2_raw == 0b00111 => {
+                        let mut val = 0;
+                        for byte in 0..4 {
+                            if rs1 >> (8 * byte) & 0xff != 0 {
+                                val |= 0xff << (8 * byte);
+                            }
+                        }
+                        val
+                    }
+                    // REV8
+                    (5, 0b0110100) if zbb && rs2_raw == 0b11000 => rs1.swap_bytes(),
+                    _ => return self.illegal(inst),
+                };
+                self.set_rd(rd, val);
+                self.pc = self.pc.wrapping_add(self.inst_len);
+            }
+            Opcode::Op => {
+                // funct7 must be zero, except for the SUB and SRA variants,
+                // the M extension and Zba/Zbb
+                let (zba, zbb) = (self.config.zba, self.config.zbb);
+                let val = match (funct7, funct3) {
+                    (0, 0) => rs1.wrapping_add(rs2),
+                    (0b0100000, 0) => rs1.wrapping_sub(rs2),
+                    (0, 2) => ((rs1 as i32) < (rs2 as i32)) as u32,
+                    (0, 3) => (rs1 < rs2) as u32,
+                    (0, 4) => rs1 ^ rs2,
+                    (0, 6) => rs1 | rs2,
+                    (0, 7) => rs1 & rs2,
+                    (0, 1) => rs1 << (rs2 & 0b11111),
+                    (0, 5) => rs1 >> (rs2 & 0b11111),
+                    (0b0100000, 5) => ((rs1 as i32) >> (rs2 & 0b11111)) as u32,
+                    (1, 0) => rs1.wrapping_mul(rs2),
+                    (1, 1) => ((rs1 as i32 as i64 * rs2 as i32 as i64) >> 32) as u32,
+                    (1, 2) => ((rs1 as i32 as i64 * rs2 as i64) >> 32) as u32,
+                    (1, 3) => ((rs1 as u64 * rs2 as u64) >> 32) as u32,
+                    // Division never traps. Dividing by zero gives all ones
+                    // and leaves the dividend as the remainder, and the one
+                    // signed overflow, MIN / -1, gives MIN remainder 0.
+                    (1, 4) if rs2 == 0 => u32::MAX,
+                    (1, 4) => (rs1 as i32).wrapping_div(rs2 as i32) as u32,
+                    (1, 5) if rs2 == 0 => u32::MAX,
+                    (1, 5) => rs1 / rs2,
+                    (1, 6) if rs2 == 0 => rs1,
+                    (1, 6) => (rs1 as i32).wrapping_rem(rs2 as i32) as u32,
+                    (1, 7) if rs2 == 0 => rs1,
+                    (1, 7) => rs1 % rs2,
+                    // SH1ADD, SH2ADD and SH3ADD
+                    (0b0010000, 2) if zba => (rs1 << 1).wrapping_add(rs2),
+                    (0b0010000, 4) if zba => (rs1 << 2).wrapping_add(rs2),
+                    (0b0010000, 6) if zba => (rs1 << 3).wrapping_add(rs2),
+                    // ANDN, ORN and XNOR
+                    (0b0100000, 7) if zbb => rs1 & !rs2,
+                    (0b0100000, 6) if zbb => rs1 | !rs2,
+                    (0b0100000, 4) if zbb => !(rs1 ^ rs2),
+                    // MIN, MINU, MAX and MAXU
+                    (0b0000101, 4) if zbb => (rs1 as i32).min(rs2 as i32) as u32,
+                    (0b0000101, 5) if zbb => rs1.min(rs2),
+                    (0b0000101, 6) if zbb => (rs1 as i32).max(rs2 as i32) as u32,
+                    (0b0000101, 7) if zbb => rs1.max(rs2),
+                    // ROL and ROR
+                    (0b0110000, 1) if zbb => rs1.rotate_left(rs2 & 0b11111),
+                    (0b0110000, 5) if zbb => rs1.rotate_right(rs2 & 0b11111),
+                    // ZEXT.H, which is PACK with rs2 = x0
+                    (0b0000100, 4) if zbb && rs2_raw == 0 => rs1 & 0xffff,
+                    _ => return self.illegal(inst),
+                };
+                self.set_rd(rd, val);
+                self.pc = self.pc.wrapping_add(self.inst_len);
+            }
+            Opcode::System => {
+                let funct12 = inst >> 20;
+                // CSRRS and CSRRC with x0, or an immediate of 0, only read
+                let csr_write = funct3 & 0b11 == 0b01 || rs1_raw != 0;
+                // The immediate forms use the rs1 field as a 5-bit zimm
+                let csr_src = if funct3 & 0b100 != 0 { rs1_raw } else { rs1 };
+                if funct3 != 0 && !self.csr_accessible(funct12, csr_write) {
+                    return self.illegal(inst);
+                }
+                if funct3 != 0
+                    && csr_write
+                    && matches!(
+                        funct12,
+                        csr::MCYCLE | csr::MCYCLEH | csr::MINSTRET | csr::MINSTRETH
+                    )
+                {
+                    self.counter_written = Some(funct12);
+                }
+                match funct3 {
+                    0b000 => match funct12 {
+                        0 => {
+                            let num = self.reg[17];
+                            if let Some(mut handler) = self.ecall_handlers.remove(&num) {
+                                let args = self.syscall_args();
+                                let ret = handler(self, args);
+                                self.ecall_handlers.insert(num, handler);
+                                self.set_rd(10, ret);
+                                self.pc = self.pc.wrapping_add(self.inst_len);
+                                return StepOutcome::Continue;
+                            }
+                            if self.config.sbi && self.priv_mode == PrivMode::Supervisor {
+                                return self.sbi_call();
+                            }
+                            let cause = match self.priv_mode {
+                                PrivMode::User => Exception::EnvCallFromU,
+                                PrivMode::Supervisor => Exception::EnvCallFromS,
+                                PrivMode::Machine => Exception::EnvCallFromM,
+                            };
+                            return self.trap(cause, 0);
+                        }
+                        // Left to the host, which can print `dump_state` if it
+                        // wants the registers
+                        1 => return StepOutcome::Ebreak,
+                        // MRET
+                        0x302 => {
+                            if self.priv_mode != PrivMode::Machine {
+                                return self.illegal(inst);
+                            }
+                            let mut status = self.csrs[csr::MSTATUS as usize];
+                            self.priv_mode = PrivMode::from_bits(status >> csr::MSTATUS_MPP_SHIFT);
+                            status &= !(csr::MSTATUS_MPP | csr::MSTATUS_MIE);
+                            // Leaving M mode stops MPRV from applying
+                            if self.priv_mode != PrivMode::Machine {
+                                status &= !csr::MSTATUS_MPRV;
+                            }
+                            if status & csr::MSTATUS_MPIE != 0 {
+                                status |= csr::MSTATUS_MIE;
+                            }
+                            status |= csr::MSTATUS_MPIE;
+                            self.csrs[csr::MSTATUS as usize] = status;
+                            self.reservation = None;
+                            self.pc = self.csrs[csr::MEPC as usize];
+                            return StepOutcome::Continue;
+                        }
+                        // SRET
+                        0x102 => {
+                            if self.priv_mode < PrivMode::Supervisor {
+                                return self.illegal(inst);
+                            }
+                            let mut status = self.csrs[csr::MSTATUS as usize];
+                            self.priv_mode = if status & csr::MSTATUS_SPP != 0 {
+                                PrivMode::Supervisor
+                            } else {
+                                PrivMode::User
+                            };
+                            status &= !(csr::MSTATUS_SPP | csr::MSTATUS_SIE | csr::MSTATUS_MPRV);
+                            if status & csr::MSTATUS_SPIE != 0 {
+                                status |= csr::MSTATUS_SIE;
+                            }
+                            status |= csr::MSTATUS_SPIE;
+                            self.csrs[csr::MSTATUS as usize] = status;
+                            self.reservation = None;
+                            self.pc = self.csrs[csr::SEPC as usize];
+                            return StepOutcome::Continue;
+                        }
+                        // WFI. Any interrupt that's pending is taken before
+                        // the next instruction anyway, so there's nothing to
+                        // wait for beyond the timer.
+                        0x105 => {
+                            let status = self.csrs[csr::MSTATUS as usize];
+                            if self.priv_mode < PrivMode::Machine && status & csr::MSTATUS_TW != 0 {
+                                return self.illegal(inst);
+                            }
+                            if self.config.wfi_fast_forward {
+                                self.skip_to_timer();
+                            }
+                        }
+                        // SFENCE.VMA. Translations aren't cached, but decoded
+                        // and compiled code may be stale under a new mapping.
+                        _ if funct7 == 0b0001001 && rd == 0 => {
+                            if !self.vm_accessible() {
+                                return self.illegal(inst);
+                            }
+                            self.flush_code();
+                        }
+                        _ => return self.illegal(inst),
+                    },
+                    // CSRRW(I) to x0 doesn't read the CSR at all
+                    0b001 | 0b101 if rd == 0 => self.write_csr(funct12, csr_src),
+                    0b001 | 0b101 => {
+                        let temp = self.read_csr(funct12);
+                        self.write_csr(funct12, csr_src);
+                        self.set_csr_rd(rd, funct12, temp);
+                    }
+                    0b010 | 0b110 => {
+                        let temp = self.read_csr(funct12);
+                        if csr_write {
+                            self.write_csr(funct12, temp | csr_src);
+                        }
+                        self.set_csr_rd(rd, funct12, temp);
+                    }
+                    0b011 | 0b111 => {
+                        let temp = self.read_csr(funct12);
+                        if csr_write {
+                            self.write_csr(funct12, temp & !csr_src);
+                        }
+                        self.set_csr_rd(rd, funct12, temp);
+                    }
+                    _ => return self.illegal(inst),
+                }
+                self.pc = self.pc.wrapping_add(self.inst_len);
+            }
+            Opcode::Auipc => {
+                self.set_rd(rd, self.pc.wrapping_add(imm));
+                self.pc = self.pc.wrapping_add(self.inst_len);
+            }
+            Opcode::Lui => {
+                self.set_rd(rd, imm);
+                self.pc = self.pc.wrapping_add(self.inst_len);
+            }
+            Opcode::Amo => {
+                if funct3 != 0b010 {
+                    return self.illegal(inst);
+                }
+                // The aq and rl bits in funct7 are ignored: each instruction
+                // completes before the next starts, and harts in a Machine
+                // step one at a time, so every AMO is already sequentially
+                // consistent
+                let funct5 = funct7 >> 2;
+                // LR and SC are handled separately, the rest are read-modify-write
+                let op: Option<fn(u32, u32) -> u32> = match funct5 {
+                    0b00010 | 0b00011 => None,
+                    0b00001 => Some(|_, b| b),
+                    0b00000 => Some(u32::wrapping_add),
+                    0b00100 => Some(|a, b| a ^ b),
+                    0b01100 => Some(|a, b| a & b),
+                    0b01000 => Some(|a, b| a | b),
+                    0b10000 => Some(|a, b| (a as i32).min(b as i32) as u32),
+                    0b10100 => Some(|a, b| (a as i32).max(b as i32) as u32),
+                    0b11000 => Some(u32::min),
+                    0b11100 => Some(u32::max),
+                    _ => return self.illegal(inst),
+                };
+                // LR only reads, everything else reports store/AMO faults
+                let (access, misaligned, fault) = if funct5 == 0b00010 {
+                    (
+                        AccessType::Load,
+                        Exception::LoadAddressMisaligned,
+                        Exception::LoadAccessFault,
+                    )
+                } else {
+                    (
+                        AccessType::Store,
+                        Exception::StoreAddressMisaligned,
+                        Exception::StoreAccessFault,
+                    )
+                };
+                // An AMO can't be split up, so it traps when misaligned
+                // whatever config.misaligned says. This outranks page faults.
+                if rs1 & 0b11 != 0 {
+                    return self.trap(misaligned, rs1);
+                }
+                let addr = match self.translate(rs1, access) {
+                    Ok(addr) => addr,
+                    Err(cause) => return self.trap(cause, rs1),
+                };
+                // AMOs need to be able to read the word as well as write it
+                if !self.pmp_allows(addr, 4, access)
+                    || (op.is_some() && !self.pmp_allows(addr, 4, AccessType::Load))
+                {
+                    return self.trap(fault, rs1);
+                }
+                let temp = match self.load(addr, 4, None) {
+                    Ok(val) => val,
+                    Err(_) => return self.trap(fault, rs1),
+                };
+                if self.trace_sink.is_some() {
+                    self.commit.loads.push(rs1);
+                }
+                match (funct5, op) {
+                    (_, Some(op)) => {
+                        self.note_store(addr, 4);
+                        let val = op(temp, rs2);
+                        if self.store(addr, 4, val, None).is_err() {
+                            return self.trap(fault, rs1);
+                        }
+                        if self.trace_sink.is_some() {
+                            self.commit.stores.push((rs1, 4, val as u64));
+                        }
+                        self.set_rd(rd, temp);
+                    }
+                    (0b00010, None) => {
+                        self.reservation = Some(addr);
+                        self.set_rd(rd, temp);
+                    }
+                    _ => {
+                        // SC succeeds only if nothing wrote to the word since
+                        // the LR. Either way the reservation is used up.
+                        if self.reservation.take() == Some(addr) {
+                            if self.store(addr, 4, rs2, None).is_err() {
+                                return self.trap(fault, rs1);
+                            }
+                            if self.trace_sink.is_some() {
+                                self.commit.stores.push((rs1, 4, rs2 as u64));
+                            }
+                            self.set_rd(rd, 0);
+                            self.stats.sc_successes += 1;
+                        } else {
+                            self.set_rd(rd, 1);
+                            self.stats.sc_failures += 1;
+                        }
+                    }
+                }
+                self.pc = self.pc.wrapping_add(self.inst_len);
+            }
+        }
+
+        StepOutcome::Continue
+    }
+}
+
+#[cfg(all(test, feature = "jit"))]
+mod jit_tests {
+    use super::*;
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    #[rustfmt::skip]
+    const LOOP: [u32; 11] = [
+        0x0000_0513, // li    a0, 0
+        0x0640_0593, // li    a1, 100
+        0x8000_1637, // lui   a2, 0x80001
+        // loop:
+        0x0035_0513, // addi  a0, a0, 3
+        0x00a6_c6b3, // xor   a3, a3, a0
+        0x0036_9713, // slli  a4, a3, 3
+        0x00e6_2023, // sw    a4, 0(a2)
+        0x0046_0613, // addi  a2, a2, 4
+        0xfff5_8593, // addi  a1, a1, -1
+        0xfe05_94e3, // bnez  a1, loop
+        0x0010_0073, // ebreak
+    ];
+
+    fn run(step: fn(&mut Core) -> StepOutcome) -> Core {
+        let mut core = Core::new();
+        core.load_image(LOOP.iter().flat_map(|w| w.to_le_bytes()).collect())
+            .unwrap();
+        while !step(&mut core).stops() {}
+        core
+    }
+
+    #[test]
+    fn step_block_matches_step_over_a_loop() {
+        let interp = run(Core::step);
+        let jit = run(Core::step_block);
+        assert_eq!(interp.regs(), jit.regs());
+        assert_eq!(interp.pc, jit.pc);
+        assert_eq!(interp.cycle_count, jit.cycle_count);
+        assert_eq!(
+            interp.counter(csr::MINSTRET, csr::MINSTRETH),
+            jit.counter(csr::MINSTRET, csr::MINSTRETH)
+        );
+        assert_eq!(
+            interp.memory_view(0x8000_1000, 400),
+            jit.memory_view(0x8000_1000, 400)
+        );
+        assert_eq!(interp.reg(12), 0x8000_1000 + 400);
+    }
+
+    #[test]
+    fn step_block_runs_mem_hooks_on_every_access() {
+        let count = Rc::new(Cell::new(0));
+        let mut core = Core::new();
+        core.load_image(LOOP.iter().flat_map(|w| w.to_le_bytes()).collect())
+            .unwrap();
+        let seen = count.clone();
+        core.set_mem_hook(move |_, _, _, _| seen.set(seen.get() + 1));
+        while !core.step_block().stops() {}
+        assert_eq!(count.get(), 100);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::{Cell, RefCell};
+    use std::rc::Rc;
+
+    /// A writer that keeps everything written to it where the test can see.
+    #[derive(Clone, Default)]
+    struct Captured(Rc<RefCell<Vec<u8>>>);
+
+    impl Captured {
+        fn text(&self) -> String {
+            String::from_utf8_lossy(&self.0.borrow()).into_owned()
+        }
+    }
+
+    impl io::Write for Captured {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    /// A core with `program` at the start of RAM.
+    fn core_with(program: &[u32]) -> Core {
+        let mut core = Core::new();
+        core.load_image(program.iter().flat_map(|w| w.to_le_bytes()).collect())
+            .unwrap();
+        core
+    }
+
+    /// A minimal ELF32 executable with `program` in one segment at `paddr`,
+    /// starting at `entry`, with `symbols` in its `.symtab`.
+    fn elf_with(paddr: u32, program: &[u32], entry: u32, symbols: &[(&str, u32)]) -> Vec<u8> {
+        let code: Vec<u8> = program.iter().flat_map(|w| w.to_le_bytes()).collect();
+        let mut strtab = vec![0];
+        let mut symtab = vec![0; 16];
+        for &(name, addr) in symbols {
+            symtab.extend((strtab.len() as u32).to_le_bytes());
+            symtab.extend(addr.to_le_bytes());
+            // Size 0, STT_FUNC, section 1
+            symtab.extend([0, 0, 0, 0, 2, 0, 1, 0]);
+            strtab.extend(name.bytes().chain(Some(0)));
+        }
+        let code_off = 52 + 32;
+        let symtab_off = code_off + code.len();
+        let strtab_off = symtab_off + symtab.len();
+        let shoff = strtab_off + strtab.len();
+
+        let mut elf = b"\x7fELF\x01\x01\x01".to_vec();
+        elf.resize(16, 0);
+        let half = |elf: &mut Vec<u8>, val: u16| elf.extend(val.to_le_bytes());
+        let word = |elf: &mut Vec<u8>, val: u32| elf.extend(val.to_le_bytes());
+        half(&mut elf, 2);
+        half(&mut elf, 243);
+        word(&mut elf, 1);
+        word(&mut elf, entry);
+        word(&mut elf, 52);
+        word(&mut elf, shoff as u32);
+        word(&mut elf, 0);
+        for val in [52, 32, 1, 40, 3, 0] {
+            half(&mut elf, val);
+        }
+        // The PT_LOAD program header
+        for val in [
+            1,
+            code_off as u32,
+            paddr,
+            paddr,
+            code.len() as u32,
+            code.len() as u32,
+            7,
+            4,
+        ] {
+            word(&mut elf, val);
+        }
+        elf.extend(&code);
+        elf.extend(&symtab);
+        elf.extend(&strtab);
+        // Null, .symtab (linked to section 2) and .strtab section headers
+        elf.resize(elf.len() + 40, 0);
+        let sections = [
+            (2, symtab_off, symtab.len(), 2),
+            (3, strtab_off, strtab.len(), 0),
+        ];
+        for (kind, offset, size, link) in sections {
+            for val in [0, kind, 0, 0, offset as u32, size as u32, link, 0, 0, 0] {
+                word(&mut elf, val);
+            }
+        }
+        elf
+    }
+
+    #[test]
+    fn rv64_addi_carries_past_32_bits() {
+        // addi a0, a0, 1
+        let mut core = core_with(&[0x0015_0513]);
+        core.config.xlen = Xlen::Rv64;
+        core.set_reg64(10, 0xffff_ffff);
+        core.step();
+        assert_eq!(core.reg64(10), 0x1_0000_0000);
+
+        let mut core = core_with(&[0x0015_0513]);
+        core.set_reg64(10, 0xffff_ffff);
+        core.step();
+        assert_eq!(core.reg64(10), 0);
+    }
+
+    #[test]
+    fn rv64_sd_and_ld_are_big_endian_as_a_whole() {
+        // sd a1, 0(a0); ld a2, 0(a0)
+        let mut core = core_with(&[0x00b5_3023, 0x0005_3603]);
+        core.config.xlen = Xlen::Rv64;
+        core.config.big_endian = true;
+        let addr = core.pc + 0x100;
+        core.set_reg64(10, addr as u64);
+        core.set_reg64(11, 0x0102_0304_0506_0708);
+        core.step();
+        core.step();
+        assert_eq!(core.reg64(12), 0x0102_0304_0506_0708);
+        assert_eq!(core.mem.lb(addr), 0x01);
+        assert_eq!(core.mem.lb(addr + 7), 0x08);
+    }
+
+    #[test]
+    fn inspect_page_table_reports_the_mapping() {
+        use crate::mmu::{PTE_R, PTE_V, PTE_X};
+
+        let mut core = Core::new();
+        let ram = core.mem.ram_base();
+        let (root, table, page) = (ram + 0x2000, ram + 0x3000, ram + 0x4000);
+        let vaddr = 0x4000_5123;
+        core.mem
+            .sw(root + (vaddr >> 22) * 4, (table >> 12) << 10 | PTE_V);
+        let leaf = (page >> 12) << 10 | PTE_V | PTE_R | PTE_X;
+        core.mem.sw(table + (vaddr >> 12 & 0x3ff) * 4, leaf);
+        assert!(core.inspect_page_table(vaddr).is_none());
+
+        core.csrs[csr::SATP as usize] = SATP_MODE_SV32 | root >> 12;
+        let walk = core.inspect_page_table(vaddr).unwrap();
+        assert_eq!(walk.ptes.len(), 2);
+        let found = walk.result.unwrap();
+        assert_eq!(found.paddr, page + 0x123);
+        assert_eq!(found.level, 0);
+        assert_eq!(found.pte & (PTE_R | mmu::PTE_W | PTE_X), PTE_R | PTE_X);
+    }
+
+    #[test]
+    fn strict_traps_a_misaligned_load() {
+        // lw a1, 1(a0)
+        for strict in [false, true] {
+            let mut core = core_with(&[0x0015_2583]);
+            core.config.strict = strict;
+            core.set_reg(10, core.pc + 0x100);
+            core.step();
+            let trapped = core.csrs[csr::MCAUSE as usize] == 4;
+            assert_eq!(trapped, strict);
+        }
+    }
+
+    #[test]
+    fn strict_gates_counters_on_counteren() {
+        // csrr a0, cycle, from U mode
+        let run = |strict: bool, mcounteren: u32, scounteren: u32| {
+            let mut core = core_with(&[0xc000_2573]);
+            core.config.strict = strict;
+            core.csrs[csr::MCOUNTEREN as usize] = mcounteren;
+            core.csrs[csr::SCOUNTEREN as usize] = scounteren;
+            core.priv_mode = PrivMode::User;
+            core.step();
+            core.csrs[csr::MCAUSE as usize] == 2
+        };
+        assert!(!run(false, 0, 0));
+        assert!(run(true, 0, 0));
+        assert!(run(true, 1, 0));
+        assert!(run(true, 0, 1));
+        // Only bit 0, for cycle, matters here
+        assert!(!run(true, 1, 1));
+        assert!(run(true, !1, !1));
+    }
+
+    // The words below are the assembler's encodings, with every register
+    // x0, so only the immediate bits are set
+
+    #[test]
+    fn read_imm_i_and_s_cover_their_range() {
+        // addi x0, x0, -2048 and 2047
+        assert_eq!(read_imm_i(0x8000_0013) as i32, -2048);
+        assert_eq!(read_imm_i(0x7ff0_0013) as i32, 2047);
+        // sw x0, -2048(x0) and 2047(x0)
+        assert_eq!(read_imm_s(0x8000_2023) as i32, -2048);
+        assert_eq!(read_imm_s(0x7e00_2fa3) as i32, 2047);
+    }
+
+    #[test]
+    fn read_imm_u_keeps_the_top_20_bits() {
+        // lui x0, 0xfffff and 0x80000
+        assert_eq!(read_imm_u(0xffff_f037), 0xffff_f000);
+        assert_eq!(read_imm_u(0x8000_0037), 0x8000_0000);
+    }
+
+    #[test]
+    fn read_imm_b_covers_its_range() {
+        // beq x0, x0 with offsets -8, -4096 and 4094
+        assert_eq!(read_imm_b(0xfe00_0ce3) as i32, -8);
+        assert_eq!(read_imm_b(0x8000_0063) as i32, -4096);
+        assert_eq!(read_imm_b(0x7e00_0fe3) as i32, 4094);
+    }
+
+    #[test]
+    fn read_imm_j_covers_its_range() {
+        // jal x0 with offsets -1 MiB, 1 MiB - 2 and 0x7f002
+        assert_eq!(read_imm_j(0x8000_006f) as i32, -0x10_0000);
+        assert_eq!(read_imm_j(0x7fff_f06f) as i32, 0xf_fffe);
+        assert_eq!(read_imm_j(0x0027_f06f) as i32, 0x7_f002);
+    }
+
+    /// Pulls the B and J immediates out of `inst` a bit at a time, straight
+    /// from the spec's tables, as a check on the shift-and-mask versions.
+    fn reference_imm(inst: u32, layout: &[(u32, u32)], bits: u32) -> u32 {
+        let mut imm = 0;
+        for &(from, to) in layout {
+            imm |= (inst >> from & 1) << to;
+        }
+        if imm >> (bits - 1) != 0 {
+            imm |= u32::MAX << bits;
+        }
+        imm
+    }
+
+    #[test]
+    fn read_imm_b_and_j_match_a_reference_on_random_words() {
+        // inst bit, immediate bit
+        let mut b = vec![(31, 12), (7, 11)];
+        b.extend((25..31).map(|i| (i, i - 20)));
+        b.extend((8..12).map(|i| (i, i - 7)));
+        let mut j = vec![(31, 20), (20, 11)];
+        j.extend((21..31).map(|i| (i, i - 20)));
+        j.extend((12..20).map(|i| (i, i)));
+
+        // xorshift32, so every run checks the same words
+        let mut state = 0x2545_f491u32;
+        for _ in 0..100_000 {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            assert_eq!(
+                read_imm_b(state),
+                reference_imm(state, &b, 13),
+                "{:#x}",
+                state
+            );
+            assert_eq!(
+                read_imm_j(state),
+                reference_imm(state, &j, 21),
+                "{:#x}",
+                state
+            );
+        }
+    }
+
+    #[test]
+    fn reset_clears_the_hart_but_not_ram() {
+        // csrw mscratch, a1; lr.w a0, (a1)
+        let mut core = core_with(&[0x3405_9073, 0x1005_a52f]);
+        let ram = core.mem.ram_base();
+        core.csrs[csr::MHARTID as usize] = 3;
+        core.set_reg(11, ram + 0x100);
+        core.mem.sw(ram + 0x100, 0x1234_5678);
+        core.step();
+        core.step();
+        core.timer.set_mtime(1000);
+        core.timer.set_mtimecmp(2000);
+        core.irq.raise(irq::MEIP);
+        core.priv_mode = PrivMode::User;
+        assert_eq!(core.reg(10), 0x1234_5678);
+        assert_eq!(core.csrs[csr::MSCRATCH as usize], ram + 0x100);
+        assert!(core.reservation.is_some());
+
+        core.reset();
+        assert_eq!(core.pc, ram);
+        assert_eq!(core.priv_mode, PrivMode::Machine);
+        assert_eq!(core.regs(), &[0; 32]);
+        assert_eq!(core.cycle_count, 0);
+        assert_eq!(core.reservation, None);
+        assert_eq!(core.timer.mtime(), 0);
+        assert!(!core.timer.is_pending());
+        assert_eq!(core.irq.pending(), 0);
+        assert_eq!(core.csrs[csr::MHARTID as usize], 3);
+        core.csrs[csr::MHARTID as usize] = 0;
+        assert!(core.csrs.iter().all(|&csr| csr == 0));
+        assert_eq!(core.mem.lw(ram + 0x100), 0x1234_5678);
+    }
+
+    #[test]
+    fn load_image_at_composes_blobs() {
+        let mut core = Core::new();
+        let ram = core.mem.ram_base();
+        let end = ram + core.mem.ram_size() as u32;
+        core.load_image_at(ram, &[1, 2, 3, 4]).unwrap();
+        core.load_image_at(ram + 4, &[5, 6]).unwrap();
+        core.load_image_at(end - 2, &[7, 8]).unwrap();
+        let mut buf = [0; 8];
+        core.read_mem(ram, &mut buf).unwrap();
+        assert_eq!(buf, [1, 2, 3, 4, 5, 6, 0, 0]);
+        core.read_mem(end - 4, &mut buf[..4]).unwrap();
+        assert_eq!(buf[..4], [0, 0, 7, 8]);
+
+        // Nothing is copied unless all of it fits
+        assert!(core.load_image_at(end - 1, &[9, 9]).is_err());
+        assert!(core.load_image_at(ram - 1, &[9, 9]).is_err());
+        core.read_mem(end - 4, &mut buf[..4]).unwrap();
+        assert_eq!(buf[..4], [0, 0, 7, 8]);
+        core.read_mem(ram, &mut buf[..1]).unwrap();
+        assert_eq!(buf[0], 1);
+    }
+
+    #[test]
+    fn load_image_rejects_an_image_bigger_than_ram() {
+        let mut core = core_with(&[0x1234_5678]);
+        let size = core.mem.ram_size();
+        assert!(core.load_image(vec![0; size + 1]).is_err());
+        assert_eq!(core.mem.lw(core.mem.ram_base()), 0x1234_5678);
+        assert!(core.load_image(vec![0; size]).is_ok());
+    }
+
+    /// A writer that's always full
+    struct Full;
+
+    impl io::Write for Full {
+        fn write(&mut self, _: &[u8]) -> io::Result<usize> {
+            Err(io::ErrorKind::WriteZero.into())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn dump_state_writes_to_the_callers_writer() {
+        // addi ra, zero, 0x123
+        let mut core = core_with(&[0x1230_0093]);
+        core.step();
+        let mut out = Vec::new();
+        core.dump_state(&mut out).unwrap();
+        let out = String::from_utf8(out).unwrap();
+        assert!(out.starts_with("pc: 80000004, priv: Machine, cycle: 1\n"));
+        assert!(out.lines().any(|line| line == " x1    ra: 00000123"));
+
+        let mut json = Vec::new();
+        core.dump_json(&mut json).unwrap();
+        let json = String::from_utf8(json).unwrap();
+        assert!(json.contains("\"pc\": \"0x80000004\""));
+        assert!(json.contains("\"0x00000000\", \"0x00000123\""));
+
+        assert!(core.dump_state(&mut Full).is_err());
+        assert!(core.dump_json(&mut Full).is_err());
+    }
+
+    #[test]
+    fn run_stops_at_a_breakpoint_before_executing_it() {
+        // li a0, 1; li a1, 2; li a2, 3; ebreak
+        let mut core = core_with(&[0x0010_0513, 0x0020_0593, 0x0030_0613, 0x0010_0073]);
+        let bp = core.pc + 8;
+        core.add_breakpoint(bp);
+        assert_eq!(core.run(), StepOutcome::Breakpoint(bp));
+        assert_eq!(core.pc, bp);
+        assert_eq!((core.reg(10), core.reg(11), core.reg(12)), (1, 2, 0));
+
+        core.remove_breakpoint(bp);
+        assert_eq!(core.run(), StepOutcome::Ebreak);
+        assert_eq!(core.reg(12), 3);
+    }
+
+    #[test]
+    fn run_for_stops_after_exactly_max_instructions() {
+        // 1: j 1b
+        let mut core = core_with(&[0x0000_006f]);
+        assert_eq!(core.run_for(10), StepOutcome::Continue);
+        assert_eq!(core.counter(csr::MINSTRET, csr::MINSTRETH), 10);
+    }
+
+    #[test]
+    fn run_to_syscall_stops_before_the_ecall() {
+        // li a0, 1; li a1, 2; li a7, 64; ecall
+        let mut core = core_with(&[0x0010_0513, 0x0020_0593, 0x0400_0893, 0x0000_0073]);
+        let ecall = core.pc + 12;
+        let args = core.run_to_syscall(10).unwrap();
+        assert_eq!(args.regs, [1, 2, 0, 0, 0, 0, 0, 64]);
+        assert_eq!(args.number(), 64);
+        assert_eq!(core.pc, ecall);
+        // Not enough budget to get there
+        let mut core = core_with(&[0x0010_0513, 0x0020_0593, 0x0400_0893, 0x0000_0073]);
+        assert_eq!(core.run_to_syscall(2), None);
+    }
+
+    #[test]
+    fn reg_history_follows_a_loop() {
+        // li a0, 0; li t0, 3; 1: addi a0, a0, 1; addi t0, t0, -1; bnez t0, 1b;
+        // ebreak
+        let program = [
+            0x0000_0513,
+            0x0030_0293,
+            0x0015_0513,
+            0xfff2_8293,
+            0xfe02_9ce3,
+            0x0010_0073,
+        ];
+        let mut core = core_with(&program);
+        core.track_reg_history(10, 8);
+        core.run_for(100);
+        assert_eq!(core.reg_history(10), [(0, 0), (2, 1), (5, 2), (8, 3)]);
+        assert!(core.reg_history(11).is_empty());
+
+        // Only the newest writes are kept
+        let mut core = core_with(&program);
+        core.track_reg_history(10, 2);
+        core.run_for(100);
+        assert_eq!(core.reg_history(10), [(5, 2), (8, 3)]);
+    }
+
+    #[test]
+    fn setup_user_stack_passes_argc_and_argv() {
+        // lw a0, 0(sp); lw a1, 4(sp); lw a2, 8(sp); lbu a3, 0(a2);
+        // lw a4, 12(sp); lw a5, 16(sp); ebreak
+        let mut core = core_with(&[
+            0x0001_2503,
+            0x0041_2583,
+            0x0081_2603,
+            0x0006_4683,
+            0x00c1_2703,
+            0x0101_2783,
+            0x0010_0073,
+        ]);
+        core.setup_user_stack(&["prog", "hello"], &["HOME=/"]);
+        assert_eq!(core.reg(2) % 16, 0);
+        core.run_for(10);
+        assert_eq!(core.reg(10), 2);
+        assert_eq!(core.reg(13), u32::from(b'h'));
+        // argv ends with a null, then envp begins
+        assert_eq!(core.reg(14), 0);
+        let string_at = |addr: u32, len: usize| {
+            let mut buf = vec![0; len + 1];
+            core.read_mem(addr, &mut buf).unwrap();
+            buf
+        };
+        assert_eq!(string_at(core.reg(11), 4), b"prog\0");
+        assert_eq!(string_at(core.reg(12), 5), b"hello\0");
+        assert_eq!(string_at(core.reg(15), 6), b"HOME=/\0");
+    }
+
+    #[test]
+    fn memory_view_borrows_plain_ram_only() {
+        use crate::uart::{Uart, UART_SIZE};
+
+        let mut core = Core::new();
+        let ram = core.mem.ram_base();
+        core.write_mem(ram + 0x100, &0xdead_beef_u32.to_le_bytes())
+            .unwrap();
+        let view = core.memory_view(ram + 0x100, 4).unwrap();
+        let word = u32::from_le_bytes([view[0], view[1], view[2], view[3]]);
+        assert_eq!(word, 0xdead_beef);
+
+        let end = ram + core.mem.ram_size() as u32;
+        assert!(core.memory_view(end - 4, 4).is_some());
+        assert!(core.memory_view(end - 2, 4).is_none());
+        assert!(core.memory_view(ram - 4, 8).is_none());
+
+        core.mem.map_device(
+            ram + 0x1000,
+            UART_SIZE,
+            Box::new(Uart::new(Box::new(io::sink()))),
+        );
+        assert!(core.memory_view(ram + 0xff0, 0x20).is_none());
+        assert!(core.memory_view(ram + 0xff0, 0x10).is_some());
+    }
+
+    #[test]
+    fn ram_slice_sees_guest_stores() {
+        // sw a1, 0(a0); lw a2, 4(a0)
+        let mut core = core_with(&[0x00b5_2023, 0x0045_2603]);
+        let addr = core.mem.ram_base() + 0x200;
+        core.set_reg(10, addr);
+        core.set_reg(11, 0x1234_5678);
+        let offset = (addr - core.mem.ram_base()) as usize;
+        core.mem.ram_slice_mut().unwrap()[offset + 4..offset + 8]
+            .copy_from_slice(&0xcafe_f00d_u32.to_le_bytes());
+        core.run_for(2);
+        let ram = core.mem.ram_slice().unwrap();
+        assert_eq!(ram[offset..offset + 4], 0x1234_5678_u32.to_le_bytes());
+        assert_eq!(core.reg(12), 0xcafe_f00d);
+
+        assert!(Mem::sparse(0x1000).ram_slice().is_none());
+    }
+
+    #[test]
+    fn current_instruction_decodes_without_executing() {
+        // add a2, a0, a1
+        let mut core = core_with(&[0x00b5_0633]);
+        core.set_reg(10, 1);
+        let pc = core.pc;
+        let inst = core.current_instruction().unwrap();
+        assert_eq!(inst.pc, pc);
+        assert_eq!(inst.inst, 0x00b5_0633);
+        assert_eq!(inst.opcode, 0b0110011);
+        assert_eq!((inst.rd, inst.rs1, inst.rs2), (12, 10, 11));
+        assert_eq!((inst.funct3, inst.funct7), (0, 0));
+        assert_eq!(inst.text, "add a2, a0, a1");
+        assert_eq!((core.pc, core.reg(12)), (pc, 0));
+
+        core.set_pc(0);
+        assert_eq!(
+            core.current_instruction(),
+            Err(Exception::InstructionAccessFault)
+        );
+        assert_eq!(core.csrs[csr::MCAUSE as usize], 0);
+    }
+
+    #[test]
+    fn load_and_run_reports_a_flat_image() {
+        // li a0, 1; li a1, 2; li a7, 64; ebreak
+        let program: [u32; 4] = [0x0010_0513, 0x0020_0593, 0x0400_0893, 0x0010_0073];
+        let path = std::env::temp_dir().join(format!("riscv-emu-{}.bin", std::process::id()));
+        std::fs::write(
+            &path,
+            program
+                .iter()
+                .flat_map(|w| w.to_le_bytes())
+                .collect::<Vec<_>>(),
+        )
+        .unwrap();
+        let mut core = Core::new();
+        let report = core.load_and_run(&path, 100);
+        let short = Core::new().load_and_run(&path, 2);
+        std::fs::remove_file(&path).unwrap();
+
+        let report = report.unwrap();
+        assert_eq!(report.outcome, Some(StepOutcome::Ebreak));
+        assert_eq!(report.instructions, 4);
+        assert_eq!(core.reg(11), 2);
+        let short = short.unwrap();
+        assert_eq!(short.outcome, None);
+        assert_eq!(short.instructions, 2);
+
+        assert!(matches!(
+            Core::new().load_and_run(&path, 100),
+            Err(LoadError::Io(_))
+        ));
+    }
+
+    #[test]
+    fn reg_diff_lists_what_one_instruction_changed() {
+        // li a0, 5 on one core and j 8 on the other, after a shared nop
+        let mut ours = core_with(&[0x13, 0x0050_0513]);
+        let mut theirs = core_with(&[0x13, 0x0080_006f]);
+        let base = ours.pc;
+        ours.step();
+        theirs.step();
+        assert!(ours.reg_diff(&theirs).is_empty());
+
+        ours.step();
+        theirs.step();
+        let diffs = ours.reg_diff(&theirs);
+        assert_eq!(
+            diffs,
+            [
+                Diff {
+                    field: StateField::Pc,
+                    ours: base + 8,
+                    theirs: base + 12,
+                },
+                Diff {
+                    field: StateField::Reg(10),
+                    ours: 5,
+                    theirs: 0,
+                },
+            ]
+        );
+        assert_eq!(diffs[1].to_string(), "x10 (a0): 00000005 != 00000000");
+    }
+
+    /// Runs the `a2 <- a0 op a1` instruction `inst` on each `(a0, a1)` pair
+    /// and checks `a2` against the expected result.
+    fn check_op(inst: u32, cases: &[(u32, u32, u32)]) {
+        for &(a, b, expected) in cases {
+            let mut core = core_with(&[inst]);
+            core.set_reg(10, a);
+            core.set_reg(11, b);
+            core.step();
+            assert_eq!(
+                core.reg(12),
+                expected,
+                "{} with {:#x}, {:#x}",
+                disassemble(inst, 0),
+                a,
+                b
+            );
+        }
+    }
+
+    const MIN: u32 = i32::MIN as u32;
+    const NEG1: u32 = u32::MAX;
+
+    #[test]
+    fn mul_keeps_the_low_half() {
+        // mul a2, a0, a1
+        check_op(
+            0x02b5_0633,
+            &[
+                (MIN, NEG1, MIN),
+                (NEG1, NEG1, 1),
+                (0x1_0000, 0x1_0000, 0),
+                (7, -3i32 as u32, -21i32 as u32),
+            ],
+        );
+    }
+
+    #[test]
+    fn mulh_variants_keep_the_high_half() {
+        // mulh a2, a0, a1
+        check_op(
+            0x02b5_1633,
+            &[(MIN, MIN, 0x4000_0000), (NEG1, NEG1, 0), (MIN, 1, NEG1)],
+        );
+        // mulhsu a2, a0, a1
+        check_op(
+            0x02b5_2633,
+            &[
+                (NEG1, NEG1, NEG1),
+                (MIN, NEG1, MIN),
+                (0x7fff_ffff, NEG1, 0x7fff_fffe),
+            ],
+        );
+        // mulhu a2, a0, a1
+        check_op(
+            0x02b5_3633,
+            &[(NEG1, NEG1, 0xffff_fffe), (MIN, 2, 1), (MIN, 1, 0)],
+        );
+    }
+
+    #[test]
+    fn div_and_divu_follow_the_spec_edge_cases() {
+        // div a2, a0, a1
+        check_op(
+            0x02b5_4633,
+            &[
+                (5, 0, NEG1),
+                (MIN, 0, NEG1),
+                (MIN, NEG1, MIN),
+                (-7i32 as u32, 2, -3i32 as u32),
+            ],
+        );
+        // divu a2, a0, a1
+        check_op(
+            0x02b5_5633,
+            &[(5, 0, NEG1), (NEG1, 2, 0x7fff_ffff), (MIN, NEG1, 0)],
+        );
+    }
+
+    #[test]
+    fn rem_and_remu_follow_the_spec_edge_cases() {
+        // rem a2, a0, a1
+        check_op(
+            0x02b5_6633,
+            &[
+                (5, 0, 5),
+                (MIN, 0, MIN),
+                (MIN, NEG1, 0),
+                (-7i32 as u32, 2, NEG1),
+            ],
+        );
+        // remu a2, a0, a1
+        check_op(0x02b5_7633, &[(5, 0, 5), (NEG1, 10, 5), (MIN, NEG1, MIN)]);
+    }
+
+    /// Runs `program` with `a0` pointing at a word holding 0x55 and `a1` set
+    /// to 0x77, returning the core and the word's address.
+    fn run_atomics(program: &[u32]) -> (Core, u32) {
+        let mut core = core_with(program);
+        let addr = core.mem.ram_base() + 0x100;
+        core.mem.sw(addr, 0x55);
+        core.set_reg(10, addr);
+        core.set_reg(11, 0x77);
+        core.run_for(program.len());
+        (core, addr)
+    }
+
+    #[test]
+    fn sc_succeeds_on_a_held_reservation() {
+        // lr.w a2, (a0); sc.w a3, a1, (a0)
+        let (mut core, addr) = run_atomics(&[0x1005_262f, 0x18b5_26af]);
+        assert_eq!(core.reg(12), 0x55);
+        assert_eq!(core.reg(13), 0);
+        assert_eq!(core.mem.lw(addr), 0x77);
+        assert_eq!(core.reservation, None);
+    }
+
+    #[test]
+    fn sc_fails_after_an_intervening_store() {
+        // lr.w a2, (a0); sw zero, 0(a0); sc.w a3, a1, (a0)
+        let (mut core, addr) = run_atomics(&[0x1005_262f, 0x0005_2023, 0x18b5_26af]);
+        assert_eq!(core.reg(12), 0x55);
+        assert_eq!(core.reg(13), 1);
+        assert_eq!(core.mem.lw(addr), 0);
+
+        // An sc with no lr before it fails too
+        let (mut core, addr) = run_atomics(&[0x18b5_26af]);
+        assert_eq!(core.reg(13), 1);
+        assert_eq!(core.mem.lw(addr), 0x55);
+    }
+
+    #[test]
+    fn registers_by_name_round_trip() {
+        let mut core = Core::new();
+        for (i, &name) in REG_NAMES.iter().enumerate().skip(1) {
+            let val = 0x100 * i as u32 + 1;
+            assert!(core.write_reg(&format!("x{}", i), val));
+            assert_eq!(core.read_reg(name), Some(val));
+            assert!(core.write_reg(name, !val));
+            assert_eq!(core.read_reg(&format!("x{}", i)), Some(!val));
+            assert_eq!(core.reg(i), !val);
+        }
+        assert!(core.write_reg("fp", 0x8888));
+        assert_eq!(core.read_reg("s0"), Some(0x8888));
+        assert_eq!(core.read_reg("x8"), Some(0x8888));
+
+        // Writes to x0 are accepted and dropped
+        assert!(core.write_reg("x0", 5));
+        assert!(core.write_reg("zero", 5));
+        assert_eq!(core.read_reg("zero"), Some(0));
+        assert_eq!(core.read_reg("x0"), Some(0));
+
+        for bad in ["x32", "x05", "x", "sp1", ""] {
+            assert_eq!(core.read_reg(bad), None);
+            assert!(!core.write_reg(bad, 1));
+        }
+
+        core.csrs[csr::MSCRATCH as usize] = 0x1234;
+        assert_eq!(core.csr_by_name("mscratch"), Some(0x1234));
+        assert_eq!(core.csr_by_name("mhartid"), Some(0));
+        assert_eq!(core.csr_by_name("nonsense"), None);
+    }
+
+    #[test]
+    fn restore_returns_to_the_snapshot() {
+        // 1: addi a0, a0, 1; sw a0, 0(a1); j 1b
+        let mut core = core_with(&[0x0015_0513, 0x00a5_a023, 0xff9f_f06f]);
+        let addr = core.mem.ram_base() + 0x100;
+        core.set_reg(11, addr);
+        core.run_for(5);
+        let snapshot = core.snapshot();
+        let (pc, count) = (core.pc, core.reg(10));
+        assert_eq!(core.mem.lw(addr), 2);
+
+        core.run_for(7);
+        let later = core.snapshot();
+        assert_ne!(later, snapshot);
+        assert_eq!(core.mem.lw(addr), 4);
+
+        core.restore(&snapshot);
+        assert_eq!(core.snapshot(), snapshot);
+        assert_eq!((core.pc, core.reg(10)), (pc, count));
+        assert_eq!(core.mem.lw(addr), 2);
+        // And it carries on just as it did the first time
+        core.run_for(7);
+        assert_eq!(core.snapshot(), later);
+    }
+
+    #[test]
+    fn read_mem_and_write_mem_round_trip() {
+        let mut core = Core::new();
+        let ram = core.mem.ram_base();
+        let data: Vec<u8> = (0..=255).collect();
+        core.write_mem(ram + 3, &data).unwrap();
+        let mut buf = vec![0; data.len() + 2];
+        core.read_mem(ram + 2, &mut buf).unwrap();
+        assert_eq!(buf[0], 0);
+        assert_eq!(buf[1..=data.len()], data[..]);
+        assert_eq!(buf[data.len() + 1], 0);
+
+        // Right up to the last byte of RAM, but not past it
+        let end = ram + core.mem.ram_size() as u32;
+        core.write_mem(end - 4, &[1, 2, 3, 4]).unwrap();
+        let mut buf = [0; 4];
+        core.read_mem(end - 4, &mut buf).unwrap();
+        assert_eq!(buf, [1, 2, 3, 4]);
+        assert_eq!(core.write_mem(end - 2, &[9; 4]), Err(AccessFault));
+        assert_eq!(core.read_mem(end - 2, &mut buf), Err(AccessFault));
+        core.read_mem(end - 4, &mut buf).unwrap();
+        assert_eq!(buf, [1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn breakpoint_on_a_symbol_stops_at_its_entry() {
+        // _start: call func; ebreak; func: li a0, 7; ret
+        let base = crate::mem::RAM_BASE;
+        let program = [0x0080_00ef, 0x0010_0073, 0x0070_0513, 0x0000_8067];
+        let elf = elf_with(
+            base,
+            &program,
+            base,
+            &[("_start", base), ("func", base + 8)],
+        );
+        let mut core = Core::new();
+        core.load_elf(&elf).unwrap();
+        assert_eq!(core.set_breakpoint_on_symbol("func"), Ok(base + 8));
+        assert_eq!(core.set_breakpoint_on_symbol("missing"), Err(()));
+        assert_eq!(core.run(), StepOutcome::Breakpoint(base + 8));
+        assert_eq!(core.pc, base + 8);
+        assert_eq!(core.reg(1), base + 4);
+        assert_eq!(core.reg(10), 0);
+    }
+
+    #[test]
+    fn addi_with_bit_30_set_is_an_immediate_not_subi() {
+        // addi a0, a0, 1024 and addi a0, a0, -1024, both with funct7
+        // bit 5 set as in SUB
+        let mut core = core_with(&[0x4005_0513, 0xc005_0513, 0xc005_0513]);
+        core.set_reg(10, 100);
+        core.step();
+        assert_eq!(core.reg(10), 1124);
+        core.step();
+        core.step();
+        assert_eq!(core.reg(10) as i32, 1124 - 2048);
+        assert_eq!(core.csrs[csr::MCAUSE as usize], 0);
+    }
+
+    #[test]
+    fn c_addi_runs_and_moves_pc_by_2() {
+        // c.addi ra, 1; c.nop
+        let mut core = core_with(&[0x0001_0085]);
+        core.config.compressed = true;
+        let pc = core.pc;
+        assert_eq!(core.step(), StepOutcome::Continue);
+        assert_eq!(core.reg(1), 1);
+        assert_eq!(core.pc, pc + 2);
+    }
+
+    #[test]
+    fn jumps_to_2_byte_targets_need_compressed() {
+        // beq zero, zero, 6 and j 6
+        for inst in [0x0000_0363, 0x0060_006f] {
+            let mut core = core_with(&[inst]);
+            core.config.compressed = true;
+            let target = core.pc + 6;
+            core.step();
+            assert_eq!(core.pc, target);
+            assert_eq!(core.csrs[csr::MCAUSE as usize], 0);
+
+            let mut core = core_with(&[inst]);
+            core.csrs[csr::MTVEC as usize] = 0x8000_1000;
+            core.csrs[csr::MCAUSE as usize] = u32::MAX;
+            core.step();
+            assert_eq!(core.pc, 0x8000_1000);
+            assert_eq!(core.csrs[csr::MCAUSE as usize], 0);
+            assert_eq!(core.csrs[csr::MTVAL as usize], target);
+        }
+    }
+
+    #[test]
+    fn odd_targets_fault_even_with_compressed() {
+        // Branch and jump offsets are even and jalr clears bit 0, so an odd
+        // target only comes from an odd pc
+        let mut core = core_with(&[0x13]);
+        core.config.compressed = true;
+        let target = core.pc + 3;
+        core.csrs[csr::MCAUSE as usize] = u32::MAX;
+        assert!(core.check_target(target).is_some());
+        assert_eq!(core.csrs[csr::MCAUSE as usize], 0);
+        assert_eq!(core.csrs[csr::MTVAL as usize], target);
+
+        core.csrs[csr::MCAUSE as usize] = u32::MAX;
+        core.set_pc(target);
+        core.step();
+        assert_eq!(core.csrs[csr::MCAUSE as usize], 0);
+        assert_eq!(core.csrs[csr::MTVAL as usize], target);
+    }
+
+    #[test]
+    fn cbo_zero_clears_the_whole_aligned_block() {
+        // cbo.zero (a0)
+        let inst = 0x0045_200f;
+        assert_eq!(disassemble(inst, 0), "cbo.zero (a0)");
+        let mut core = core_with(&[inst]);
+        let block = core.mem.ram_base() + 0x1000;
+        core.write_mem(block - 4, &[0xaa; 72]).unwrap();
+        core.set_reg(10, block + 0x25);
+        core.step();
+        let mut buf = [0; 72];
+        core.read_mem(block - 4, &mut buf).unwrap();
+        assert_eq!(buf[..4], [0xaa; 4]);
+        assert_eq!(buf[4..68], [0; 64]);
+        assert_eq!(buf[68..], [0xaa; 4]);
+
+        let mut core = core_with(&[inst]);
+        core.config.zicboz = false;
+        core.write_mem(block, &[0xaa; 64]).unwrap();
+        core.set_reg(10, block);
+        core.step();
+        assert_eq!(core.csrs[csr::MCAUSE as usize], 2);
+        assert_eq!(core.mem.lb(block + 63), 0xaa);
+    }
+
+    #[test]
+    fn reset_returns_to_the_elf_entry() {
+        // Two nops, then the entry: li a0, 7; li a0, 8
+        let base = crate::mem::RAM_BASE + 0x2000;
+        let program = [0x13, 0x13, 0x0070_0513, 0x0080_0513];
+        let entry = base + 8;
+        let mut core = Core::new();
+        core.load_elf(&elf_with(base, &program, entry, &[]))
+            .unwrap();
+        assert_eq!((core.pc, core.reset_pc), (entry, entry));
+        core.step();
+        core.step();
+        assert_eq!(core.reg(10), 8);
+
+        core.reset();
+        assert_eq!(core.pc, entry);
+        assert_eq!(core.reg(10), 0);
+        core.step();
+        assert_eq!(core.reg(10), 7);
+    }
+
+    #[test]
+    fn panic_dump_writes_the_state_before_unwinding() {
+        // li a0, 5; li a1, 9; sw a1, 0(a2)
+        let mut core = core_with(&[0x0050_0513, 0x0090_0593, 0x00b6_2023]);
+        core.config.panic_dump = true;
+        let dump = Captured::default();
+        core.set_panic_dump_sink(Box::new(dump.clone()));
+        core.set_reg(12, core.pc + 0x100);
+        core.set_mem_hook(|_, _, _, _| panic!("invariant broken"));
+        let store = core.pc + 8;
+        core.step();
+        core.step();
+        let result = panic::catch_unwind(AssertUnwindSafe(|| core.step()));
+        assert!(result.is_err());
+
+        let text = dump.text();
+        assert!(text.starts_with(&format!("pc: {:08x}, priv: Machine", store)));
+        assert!(text.contains(" x10   a0: 00000005"));
+        assert!(text.contains(" x11   a1: 00000009"));
+        let recent = text.split("Recent instructions:\n").nth(1).unwrap();
+        let lines: Vec<&str> = recent.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert!(lines[0].ends_with("addi a0, zero, 5"));
+        assert!(lines[2].starts_with(&format!(" {:08x}: 00b62023", store)));
+    }
+
+    #[test]
+    fn ecall_handlers_service_their_number() {
+        // li a0, 1; li a1, 2; li a2, 3; li a7, 0x123; ecall
+        let program = [0x0010_0513, 0x0020_0593, 0x0030_0613, 0x1230_0893, 0x73];
+        let seen = Rc::new(RefCell::new(None));
+        let mut core = core_with(&program);
+        let handler_seen = seen.clone();
+        core.register_ecall_handler(0x123, move |_, args| {
+            *handler_seen.borrow_mut() = Some(args);
+            args.regs[0] + args.regs[1] + args.regs[2]
+        });
+        core.csrs[csr::MCAUSE as usize] = u32::MAX;
+        assert_eq!(core.run_for(5), StepOutcome::Continue);
+        let args = seen.borrow().unwrap();
+        assert_eq!(args.regs[..3], [1, 2, 3]);
+        assert_eq!(args.number(), 0x123);
+        assert_eq!(core.reg(10), 6);
+        assert_eq!(core.pc, core.reset_pc + 20);
+        assert_eq!(core.priv_mode, PrivMode::Machine);
+        assert_eq!(core.csrs[csr::MCAUSE as usize], u32::MAX);
+
+        // Any other number still traps
+        core.reset();
+        core.register_ecall_handler(0x124, |_, _| unreachable!());
+        core.unregister_ecall_handler(0x123);
+        core.csrs[csr::MTVEC as usize] = 0x8000_1000;
+        core.run_for(5);
+        assert_eq!(core.csrs[csr::MCAUSE as usize], 11);
+        assert_eq!(core.pc, 0x8000_1000);
+        assert_eq!(core.reg(10), 1);
+    }
+
+    #[test]
+    fn step_hooks_run_once_per_step() {
+        // 1: addi a0, a0, 1; sw a0, 0(a1); j 1b
+        let mut core = core_with(&[0x0015_0513, 0x00a5_a023, 0xff9f_f06f]);
+        core.set_reg(11, core.pc + 0x100);
+        let pre_pcs = Rc::new(RefCell::new(Vec::new()));
+        let post = Rc::new(Cell::new(0));
+        let seen = pre_pcs.clone();
+        core.set_pre_hook(move |core, inst| seen.borrow_mut().push((core.pc, inst)));
+        let count = post.clone();
+        core.set_post_hook(move |_, _| count.set(count.get() + 1));
+        let mut expected = Vec::new();
+        for _ in 0..10 {
+            expected.push((core.pc, core.mem.lw(core.pc)));
+            core.step();
+        }
+        assert_eq!(*pre_pcs.borrow(), expected);
+        assert_eq!(post.get(), 10);
+
+        core.clear_step_hooks();
+        core.step();
+        assert_eq!((pre_pcs.borrow().len(), post.get()), (10, 10));
+    }
+
+    #[test]
+    fn hooks_see_memory_then_rd_then_pc_then_counters() {
+        // amoadd.w a2, a1, (a0), which writes both memory and rd
+        let mut core = core_with(&[0x00b5_262f]);
+        let addr = core.pc + 0x100;
+        core.mem.sw(addr, 40);
+        core.set_reg(10, addr);
+        core.set_reg(11, 2);
+        let pc = core.pc;
+        let events = Rc::new(RefCell::new(Vec::new()));
+        // What each hook sees: memory, rd, pc and minstret
+        let state = move |core: &Core| {
+            let mut word = [0; 4];
+            core.read_mem(addr, &mut word).unwrap();
+            (
+                u32::from_le_bytes(word),
+                core.reg(12),
+                core.pc,
+                core.csrs[csr::MINSTRET as usize],
+            )
+        };
+        let seen = events.clone();
+        core.set_mem_hook(move |core, _, _, _| seen.borrow_mut().push(("mem", state(core))));
+        let seen = events.clone();
+        core.watch_reg(12, move |core, _| {
+            seen.borrow_mut().push(("rd", state(core)))
+        });
+        core.step();
+        assert_eq!(
+            *events.borrow(),
+            [("mem", (42, 0, pc, 0)), ("rd", (42, 40, pc, 0))]
+        );
+        assert_eq!((core.pc, core.csrs[csr::MINSTRET as usize]), (pc + 4, 1));
+    }
+
+    #[test]
+    fn run_callback_stops_when_the_callback_says() {
+        // 1: addi a0, a0, 1; sw a0, 0(a1); j 1b
+        let mut core = core_with(&[0x0015_0513, 0x00a5_a023, 0xff9f_f06f]);
+        core.set_reg(11, core.pc + 0x100);
+        let mut calls = 0;
+        let outcome = core.run_callback(100, |core| {
+            calls += 1;
+            core.reg(10) != 3
+        });
+        assert_eq!(outcome, None);
+        assert_eq!(calls, 7);
+        assert_eq!(core.pc, core.reset_pc + 4);
+        assert_eq!(core.cycle_count, 7);
+
+        // Or runs out of budget
+        let mut calls = 0;
+        let outcome = core.run_callback(5, |_| {
+            calls += 1;
+            true
+        });
+        assert_eq!((outcome, calls), (None, 5));
+        assert_eq!(core.cycle_count, 12);
+    }
+
+    #[test]
+    fn hot_blocks_rank_the_loop_first() {
+        // li a0, 0; li t0, 10; 1: addi a0, a0, 1; addi t0, t0, -1;
+        // bnez t0, 1b; ebreak
+        let mut core = core_with(&[
+            0x0000_0513,
+            0x00a0_0293,
+            0x0015_0513,
+            0xfff2_8293,
+            0xfe02_9ce3,
+            0x0010_0073,
+        ]);
+        core.config.profile_blocks = true;
+        let base = core.pc;
+        assert_eq!(core.run(), StepOutcome::Ebreak);
+        // The first time round falls into the loop, so it's part of the
+        // block at the entry point
+        assert_eq!(core.hot_blocks(1), [(base + 8, 9)]);
+        let blocks = core.hot_blocks(10);
+        assert_eq!(blocks.len(), 3);
+        assert!(blocks[1..].contains(&(base, 1)));
+        assert!(blocks[1..].contains(&(base + 20, 1)));
+    }
+
+    #[test]
+    fn recent_instructions_keeps_the_last_n_in_order() {
+        // 1: addi a0, a0, 1; sw a0, 0(a1); j 1b
+        let program = [0x0015_0513, 0x00a5_a023, 0xff9f_f06f];
+        let mut core = core_with(&program);
+        core.config.history_len = 8;
+        core.set_reg(11, core.pc + 0x100);
+        let mut executed = Vec::new();
+        for _ in 0..20 {
+            executed.push((core.pc, program[(core.pc - core.reset_pc) as usize / 4]));
+            core.step();
+        }
+        let recent: Vec<(u32, u32)> = core.recent_instructions().collect();
+        assert_eq!(recent, executed[12..]);
+    }
+
+    #[test]
+    fn set_reg_leaves_x0_zero() {
+        // add a0, zero, zero
+        let mut core = core_with(&[0x0000_0533]);
+        core.set_reg(0, 5);
+        core.set_reg64(0, u64::MAX);
+        assert_eq!(core.reg(0), 0);
+        assert_eq!(core.regs()[0], 0);
+        core.set_reg(10, 1);
+        core.step();
+        assert_eq!(core.reg(10), 0);
+    }
+
+    #[test]
+    fn trap_on_csr_stops_before_the_access() {
+        // li a0, 5; csrw satp, a0; csrr a1, satp; ebreak
+        let mut core = core_with(&[0x0050_0513, 0x1805_1073, 0x1800_25f3, 0x0010_0073]);
+        core.trap_on_csr(csr::SATP);
+        let write = core.pc + 4;
+        assert_eq!(core.run(), StepOutcome::Break(write));
+        assert_eq!(core.pc, write);
+        assert_eq!(core.csrs[csr::SATP as usize], 0);
+        assert_eq!(core.cycle_count, 1);
+
+        // Carrying on does the write, then stops at the read
+        assert_eq!(core.run(), StepOutcome::Break(write + 4));
+        assert_eq!(core.csrs[csr::SATP as usize], 5);
+        assert_eq!(core.reg(11), 0);
+        core.clear_csr_trap(csr::SATP);
+        assert_eq!(core.run(), StepOutcome::Ebreak);
+        assert_eq!(core.reg(11), 5);
+    }
+
+    #[test]
+    fn mstatush_mbe_is_fixed_by_the_config() {
+        // li t0, SBE | MBE; csrw mstatush, t0; csrr a2, mstatush;
+        // sw a1, 0(a0); csrw mstatush, zero; csrr a3, mstatush
+        let program = [
+            0x0300_0293,
+            0x3102_9073,
+            0x3100_2673,
+            0x00b5_2023,
+            0x3100_1073,
+            0x3100_26f3,
+        ];
+        for big_endian in [false, true] {
+            let mut core = core_with(&program);
+            core.config.big_endian = big_endian;
+            let addr = core.pc + 0x100;
+            core.set_reg(10, addr);
+            core.set_reg(11, 0x1122_3344);
+            core.run_for(program.len());
+            let both = csr::MSTATUSH_SBE | csr::MSTATUSH_MBE;
+            let expected = if big_endian { both } else { 0 };
+            assert_eq!((core.reg(12), core.reg(13)), (expected, expected));
+            let mut word = [0; 4];
+            core.read_mem(addr, &mut word).unwrap();
+            let stored = if big_endian {
+                [0x11, 0x22, 0x33, 0x44]
+            } else {
+                [0x44, 0x33, 0x22, 0x11]
+            };
+            assert_eq!(word, stored);
+        }
+    }
+
+    #[test]
+    fn code_runs_from_rom_but_cant_store_to_it() {
+        // li a0, 9; auipc t0, 0; sw a0, 16(t0)
+        let program: [u32; 3] = [0x0090_0513, 0x0000_0297, 0x00a2_a823];
+        let rom: Vec<u8> = program.iter().flat_map(|w| w.to_le_bytes()).collect();
+        let mut core = Core::new();
+        core.load_raw_to_rom(0x1000, [rom, vec![0xee; 0x20]].concat());
+        core.csrs[csr::MTVEC as usize] = core.mem.ram_base();
+        core.set_pc(0x1000);
+        core.step();
+        core.step();
+        assert_eq!((core.reg(10), core.reg(5), core.pc), (9, 0x1004, 0x1008));
+        assert!(matches!(
+            core.step(),
+            StepOutcome::Trap(Exception::StoreAccessFault)
+        ));
+        assert_eq!(core.csrs[csr::MCAUSE as usize], 7);
+        assert_eq!(core.csrs[csr::MTVAL as usize], 0x1014);
+        assert_eq!(core.csrs[csr::MEPC as usize], 0x1008);
+        let mut word = [0; 4];
+        core.read_mem(0x1014, &mut word).unwrap();
+        assert_eq!(word, [0xee; 4]);
+    }
+
+    #[test]
+    fn load_image_with_base_starts_there() {
+        // li a0, 7; li a1, 2
+        let image: Vec<u8> = [0x0070_0513u32, 0x0020_0593]
+            .iter()
+            .flat_map(|w| w.to_le_bytes())
+            .collect();
+        let mut core = Core::new();
+        let base = core.mem.ram_base() + 0x4000;
+        core.load_image_with_base(base, image).unwrap();
+        assert_eq!((core.pc, core.reset_pc), (base, base));
+        assert_eq!(core.mem.lw(core.mem.ram_base()), 0);
+        core.step();
+        assert_eq!((core.reg(10), core.reg(11)), (7, 0));
+
+        // reset_to_entry moves the reset pc without touching memory
+        core.reset_to_entry(base + 4);
+        assert_eq!((core.pc, core.reg(10)), (base + 4, 0));
+        core.step();
+        assert_eq!((core.reg(10), core.reg(11)), (0, 2));
+        core.reset();
+        assert_eq!(core.pc, base + 4);
+    }
+
+    #[test]
+    fn an_image_linked_at_0x80100000_runs_there() {
+        // auipc a0, 0; li a1, 3; ebreak
+        let image: Vec<u8> = [0x0000_0517u32, 0x0030_0593, 0x0010_0073]
+            .iter()
+            .flat_map(|w| w.to_le_bytes())
+            .collect();
+        let mut core = Core::new();
+        core.load_image_with_base(0x8010_0000, image).unwrap();
+        assert_eq!(core.run(), StepOutcome::Ebreak);
+        assert_eq!(core.reg(10), 0x8010_0000);
+        assert_eq!(core.reg(11), 3);
+        assert_eq!(core.pc, 0x8010_0008);
+    }
+
+    #[test]
+    fn a_passed_stimecmp_deadline_traps_to_stvec() {
+        // nop, eight times
+        let mut core = core_with(&[0x0000_0013; 8]);
+        let ram = core.mem.ram_base();
+        core.csrs[csr::MENVCFGH as usize] = csr::MENVCFGH_STCE;
+        core.csrs[csr::MIDELEG as usize] = 1 << irq::STIP;
+        core.csrs[csr::MIE as usize] = 1 << irq::STIP;
+        core.csrs[csr::MSTATUS as usize] = csr::MSTATUS_SIE;
+        core.csrs[csr::STVEC as usize] = ram + 0x100;
+        core.csrs[csr::STIMECMP as usize] = 3;
+        core.priv_mode = PrivMode::Supervisor;
+
+        for _ in 0..3 {
+            assert_eq!(core.step(), StepOutcome::Continue);
+        }
+        assert_eq!(core.mtime(), 3);
+        assert_eq!(core.step(), StepOutcome::Interrupt(irq::STIP));
+        assert_eq!(core.pc, ram + 0x100);
+        assert_eq!(core.priv_mode, PrivMode::Supervisor);
+        assert_eq!(core.read_csr(csr::SCAUSE), 0x8000_0005);
+        assert_eq!(core.read_csr(csr::SEPC), ram + 12);
+        assert_eq!(core.read_csr(csr::MCAUSE), 0);
+    }
+
+    #[test]
+    fn stimecmp_only_fires_once_menvcfg_stce_is_set() {
+        // nop; csrw menvcfgh, t0; nop
+        let mut core = core_with(&[0x0000_0013, 0x31a2_9073, 0x0000_0013]);
+        let ram = core.mem.ram_base();
+        core.csrs[csr::MIE as usize] = 1 << irq::STIP;
+        core.csrs[csr::MSTATUS as usize] = csr::MSTATUS_MIE;
+        core.csrs[csr::MTVEC as usize] = ram + 0x100;
+        core.set_reg(5, csr::MENVCFGH_STCE);
+
+        // stimecmp is 0, so long past, but STCE is clear
+        assert_eq!(core.step(), StepOutcome::Continue);
+        assert_eq!(core.read_csr(csr::MIP) & 1 << irq::STIP, 0);
+        assert_eq!(core.step(), StepOutcome::Continue);
+        assert_ne!(core.read_csr(csr::MIP) & 1 << irq::STIP, 0);
+        assert_eq!(core.step(), StepOutcome::Interrupt(irq::STIP));
+        assert_eq!(core.pc, ram + 0x100);
+        assert_eq!(core.read_csr(csr::MCAUSE), 0x8000_0005);
+        assert_eq!(core.read_csr(csr::MEPC), ram + 8);
+    }
+
+    #[test]
+    fn an_interrupt_between_lr_and_sc_makes_the_sc_fail() {
+        // lr.w a0, (a1); sc.w a2, a3, (a1)
+        let mut core = core_with(&[0x1005_a52f, 0x18d5_a62f]);
+        let ram = core.mem.ram_base();
+        // mret
+        core.mem.sw(ram + 0x100, 0x3020_0073);
+        core.csrs[csr::MTVEC as usize] = ram + 0x100;
+        core.csrs[csr::MIE as usize] = 1 << irq::MEIP;
+        core.csrs[csr::MSTATUS as usize] = csr::MSTATUS_MIE;
+        core.set_reg(11, ram + 0x200);
+        core.set_reg(13, 0xdead_beef);
+        core.mem.sw(ram + 0x200, 7);
+
+        assert_eq!(core.step(), StepOutcome::Continue);
+        assert!(core.reservation.is_some());
+        core.irq.raise(irq::MEIP);
+        assert_eq!(core.step(), StepOutcome::Interrupt(irq::MEIP));
+        assert_eq!(core.reservation, None);
+        core.irq.lower(irq::MEIP);
+        assert_eq!(core.step(), StepOutcome::Continue);
+        assert_eq!(core.pc, ram + 4);
+
+        assert_eq!(core.step(), StepOutcome::Continue);
+        assert_eq!(core.reg(12), 1);
+        assert_eq!(core.mem.lw(ram + 0x200), 7);
+    }
+
+    #[test]
+    fn reading_an_unconfigured_uart_warns() {
+        use crate::uart::{Uart, UART_BASE, UART_SIZE};
+
+        // lbu a0, 5(a1); sb zero, 1(a1); lbu a0, 5(a1)
+        let mut core = core_with(&[0x0055_c503, 0x0005_80a3, 0x0055_c503]);
+        core.config.warn_uninit_mmio = true;
+        let warnings = Captured::default();
+        core.set_warning_sink(Box::new(warnings.clone()));
+        let uart = Uart::new(Box::new(io::sink()));
+        core.mem.map_device(UART_BASE, UART_SIZE, Box::new(uart));
+        core.set_reg(11, UART_BASE);
+        let pc = core.pc;
+
+        core.step();
+        assert_eq!(core.stats.uninit_mmio_reads, 1);
+        assert_eq!(
+            warnings.text(),
+            format!(
+                "warning: pc {:08x}: read from device register {:08x} before the device was set up\n",
+                pc,
+                UART_BASE + 5
+            )
+        );
+
+        // Writing IER configures it
+        core.step();
+        core.step();
+        assert_eq!(core.stats.uninit_mmio_reads, 1);
+        assert_eq!(warnings.text().lines().count(), 1);
     }
 }
@@ -0,0 +1,239 @@
+// Unprivileged floating-point CSRs
+pub const FFLAGS: u32 = 0x001;
+pub const FRM: u32 = 0x002;
+pub const FCSR: u32 = 0x003;
+
+// Unprivileged counters
+pub const CYCLE: u32 = 0xC00;
+pub const TIME: u32 = 0xC01;
+pub const INSTRET: u32 = 0xC02;
+pub const CYCLEH: u32 = 0xC80;
+pub const TIMEH: u32 = 0xC81;
+pub const INSTRETH: u32 = 0xC82;
+
+// Supervisor trap setup
+pub const SSTATUS: u32 = 0x100;
+pub const SIE: u32 = 0x104;
+pub const STVEC: u32 = 0x105;
+pub const SCOUNTEREN: u32 = 0x106;
+
+// Supervisor configuration
+pub const SENVCFG: u32 = 0x10A;
+
+// Supervisor trap handling
+pub const SSCRATCH: u32 = 0x140;
+pub const SEPC: u32 = 0x141;
+pub const SCAUSE: u32 = 0x142;
+pub const STVAL: u32 = 0x143;
+pub const SIP: u32 = 0x144;
+pub const STIMECMP: u32 = 0x14D;
+pub const STIMECMPH: u32 = 0x15D;
+
+// Machine information registers
+pub const MHARTID: u32 = 0xF14;
+pub const MCONFIGPTR: u32 = 0xF15;
+
+// Supervisor protection and translation
+pub const SATP: u32 = 0x180;
+
+// Machine trap setup
+pub const MSTATUS: u32 = 0x300;
+pub const MISA: u32 = 0x301;
+pub const MEDELEG: u32 = 0x302;
+pub const MIDELEG: u32 = 0x303;
+pub const MIE: u32 = 0x304;
+pub const MTVEC: u32 = 0x305;
+pub const MCOUNTEREN: u32 = 0x306;
+pub const MSTATUSH: u32 = 0x310;
+
+// Machine configuration
+pub const MENVCFG: u32 = 0x30A;
+pub const MENVCFGH: u32 = 0x31A;
+
+// Machine memory protection
+pub const PMPCFG0: u32 = 0x3A0;
+pub const PMPCFG3: u32 = 0x3A3;
+pub const PMPADDR0: u32 = 0x3B0;
+pub const PMPADDR15: u32 = 0x3BF;
+
+// Machine trap handling
+pub const MSCRATCH: u32 = 0x340;
+pub const MEPC: u32 = 0x341;
+pub const MCAUSE: u32 = 0x342;
+pub const MTVAL: u32 = 0x343;
+pub const MIP: u32 = 0x344;
+
+// Machine counters
+pub const MCYCLE: u32 = 0xB00;
+pub const MINSTRET: u32 = 0xB02;
+pub const MCYCLEH: u32 = 0xB80;
+pub const MINSTRETH: u32 = 0xB82;
+
+// misa fields
+/// MXL = 1, a 32-bit hart
+pub const MISA_MXL_32: u32 = 1 << 30;
+/// MXL = 2, a 64-bit hart, in the top bits of a 64-bit misa
+pub const MISA_MXL_64: u64 = 2 << 62;
+/// The bit for extension `letter`, such as `'M'`
+pub const fn misa_ext(letter: char) -> u32 {
+    1 << (letter as u32 - 'A' as u32)
+}
+
+// mstatus fields
+pub const MSTATUS_SIE: u32 = 1 << 1;
+pub const MSTATUS_MIE: u32 = 1 << 3;
+pub const MSTATUS_SPIE: u32 = 1 << 5;
+pub const MSTATUS_UBE: u32 = 1 << 6;
+pub const MSTATUS_MPIE: u32 = 1 << 7;
+pub const MSTATUS_SPP: u32 = 1 << 8;
+pub const MSTATUS_MPP_SHIFT: u32 = 11;
+pub const MSTATUS_MPP: u32 = 0b11 << MSTATUS_MPP_SHIFT;
+/// Floating-point state: off, initial, clean or dirty
+pub const MSTATUS_FS: u32 = 0b11 << 13;
+/// Loads and stores in M mode translate and check permissions as if in MPP
+pub const MSTATUS_MPRV: u32 = 1 << 17;
+pub const MSTATUS_SUM: u32 = 1 << 18;
+pub const MSTATUS_MXR: u32 = 1 << 19;
+/// Traps S-mode accesses to satp and SFENCE.VMA
+pub const MSTATUS_TVM: u32 = 1 << 20;
+pub const MSTATUS_TW: u32 = 1 << 21;
+/// Read-only, set when FS is dirty
+pub const MSTATUS_SD: u32 = 1 << 31;
+/// The fields of mstatus software can write. UBE is fixed by the config and
+/// SD is computed from FS.
+pub const MSTATUS_WRITABLE: u32 = MSTATUS_SIE
+    | MSTATUS_MIE
+    | MSTATUS_SPIE
+    | MSTATUS_MPIE
+    | MSTATUS_SPP
+    | MSTATUS_MPP
+    | MSTATUS_FS
+    | MSTATUS_MPRV
+    | MSTATUS_SUM
+    | MSTATUS_MXR
+    | MSTATUS_TVM
+    | MSTATUS_TW;
+/// The parts of mstatus visible through sstatus
+pub const SSTATUS_MASK: u32 = MSTATUS_SIE
+    | MSTATUS_SPIE
+    | MSTATUS_UBE
+    | MSTATUS_SPP
+    | MSTATUS_FS
+    | MSTATUS_SUM
+    | MSTATUS_MXR
+    | MSTATUS_SD;
+
+// fcsr fields
+pub const FCSR_FFLAGS: u32 = 0x1f;
+pub const FCSR_FRM_SHIFT: u32 = 5;
+pub const FCSR_FRM: u32 = 0b111 << FCSR_FRM_SHIFT;
+
+// pmpcfg fields, repeated in each byte for one entry
+pub const PMP_R: u32 = 1 << 0;
+pub const PMP_W: u32 = 1 << 1;
+pub const PMP_X: u32 = 1 << 2;
+/// How the entry matches addresses: off, TOR, NA4 or NAPOT
+pub const PMP_A_SHIFT: u32 = 3;
+pub const PMP_A: u32 = 0b11 << PMP_A_SHIFT;
+/// Locks the entry until reset, and applies it to M mode too
+pub const PMP_L: u32 = 1 << 7;
+
+// mstatush fields
+pub const MSTATUSH_SBE: u32 = 1 << 4;
+pub const MSTATUSH_MBE: u32 = 1 << 5;
+
+// menvcfg and senvcfg fields
+/// Low bit of the two-bit CBIE field, which both of its legal non-zero
+/// settings have set
+pub const ENVCFG_CBIE: u32 = 1 << 4;
+pub const ENVCFG_CBCFE: u32 = 1 << 6;
+pub const ENVCFG_CBZE: u32 = 1 << 7;
+
+// menvcfgh fields
+pub const MENVCFGH_STCE: u32 = 1 << 31;
+
+/// The CSRs above by their assembler names
+const NAMES: [(&str, u32); 63] = [
+    ("fflags", FFLAGS),
+    ("frm", FRM),
+    ("fcsr", FCSR),
+    ("cycle", CYCLE),
+    ("time", TIME),
+    ("instret", INSTRET),
+    ("cycleh", CYCLEH),
+    ("timeh", TIMEH),
+    ("instreth", INSTRETH),
+    ("sstatus", SSTATUS),
+    ("sie", SIE),
+    ("stvec", STVEC),
+    ("scounteren", SCOUNTEREN),
+    ("senvcfg", SENVCFG),
+    ("sscratch", SSCRATCH),
+    ("sepc", SEPC),
+    ("scause", SCAUSE),
+    ("stval", STVAL),
+    ("sip", SIP),
+    ("stimecmp", STIMECMP),
+    ("stimecmph", STIMECMPH),
+    ("mhartid", MHARTID),
+    ("mconfigptr", MCONFIGPTR),
+    ("satp", SATP),
+    ("mstatus", MSTATUS),
+    ("misa", MISA),
+    ("medeleg", MEDELEG),
+    ("mideleg", MIDELEG),
+    ("mie", MIE),
+    ("mtvec", MTVEC),
+    ("mcounteren", MCOUNTEREN),
+    ("mstatush", MSTATUSH),
+    ("menvcfg", MENVCFG),
+    ("menvcfgh", MENVCFGH),
+    ("pmpcfg0", PMPCFG0),
+    ("pmpcfg1", PMPCFG0 + 1),
+    ("pmpcfg2", PMPCFG0 + 2),
+    ("pmpcfg3", PMPCFG3),
+    ("pmpaddr0", PMPADDR0),
+    ("pmpaddr1", PMPADDR0 + 1),
+    ("pmpaddr2", PMPADDR0 + 2),
+    ("pmpaddr3", PMPADDR0 + 3),
+    ("pmpaddr4", PMPADDR0 + 4),
+    ("pmpaddr5", PMPADDR0 + 5),
+    ("pmpaddr6", PMPADDR0 + 6),
+    ("pmpaddr7", PMPADDR0 + 7),
+    ("pmpaddr8", PMPADDR0 + 8),
+    ("pmpaddr9", PMPADDR0 + 9),
+    ("pmpaddr10", PMPADDR0 + 10),
+    ("pmpaddr11", PMPADDR0 + 11),
+    ("pmpaddr12", PMPADDR0 + 12),
+    ("pmpaddr13", PMPADDR0 + 13),
+    ("pmpaddr14", PMPADDR0 + 14),
+    ("pmpaddr15", PMPADDR15),
+    ("mscratch", MSCRATCH),
+    ("mepc", MEPC),
+    ("mcause", MCAUSE),
+    ("mtval", MTVAL),
+    ("mip", MIP),
+    ("mcycle", MCYCLE),
+    ("minstret", MINSTRET),
+    ("mcycleh", MCYCLEH),
+    ("minstreth", MINSTRETH),
+];
+
+/// The address of the CSR called `name`, such as `mstatus`.
+pub fn by_name(name: &str) -> Option<u32> {
+    NAMES
+        .iter()
+        .find(|&&(csr, _)| csr == name)
+        .map(|&(_, addr)| addr)
+}
+
+/// The assembler name of the CSR at `addr`, if it's one of those above.
+pub fn name(addr: u32) -> Option<&'static str> {
+    NAMES
+        .iter()
+        .find(|&&(_, csr)| csr == addr)
+        .map(|&(name, _)| name)
+}
+
+/// Set in mcause/scause when the trap was an interrupt
+pub const CAUSE_INTERRUPT: u32 = 1 << 31;
@@ -0,0 +1,164 @@
+//! Instruction decoding, split out of execution so decoded instructions can
+//! be cached by `Config::decode_cache` and reused on the next visit.
+
+use crate::core::{read_imm_b, read_imm_i, read_imm_j, read_imm_s, read_imm_u};
+
+#[derive(Debug, Clone, Copy)]
+pub(crate) enum Opcode {
+    OpImm,
+    /// The RV64 word forms, OP-IMM-32 and OP-32
+    OpImm32,
+    Op32,
+    Lui,
+    Auipc,
+    Op,
+    Jal,
+    Jalr,
+    Branch,
+    Load,
+    Store,
+    MiscMem,
+    System,
+    Amo,
+    LoadFp,
+    StoreFp,
+    Madd,
+    Msub,
+    Nmsub,
+    Nmadd,
+    OpFp,
+}
+
+/// A 32-bit instruction split into its fields, with the immediate its
+/// format has already put together.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Decoded {
+    /// The instruction as fetched, which is 16 bits if it was compressed
+    pub(crate) raw: u32,
+    /// Its 32-bit form
+    pub(crate) inst: u32,
+    /// Size in bytes of `raw`
+    pub(crate) len: u32,
+    pub(crate) opcode: Opcode,
+    pub(crate) rd: u32,
+    pub(crate) rs1: u32,
+    pub(crate) rs2: u32,
+    pub(crate) funct3: u32,
+    pub(crate) funct7: u32,
+    /// The I, S, B, U or J immediate, whichever the opcode uses, and 0 for
+    /// the register-only formats
+    pub(crate) imm: u32,
+}
+
+/// Decodes the 32-bit instruction `inst`, which stands for `raw`. `None`
+/// if the opcode isn't one we implement, which includes any word whose low
+/// bits mark it as compressed.
+pub(crate) fn decode(raw: u32, inst: u32, len: u32) -> Option<Decoded> {
+    // Everything that isn't compressed has its low two bits set, so this
+    // also catches an all-zeros word, which is defined to be illegal
+    if inst & 0b11 != 0b11 {
+        return None;
+    }
+    let opcode = match (inst & 0b1111100) >> 2 {
+        0b00000 => Opcode::Load,
+        0b01000 => Opcode::Store,
+        0b11000 => Opcode::Branch,
+        0b11001 => Opcode::Jalr,
+        0b11011 => Opcode::Jal,
+        0b00011 => Opcode::MiscMem,
+        0b00100 => Opcode::OpImm,
+        0b00110 => Opcode::OpImm32,
+        0b01110 => Opcode::Op32,
+        0b01100 => Opcode::Op,
+        0b11100 => Opcode::System,
+        0b00101 => Opcode::Auipc,
+        0b01101 => Opcode::Lui,
+        0b01011 => Opcode::Amo,
+        0b00001 => Opcode::LoadFp,
+        0b01001 => Opcode::StoreFp,
+        0b10000 => Opcode::Madd,
+        0b10001 => Opcode::Msub,
+        0b10010 => Opcode::Nmsub,
+        0b10011 => Opcode::Nmadd,
+        0b10100 => Opcode::OpFp,
+        _ => return None,
+    };
+    let imm = match opcode {
+        Opcode::Load | Opcode::Jalr | Opcode::OpImm | Opcode::OpImm32 | Opcode::LoadFp => {
+            read_imm_i(inst)
+        }
+        Opcode::Store | Opcode::StoreFp => read_imm_s(inst),
+        Opcode::Branch => read_imm_b(inst),
+        Opcode::Jal => read_imm_j(inst),
+        Opcode::Lui | Opcode::Auipc => read_imm_u(inst),
+        _ => 0,
+    };
+    Some(Decoded {
+        raw,
+        inst,
+        len,
+        opcode,
+        rd: (inst >> 7) & 0b11111,
+        rs1: (inst >> 15) & 0b11111,
+        rs2: (inst >> 20) & 0b11111,
+        funct3: (inst >> 12) & 0b111,
+        funct7: (inst >> 25) & 0b1111111,
+        imm,
+    })
+}
+
+/// Number of slots in a `DecodeCache`
+const ENTRIES: usize = 4096;
+
+/// Decoded instructions by the physical address they were fetched from.
+/// It's direct-mapped, so a lookup is a single compare, and two hot
+/// instructions that share a slot just take turns in it.
+#[derive(Default)]
+pub(crate) struct DecodeCache {
+    /// `(address, instruction)`, allocated on first use so cores that never
+    /// turn the cache on don't pay for it
+    slots: Vec<Option<(u32, Decoded)>>,
+}
+
+fn slot(addr: u32) -> usize {
+    (addr >> 1) as usize % ENTRIES
+}
+
+impl DecodeCache {
+    pub(crate) fn get(&self, addr: u32) -> Option<Decoded> {
+        match self.slots.get(slot(addr)) {
+            Some(&Some((tag, decoded))) if tag == addr => Some(decoded),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn insert(&mut self, addr: u32, decoded: Decoded) {
+        if self.slots.is_empty() {
+            self.slots = vec![None; ENTRIES];
+        }
+        self.slots[slot(addr)] = Some((addr, decoded));
+    }
+
+    /// Drops any instruction a store of `size` bytes at `addr` may have
+    /// overwritten, which means any starting up to 3 bytes before it.
+    pub(crate) fn invalidate(&mut self, addr: u32, size: u32) {
+        if self.slots.is_empty() {
+            return;
+        }
+        let first = (addr & !1).wrapping_sub(2);
+        let starts = (addr.wrapping_sub(first) + size).div_ceil(2);
+        for i in 0..starts {
+            let start = first.wrapping_add(2 * i);
+            let slot = &mut self.slots[slot(start)];
+            if matches!(slot, Some((tag, _)) if *tag == start) {
+                *slot = None;
+            }
+        }
+    }
+
+    pub(crate) fn clear(&mut self) {
+        for slot in &mut self.slots {
+            *slot = None;
+        }
+    }
+}
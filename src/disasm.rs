@@ -0,0 +1,415 @@
+use crate::core::{read_imm_b, read_imm_i, read_imm_j, read_imm_s};
+use crate::csr;
+use crate::rvc;
+
+pub const REG_NAMES: [&str; 32] = [
+    "zero", "ra", "sp", "gp", "tp", "t0", "t1", "t2", "s0", "s1", "a0", "a1", "a2", "a3", "a4",
+    "a5", "a6", "a7", "s2", "s3", "s4", "s5", "s6", "s7", "s8", "s9", "s10", "s11", "t3", "t4",
+    "t5", "t6",
+];
+
+pub const FREG_NAMES: [&str; 32] = [
+    "ft0", "ft1", "ft2", "ft3", "ft4", "ft5", "ft6", "ft7", "fs0", "fs1", "fa0", "fa1", "fa2",
+    "fa3", "fa4", "fa5", "fa6", "fa7", "fs2", "fs3", "fs4", "fs5", "fs6", "fs7", "fs8", "fs9",
+    "fs10", "fs11", "ft8", "ft9", "ft10", "ft11",
+];
+
+/// The number of the register called `name`, either `x0`-`x31` or an ABI
+/// name such as `sp`, `a0`, or `fp` for `s0`.
+pub fn reg_index(name: &str) -> Option<usize> {
+    if name == "fp" {
+        return Some(8);
+    }
+    if let Some(i) = REG_NAMES.iter().position(|&reg| reg == name) {
+        return Some(i);
+    }
+    let num = name.strip_prefix('x')?;
+    // No leading zeroes or signs, so x05 and x+5 aren't registers
+    if num.len() > 1 && num.starts_with('0') || !num.bytes().all(|b| b.is_ascii_digit()) {
+        return None;
+    }
+    num.parse().ok().filter(|&i| i < 32)
+}
+
+/// An instruction split into its fields, along with its disassembly. Fields
+/// an encoding doesn't use are still filled in from where they would be.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DecodedInst {
+    pub pc: u32,
+    pub inst: u32,
+    pub opcode: u32,
+    pub rd: u32,
+    pub rs1: u32,
+    pub rs2: u32,
+    pub funct3: u32,
+    pub funct7: u32,
+    pub text: String,
+}
+
+impl DecodedInst {
+    pub fn new(inst: u32, pc: u32) -> DecodedInst {
+        DecodedInst {
+            pc,
+            inst,
+            opcode: inst & 0b1111111,
+            rd: (inst >> 7) & 0b11111,
+            rs1: (inst >> 15) & 0b11111,
+            rs2: (inst >> 20) & 0b11111,
+            funct3: (inst >> 12) & 0b111,
+            funct7: inst >> 25,
+            text: disassemble(inst, pc),
+        }
+    }
+}
+
+/// Renders a branch or jump offset as the absolute address it lands on.
+fn target(pc: u32, offset: u32) -> String {
+    format!("{:#x}", pc.wrapping_add(offset))
+}
+
+/// The rounding mode operand of an FP instruction, which is left off when
+/// it's dynamic.
+fn rounding(funct3: u32) -> &'static str {
+    match funct3 {
+        0 => ", rne",
+        1 => ", rtz",
+        2 => ", rdn",
+        3 => ", rup",
+        4 => ", rmm",
+        7 => "",
+        _ => ", invalid",
+    }
+}
+
+/// Disassembles a single instruction into assembly text. `pc` is the
+/// instruction's address, used to resolve branch and jump targets.
+///
+/// How an immediate is shown depends on what it means: branch and jump
+/// targets are absolute hex addresses (`beq a0, a1, 0x80001020`), `lui` and
+/// `auipc` show their upper immediate in hex, CSRs go by name if `csr`
+/// defines them and are hex otherwise, and everything else, including
+/// load/store offsets, is signed decimal (`addi a0, a0, -16`).
+///
+/// A 16-bit value that isn't a 32-bit instruction is taken to be compressed
+/// and shown as the instruction it expands to.
+pub fn disassemble(inst: u32, pc: u32) -> String {
+    if inst >> 16 == 0 && inst & 0b11 != 0b11 {
+        if let Some(expanded) = rvc::expand(inst as u16) {
+            return disassemble(expanded, pc);
+        }
+    }
+    let rd_raw = (inst >> 7) & 0b11111;
+    let rs1_raw = (inst >> 15) & 0b11111;
+    let rd = REG_NAMES[rd_raw as usize];
+    let rs1 = REG_NAMES[rs1_raw as usize];
+    let rs2 = REG_NAMES[((inst >> 20) & 0b11111) as usize];
+    let funct3 = (inst >> 12) & 0b111;
+    let funct7 = inst >> 25;
+    let frd = FREG_NAMES[rd_raw as usize];
+    let frs1 = FREG_NAMES[rs1_raw as usize];
+    let frs2 = FREG_NAMES[((inst >> 20) & 0b11111) as usize];
+    let unknown = || format!(".word {:#010x}", inst);
+
+    match inst & 0b1111111 {
+        0b0110111 => format!("lui {}, {:#x}", rd, inst >> 12),
+        0b0010111 => format!("auipc {}, {:#x}", rd, inst >> 12),
+        0b1101111 => {
+            let target = target(pc, read_imm_j(inst));
+            match rd_raw {
+                0 => format!("j {}", target),
+                1 => format!("jal {}", target),
+                _ => format!("jal {}, {}", rd, target),
+            }
+        }
+        0b1100111 if funct3 == 0 => {
+            let imm = read_imm_i(inst) as i32;
+            match (rd_raw, imm) {
+                (0, 0) if rs1_raw == 1 => "ret".to_string(),
+                (0, 0) => format!("jr {}", rs1),
+                (1, 0) => format!("jalr {}", rs1),
+                _ => format!("jalr {}, {}({})", rd, imm, rs1),
+            }
+        }
+        0b1100011 => {
+            let name = match funct3 {
+                0 => "beq",
+                1 => "bne",
+                4 => "blt",
+                5 => "bge",
+                6 => "bltu",
+                7 => "bgeu",
+                _ => return unknown(),
+            };
+            format!(
+                "{} {}, {}, {}",
+                name,
+                rs1,
+                rs2,
+                target(pc, read_imm_b(inst))
+            )
+        }
+        0b0000011 => {
+            let name = match funct3 {
+                0 => "lb",
+                1 => "lh",
+                2 => "lw",
+                3 => "ld",
+                4 => "lbu",
+                5 => "lhu",
+                6 => "lwu",
+                _ => return unknown(),
+            };
+            format!("{} {}, {}({})", name, rd, read_imm_i(inst) as i32, rs1)
+        }
+        0b0100011 => {
+            let name = match funct3 {
+                0 => "sb",
+                1 => "sh",
+                2 => "sw",
+                3 => "sd",
+                _ => return unknown(),
+            };
+            format!("{} {}, {}({})", name, rs2, read_imm_s(inst) as i32, rs1)
+        }
+        0b0010011 => {
+            let imm = read_imm_i(inst) as i32;
+            let shamt = (inst >> 20) & 0b11111;
+            match (funct3, funct7) {
+                (0, _) => format!("addi {}, {}, {}", rd, rs1, imm),
+                (2, _) => format!("slti {}, {}, {}", rd, rs1, imm),
+                (3, _) => format!("sltiu {}, {}, {}", rd, rs1, imm),
+                (4, _) => format!("xori {}, {}, {}", rd, rs1, imm),
+                (6, _) => format!("ori {}, {}, {}", rd, rs1, imm),
+                (7, _) => format!("andi {}, {}, {}", rd, rs1, imm),
+                (1, 0) => format!("slli {}, {}, {}", rd, rs1, shamt),
+                (5, 0) => format!("srli {}, {}, {}", rd, rs1, shamt),
+                (5, 0b0100000) => format!("srai {}, {}, {}", rd, rs1, shamt),
+                (1, 0b0110000) => {
+                    let name = match shamt {
+                        0 => "clz",
+                        1 => "ctz",
+                        2 => "cpop",
+                        4 => "sext.b",
+                        5 => "sext.h",
+                        _ => return unknown(),
+                    };
+                    format!("{} {}, {}", name, rd, rs1)
+                }
+                (5, 0b0110000) => format!("rori {}, {}, {}", rd, rs1, shamt),
+                (5, 0b0010100) if shamt == 0b00111 => format!("orc.b {}, {}", rd, rs1),
+                (5, 0b0110100) if shamt == 0b11000 => format!("rev8 {}, {}", rd, rs1),
+                _ => unknown(),
+            }
+        }
+        // The RV64 word forms
+        0b0011011 => {
+            let shamt = (inst >> 20) & 0b11111;
+            match (funct3, funct7) {
+                (0, _) => format!("addiw {}, {}, {}", rd, rs1, read_imm_i(inst) as i32),
+                (1, 0) => format!("slliw {}, {}, {}", rd, rs1, shamt),
+                (5, 0) => format!("srliw {}, {}, {}", rd, rs1, shamt),
+                (5, 0b0100000) => format!("sraiw {}, {}, {}", rd, rs1, shamt),
+                _ => unknown(),
+            }
+        }
+        0b0111011 => {
+            let name = match (funct7, funct3) {
+                (0, 0) => "addw",
+                (0b0100000, 0) => "subw",
+                (0, 1) => "sllw",
+                (0, 5) => "srlw",
+                (0b0100000, 5) => "sraw",
+                (1, 0) => "mulw",
+                (1, 4) => "divw",
+                (1, 5) => "divuw",
+                (1, 6) => "remw",
+                (1, 7) => "remuw",
+                _ => return unknown(),
+            };
+            format!("{} {}, {}, {}", name, rd, rs1, rs2)
+        }
+        0b0110011 => {
+            if (funct7, funct3) == (0b0000100, 4) && inst >> 20 & 0b11111 == 0 {
+                return format!("zext.h {}, {}", rd, rs1);
+            }
+            let name = match (funct7, funct3) {
+                (0, 0) => "add",
+                (0b0100000, 0) => "sub",
+                (0, 1) => "sll",
+                (0, 2) => "slt",
+                (0, 3) => "sltu",
+                (0, 4) => "xor",
+                (0, 5) => "srl",
+                (0b0100000, 5) => "sra",
+                (0, 6) => "or",
+                (0, 7) => "and",
+                (1, 0) => "mul",
+                (1, 1) => "mulh",
+                (1, 2) => "mulhsu",
+                (1, 3) => "mulhu",
+                (1, 4) => "div",
+                (1, 5) => "divu",
+                (1, 6) => "rem",
+                (1, 7) => "remu",
+                (0b0010000, 2) => "sh1add",
+                (0b0010000, 4) => "sh2add",
+                (0b0010000, 6) => "sh3add",
+                (0b0100000, 7) => "andn",
+                (0b0100000, 6) => "orn",
+                (0b0100000, 4) => "xnor",
+                (0b0000101, 4) => "min",
+                (0b0000101, 5) => "minu",
+                (0b0000101, 6) => "max",
+                (0b0000101, 7) => "maxu",
+                (0b0110000, 1) => "rol",
+                (0b0110000, 5) => "ror",
+                _ => return unknown(),
+            };
+            format!("{} {}, {}, {}", name, rd, rs1, rs2)
+        }
+        0b0001111 => match funct3 {
+            0 => "fence".to_string(),
+            1 => "fence.i".to_string(),
+            0b010 if rd_raw == 0 => match inst >> 20 {
+                0 => format!("cbo.inval ({})", rs1),
+                1 => format!("cbo.clean ({})", rs1),
+                2 => format!("cbo.flush ({})", rs1),
+                4 => format!("cbo.zero ({})", rs1),
+                _ => unknown(),
+            },
+            _ => unknown(),
+        },
+        0b1110011 => {
+            let csr = match csr::name(inst >> 20) {
+                Some(name) => name.to_string(),
+                None => format!("{:#x}", inst >> 20),
+            };
+            match funct3 {
+                0 => match inst {
+                    0x00000073 => "ecall".to_string(),
+                    0x00100073 => "ebreak".to_string(),
+                    0x10200073 => "sret".to_string(),
+                    0x30200073 => "mret".to_string(),
+                    0x10500073 => "wfi".to_string(),
+                    _ if funct7 == 0b0001001 && rd_raw == 0 => {
+                        format!("sfence.vma {}, {}", rs1, rs2)
+                    }
+                    _ => unknown(),
+                },
+                1 => format!("csrrw {}, {}, {}", rd, csr, rs1),
+                2 => format!("csrrs {}, {}, {}", rd, csr, rs1),
+                3 => format!("csrrc {}, {}, {}", rd, csr, rs1),
+                5 => format!("csrrwi {}, {}, {}", rd, csr, rs1_raw),
+                6 => format!("csrrsi {}, {}, {}", rd, csr, rs1_raw),
+                7 => format!("csrrci {}, {}, {}", rd, csr, rs1_raw),
+                _ => unknown(),
+            }
+        }
+        0b0101111 if funct3 == 0b010 => {
+            let name = match funct7 >> 2 {
+                0b00010 => return format!("lr.w {}, ({})", rd, rs1),
+                0b00011 => "sc.w",
+                0b00001 => "amoswap.w",
+                0b00000 => "amoadd.w",
+                0b00100 => "amoxor.w",
+                0b01100 => "amoand.w",
+                0b01000 => "amoor.w",
+                0b10000 => "amomin.w",
+                0b10100 => "amomax.w",
+                0b11000 => "amominu.w",
+                0b11100 => "amomaxu.w",
+                _ => return unknown(),
+            };
+            format!("{} {}, {}, ({})", name, rd, rs2, rs1)
+        }
+        0b0000111 if funct3 == 0b010 => {
+            format!("flw {}, {}({})", frd, read_imm_i(inst) as i32, rs1)
+        }
+        0b0100111 if funct3 == 0b010 => {
+            format!("fsw {}, {}({})", frs2, read_imm_s(inst) as i32, rs1)
+        }
+        0b1000011 | 0b1000111 | 0b1001011 | 0b1001111 if funct7 & 0b11 == 0 => {
+            let name = match (inst >> 2) & 0b11 {
+                0 => "fmadd.s",
+                1 => "fmsub.s",
+                2 => "fnmsub.s",
+                _ => "fnmadd.s",
+            };
+            let frs3 = FREG_NAMES[(inst >> 27) as usize];
+            format!(
+                "{} {}, {}, {}, {}{}",
+                name,
+                frd,
+                frs1,
+                frs2,
+                frs3,
+                rounding(funct3)
+            )
+        }
+        0b1010011 => {
+            let rs2_raw = (inst >> 20) & 0b11111;
+            let rm = rounding(funct3);
+            match (funct7, funct3, rs2_raw) {
+                (0x00, _, _) => format!("fadd.s {}, {}, {}{}", frd, frs1, frs2, rm),
+                (0x04, _, _) => format!("fsub.s {}, {}, {}{}", frd, frs1, frs2, rm),
+                (0x08, _, _) => format!("fmul.s {}, {}, {}{}", frd, frs1, frs2, rm),
+                (0x0c, _, _) => format!("fdiv.s {}, {}, {}{}", frd, frs1, frs2, rm),
+                (0x2c, _, 0) => format!("fsqrt.s {}, {}{}", frd, frs1, rm),
+                (0x10, 0, _) if rs1_raw == rs2_raw => format!("fmv.s {}, {}", frd, frs1),
+                (0x10, 1, _) if rs1_raw == rs2_raw => format!("fneg.s {}, {}", frd, frs1),
+                (0x10, 2, _) if rs1_raw == rs2_raw => format!("fabs.s {}, {}", frd, frs1),
+                (0x10, 0, _) => format!("fsgnj.s {}, {}, {}", frd, frs1, frs2),
+                (0x10, 1, _) => format!("fsgnjn.s {}, {}, {}", frd, frs1, frs2),
+                (0x10, 2, _) => format!("fsgnjx.s {}, {}, {}", frd, frs1, frs2),
+                (0x14, 0, _) => format!("fmin.s {}, {}, {}", frd, frs1, frs2),
+                (0x14, 1, _) => format!("fmax.s {}, {}, {}", frd, frs1, frs2),
+                (0x60, _, 0) => format!("fcvt.w.s {}, {}{}", rd, frs1, rm),
+                (0x60, _, 1) => format!("fcvt.wu.s {}, {}{}", rd, frs1, rm),
+                (0x68, _, 0) => format!("fcvt.s.w {}, {}{}", frd, rs1, rm),
+                (0x68, _, 1) => format!("fcvt.s.wu {}, {}{}", frd, rs1, rm),
+                (0x50, 0, _) => format!("fle.s {}, {}, {}", rd, frs1, frs2),
+                (0x50, 1, _) => format!("flt.s {}, {}, {}", rd, frs1, frs2),
+                (0x50, 2, _) => format!("feq.s {}, {}, {}", rd, frs1, frs2),
+                (0x70, 0, 0) => format!("fmv.x.w {}, {}", rd, frs1),
+                (0x70, 1, 0) => format!("fclass.s {}, {}", rd, frs1),
+                (0x78, 0, 0) => format!("fmv.w.x {}, {}", frd, rs1),
+                _ => unknown(),
+            }
+        }
+        _ => unknown(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn disassembles_hand_encoded_instructions() {
+        let pc = 0x8000_1000;
+        let cases = [
+            (0xff05_0513, "addi a0, a0, -16"),
+            (0x0081_2583, "lw a1, 8(sp)"),
+            (0xfe14_2e23, "sw ra, -4(s0)"),
+            (0x0000_8067, "ret"),
+            (0x1234_5537, "lui a0, 0x12345"),
+            (0x3005_1073, "csrrw zero, mstatus, a0"),
+        ];
+        for &(inst, text) in &cases {
+            assert_eq!(disassemble(inst, pc), text);
+        }
+    }
+
+    #[test]
+    fn branch_and_jump_targets_are_absolute() {
+        let pc = 0x8000_1000;
+        // beq a0, a1, +32
+        assert_eq!(disassemble(0x02b5_0063, pc), "beq a0, a1, 0x80001020");
+        // bne t0, zero, -8
+        assert_eq!(disassemble(0xfe02_9ce3, pc), "bne t0, zero, 0x80000ff8");
+        // jal ra, +2048
+        assert_eq!(disassemble(0x0010_00ef, pc), "jal 0x80001800");
+        // The same branch elsewhere lands elsewhere
+        assert_eq!(disassemble(0x02b5_0063, 0), "beq a0, a1, 0x20");
+    }
+}
@@ -0,0 +1,166 @@
+use crate::symbols::SymbolTable;
+use std::fmt;
+
+const MAGIC: &[u8; 4] = b"\x7fELF";
+const ELFCLASS32: u8 = 1;
+const ELFDATA2LSB: u8 = 1;
+const EM_RISCV: u16 = 243;
+const PT_LOAD: u32 = 1;
+const SHT_SYMTAB: u32 = 2;
+const SHN_UNDEF: u16 = 0;
+const STT_SECTION: u8 = 3;
+const STT_FILE: u8 = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ElfError {
+    BadMagic,
+    /// A 64-bit or big-endian file
+    NotElf32,
+    /// Built for something other than RISC-V
+    WrongMachine(u16),
+    /// A header or segment runs past the end of the file
+    Truncated,
+    /// A segment's physical address isn't in RAM
+    BadSegment(u32),
+}
+
+impl fmt::Display for ElfError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ElfError::BadMagic => write!(f, "not an ELF file"),
+            ElfError::NotElf32 => write!(f, "not a little-endian ELF32 file"),
+            ElfError::WrongMachine(machine) => {
+                write!(f, "not a RISC-V ELF (e_machine {})", machine)
+            }
+            ElfError::Truncated => write!(f, "ELF file is truncated"),
+            ElfError::BadSegment(addr) => write!(f, "segment at {:#010x} is outside RAM", addr),
+        }
+    }
+}
+
+impl std::error::Error for ElfError {}
+
+/// A `PT_LOAD` segment.
+#[derive(Debug, Clone)]
+pub struct Segment {
+    pub paddr: u32,
+    /// The bytes stored in the file. The segment may be longer in memory, in
+    /// which case the rest is zero.
+    pub data: Vec<u8>,
+    pub mem_size: u32,
+}
+
+/// The parts of an executable needed to run it.
+#[derive(Debug, Clone)]
+pub struct Elf {
+    pub entry: u32,
+    pub segments: Vec<Segment>,
+    /// Named symbols from `.symtab`, empty if the file is stripped
+    pub symbols: SymbolTable,
+}
+
+/// Whether `data` starts with the ELF magic number.
+pub fn is_elf(data: &[u8]) -> bool {
+    data.starts_with(MAGIC)
+}
+
+fn read_u16(data: &[u8], offset: usize) -> Result<u16, ElfError> {
+    let bytes = data.get(offset..offset + 2).ok_or(ElfError::Truncated)?;
+    Ok(u16::from_le_bytes([bytes[0], bytes[1]]))
+}
+
+fn read_u32(data: &[u8], offset: usize) -> Result<u32, ElfError> {
+    let bytes = data.get(offset..offset + 4).ok_or(ElfError::Truncated)?;
+    Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+}
+
+impl Elf {
+    /// Parses the header and loadable segments of a RISC-V ELF32 executable.
+    pub fn parse(data: &[u8]) -> Result<Elf, ElfError> {
+        if !is_elf(data) {
+            return Err(ElfError::BadMagic);
+        }
+        if data.get(4) != Some(&ELFCLASS32) || data.get(5) != Some(&ELFDATA2LSB) {
+            return Err(ElfError::NotElf32);
+        }
+        let machine = read_u16(data, 18)?;
+        if machine != EM_RISCV {
+            return Err(ElfError::WrongMachine(machine));
+        }
+        let entry = read_u32(data, 24)?;
+        let phoff = read_u32(data, 28)? as usize;
+        let phentsize = read_u16(data, 42)? as usize;
+        let phnum = read_u16(data, 44)? as usize;
+
+        let mut segments = Vec::new();
+        for i in 0..phnum {
+            let ph = phoff + i * phentsize;
+            if read_u32(data, ph)? != PT_LOAD {
+                continue;
+            }
+            let offset = read_u32(data, ph + 4)? as usize;
+            let paddr = read_u32(data, ph + 12)?;
+            let file_size = read_u32(data, ph + 16)? as usize;
+            let mem_size = read_u32(data, ph + 20)?;
+            let bytes = data
+                .get(offset..offset + file_size)
+                .ok_or(ElfError::Truncated)?;
+            segments.push(Segment {
+                paddr,
+                data: bytes.to_vec(),
+                mem_size,
+            });
+        }
+        Ok(Elf {
+            entry,
+            segments,
+            symbols: parse_symbols(data)?,
+        })
+    }
+}
+
+/// A NUL-terminated string at `offset` in a string table.
+fn read_str(strtab: &[u8], offset: usize) -> Result<String, ElfError> {
+    let bytes = strtab.get(offset..).ok_or(ElfError::Truncated)?;
+    let len = bytes
+        .iter()
+        .position(|&b| b == 0)
+        .ok_or(ElfError::Truncated)?;
+    Ok(String::from_utf8_lossy(&bytes[..len]).into_owned())
+}
+
+/// The defined symbols in the first `SHT_SYMTAB` section, leaving out
+/// section and file symbols.
+fn parse_symbols(data: &[u8]) -> Result<SymbolTable, ElfError> {
+    let mut symbols = SymbolTable::default();
+    let shoff = read_u32(data, 32)? as usize;
+    let shentsize = read_u16(data, 46)? as usize;
+    let shnum = read_u16(data, 48)? as usize;
+    let section = |i: usize| -> Result<&[u8], ElfError> {
+        let sh = shoff + i * shentsize;
+        let offset = read_u32(data, sh + 16)? as usize;
+        let size = read_u32(data, sh + 20)? as usize;
+        data.get(offset..offset + size).ok_or(ElfError::Truncated)
+    };
+    for i in 0..shnum {
+        let sh = shoff + i * shentsize;
+        if read_u32(data, sh + 4)? != SHT_SYMTAB {
+            continue;
+        }
+        let symtab = section(i)?;
+        let strtab = section(read_u32(data, sh + 24)? as usize)?;
+        for sym in symtab.chunks_exact(16) {
+            let kind = sym[12] & 0xf;
+            let shndx = read_u16(sym, 14)?;
+            if shndx == SHN_UNDEF || kind == STT_SECTION || kind == STT_FILE {
+                continue;
+            }
+            let name = read_str(strtab, read_u32(sym, 0)? as usize)?;
+            if !name.is_empty() {
+                symbols.insert(read_u32(sym, 4)?, name);
+            }
+        }
+        break;
+    }
+    Ok(symbols)
+}
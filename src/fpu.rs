@@ -0,0 +1,396 @@
+//! Single-precision arithmetic for the F extension, correctly rounded in
+//! every rounding mode and raising the IEEE exception flags.
+//!
+//! Each operation is done in f64, where its result is either exact or off
+//! by an error whose sign can be recovered exactly, and then rounded to f32
+//! by hand. Knowing the sign of the error is enough to round correctly,
+//! since f64 resolves every f32 value and halfway point.
+
+use std::cmp::Ordering;
+
+// fflags bits
+pub(crate) const NX: u32 = 1 << 0;
+pub(crate) const UF: u32 = 1 << 1;
+pub(crate) const OF: u32 = 1 << 2;
+pub(crate) const DZ: u32 = 1 << 3;
+pub(crate) const NV: u32 = 1 << 4;
+
+/// The NaN every operation that produces one returns
+pub(crate) const CANONICAL_NAN: u32 = 0x7fc0_0000;
+
+const SIGN: u32 = 1 << 31;
+const INFINITY: u32 = 0x7f80_0000;
+const MAX: u32 = 0x7f7f_ffff;
+const QUIET: u32 = 1 << 22;
+const F64_FRAC: u64 = (1 << 52) - 1;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Rounding {
+    NearestEven,
+    TowardZero,
+    Down,
+    Up,
+    NearestMaxMagnitude,
+}
+
+impl Rounding {
+    /// The mode an `rm` field or `frm` encodes, if it's a valid one.
+    pub(crate) fn from_bits(bits: u32) -> Option<Rounding> {
+        match bits {
+            0 => Some(Rounding::NearestEven),
+            1 => Some(Rounding::TowardZero),
+            2 => Some(Rounding::Down),
+            3 => Some(Rounding::Up),
+            4 => Some(Rounding::NearestMaxMagnitude),
+            _ => None,
+        }
+    }
+}
+
+fn to_f64(bits: u32) -> f64 {
+    f32::from_bits(bits) as f64
+}
+
+fn is_nan(bits: u32) -> bool {
+    bits & !SIGN > INFINITY
+}
+
+fn is_signaling(bits: u32) -> bool {
+    is_nan(bits) && bits & QUIET == 0
+}
+
+/// Drops the low `shift` bits of `mant`, rounding as `rm` says. `sticky`
+/// means the exact value is a little more than `mant`. Returns the rounded
+/// value and whether it's inexact.
+fn round_bits(mant: u64, shift: u32, sticky: bool, negative: bool, rm: Rounding) -> (u64, bool) {
+    let kept = mant >> shift;
+    let rem = mant & ((1 << shift) - 1);
+    let half = 1 << (shift - 1);
+    let inexact = rem != 0 || sticky;
+    let up = match rm {
+        Rounding::NearestEven => rem > half || rem == half && (sticky || kept & 1 == 1),
+        Rounding::NearestMaxMagnitude => rem >= half,
+        Rounding::TowardZero => false,
+        Rounding::Down => negative && inexact,
+        Rounding::Up => !negative && inexact,
+    };
+    (kept + up as u64, inexact)
+}
+
+/// Rounds the exact value `r + e` to single precision, where `e` is smaller
+/// than an ulp of `r` and `err` is its sign. A NaN `r` gives the canonical
+/// NaN, leaving NV to the caller.
+fn round(r: f64, err: Ordering, rm: Rounding, flags: &mut u32) -> u32 {
+    if r.is_nan() {
+        return CANONICAL_NAN;
+    }
+    let negative = r.is_sign_negative();
+    let sign = if negative { SIGN } else { 0 };
+    if r.is_infinite() || r == 0.0 {
+        return sign | if r.is_infinite() { INFINITY } else { 0 };
+    }
+    // Work on the magnitude. If the error makes it smaller, step down to
+    // the next f64: the exact value lies strictly between the two, and no
+    // f32 value or halfway point does.
+    let err = if negative { err.reverse() } else { err };
+    let mut mag = r.abs().to_bits();
+    if err == Ordering::Less {
+        mag -= 1;
+    }
+    let sticky = err != Ordering::Equal;
+    let (exp, mant) = match (mag >> 52) as i32 {
+        0 => (-1022, mag & F64_FRAC),
+        biased => (biased - 1023, mag & F64_FRAC | 1 << 52),
+    };
+
+    if exp >= -126 {
+        let (mut kept, inexact) = round_bits(mant, 29, sticky, negative, rm);
+        let mut exp = exp;
+        if kept == 1 << 24 {
+            kept >>= 1;
+            exp += 1;
+        }
+        if exp > 127 {
+            *flags |= OF | NX;
+            let to_infinity = match rm {
+                Rounding::NearestEven | Rounding::NearestMaxMagnitude => true,
+                Rounding::TowardZero => false,
+                Rounding::Down => negative,
+                Rounding::Up => !negative,
+            };
+            return sign | if to_infinity { INFINITY } else { MAX };
+        }
+        if inexact {
+            *flags |= NX;
+        }
+        return sign | ((exp + 127) as u32) << 23 | (kept as u32 & 0x7f_ffff);
+    }
+
+    // Subnormal. Rounding up to the smallest normal number carries into the
+    // exponent field by itself.
+    let shift = (-97 - exp).min(63) as u32;
+    let (kept, inexact) = round_bits(mant, shift, sticky, negative, rm);
+    if inexact {
+        *flags |= NX;
+        // Tininess is detected after rounding, as if the exponent range
+        // were unbounded
+        let tiny = exp < -127 || round_bits(mant, 29, sticky, negative, rm).0 < 1 << 24;
+        if tiny {
+            *flags |= UF;
+        }
+    }
+    sign | kept as u32
+}
+
+/// The canonical NaN if any operand is a NaN, raising NV if any are
+/// signaling.
+fn nan_operands(ops: &[u32], flags: &mut u32) -> Option<u32> {
+    if ops.iter().any(|&op| is_signaling(op)) {
+        *flags |= NV;
+    }
+    if ops.iter().any(|&op| is_nan(op)) {
+        Some(CANONICAL_NAN)
+    } else {
+        None
+    }
+}
+
+fn sign_of(val: f64) -> Ordering {
+    val.partial_cmp(&0.0).unwrap_or(Ordering::Equal)
+}
+
+/// The sign of the error in `s`, the rounded sum of `a` and `b` (TwoSum).
+fn sum_error(a: f64, b: f64, s: f64) -> Ordering {
+    if !s.is_finite() {
+        return Ordering::Equal;
+    }
+    let b_virtual = s - a;
+    sign_of((a - (s - b_virtual)) + (b - b_virtual))
+}
+
+/// Rounds `a + b`, both already exact in f64.
+fn round_sum(a: f64, b: f64, rm: Rounding, flags: &mut u32) -> u32 {
+    let s = a + b;
+    if s.is_nan() {
+        // inf - inf
+        *flags |= NV;
+        return CANONICAL_NAN;
+    }
+    if s == 0.0 {
+        // An exact zero keeps the sign the operands agree on, and otherwise
+        // is positive unless rounding down
+        return if a == 0.0 && b == 0.0 && a.is_sign_negative() == b.is_sign_negative() {
+            if a.is_sign_negative() {
+                SIGN
+            } else {
+                0
+            }
+        } else if rm == Rounding::Down {
+            SIGN
+        } else {
+            0
+        };
+    }
+    round(s, sum_error(a, b, s), rm, flags)
+}
+
+pub(crate) fn add(a: u32, b: u32, rm: Rounding, flags: &mut u32) -> u32 {
+    if let Some(nan) = nan_operands(&[a, b], flags) {
+        return nan;
+    }
+    round_sum(to_f64(a), to_f64(b), rm, flags)
+}
+
+pub(crate) fn sub(a: u32, b: u32, rm: Rounding, flags: &mut u32) -> u32 {
+    add(a, b ^ SIGN, rm, flags)
+}
+
+pub(crate) fn mul(a: u32, b: u32, rm: Rounding, flags: &mut u32) -> u32 {
+    if let Some(nan) = nan_operands(&[a, b], flags) {
+        return nan;
+    }
+    // Exact, since the significands are only 24 bits
+    let p = to_f64(a) * to_f64(b);
+    if p.is_nan() {
+        // inf * 0
+        *flags |= NV;
+    }
+    round(p, Ordering::Equal, rm, flags)
+}
+
+pub(crate) fn div(a: u32, b: u32, rm: Rounding, flags: &mut u32) -> u32 {
+    if let Some(nan) = nan_operands(&[a, b], flags) {
+        return nan;
+    }
+    let (x, y) = (to_f64(a), to_f64(b));
+    let q = x / y;
+    if q.is_nan() {
+        // 0 / 0 or inf / inf
+        *flags |= NV;
+        return CANONICAL_NAN;
+    }
+    if y == 0.0 {
+        if x.is_finite() {
+            *flags |= DZ;
+        }
+        return round(q, Ordering::Equal, rm, flags);
+    }
+    // The remainder is exact, and the quotient is short by remainder / y
+    let err = if q.is_finite() && q != 0.0 {
+        match sign_of((-q).mul_add(y, x)) {
+            Ordering::Equal => Ordering::Equal,
+            rem if (rem == Ordering::Greater) == (y > 0.0) => Ordering::Greater,
+            _ => Ordering::Less,
+        }
+    } else {
+        Ordering::Equal
+    };
+    round(q, err, rm, flags)
+}
+
+pub(crate) fn sqrt(a: u32, rm: Rounding, flags: &mut u32) -> u32 {
+    if let Some(nan) = nan_operands(&[a], flags) {
+        return nan;
+    }
+    let x = to_f64(a);
+    if x < 0.0 {
+        *flags |= NV;
+        return CANONICAL_NAN;
+    }
+    let r = x.sqrt();
+    let err = if r.is_finite() && r != 0.0 {
+        sign_of((-r).mul_add(r, x))
+    } else {
+        Ordering::Equal
+    };
+    round(r, err, rm, flags)
+}
+
+/// `±(a * b) ± c` with a single rounding, negating the product and addend
+/// as asked, for the FMA family.
+pub(crate) fn fused_mul_add(
+    a: u32,
+    b: u32,
+    c: u32,
+    negate_product: bool,
+    negate_addend: bool,
+    rm: Rounding,
+    flags: &mut u32,
+) -> u32 {
+    let (x, y) = (to_f64(a), to_f64(b));
+    // inf * 0 is invalid even when the addend is a quiet NaN
+    if (x.is_infinite() && y == 0.0) || (x == 0.0 && y.is_infinite()) {
+        *flags |= NV;
+        return CANONICAL_NAN;
+    }
+    if let Some(nan) = nan_operands(&[a, b, c], flags) {
+        return nan;
+    }
+    let p = if negate_product { -(x * y) } else { x * y };
+    let z = if negate_addend { -to_f64(c) } else { to_f64(c) };
+    round_sum(p, z, rm, flags)
+}
+
+/// `fmin.s` or `fmax.s`: a NaN operand is ignored in favour of the other,
+/// and -0 is less than +0.
+pub(crate) fn min_max(a: u32, b: u32, max: bool, flags: &mut u32) -> u32 {
+    if is_signaling(a) || is_signaling(b) {
+        *flags |= NV;
+    }
+    match (is_nan(a), is_nan(b)) {
+        (true, true) => CANONICAL_NAN,
+        (true, false) => b,
+        (false, true) => a,
+        (false, false) => {
+            let (x, y) = (f32::from_bits(a), f32::from_bits(b));
+            let a_less = x < y || (x == y && a & SIGN != 0);
+            if a_less != max {
+                a
+            } else {
+                b
+            }
+        }
+    }
+}
+
+/// How `a` compares with `b`, or `None` if either is a NaN. `feq.s` is
+/// quiet, raising NV only for signaling NaNs, while `flt.s` and `fle.s`
+/// raise it for any NaN.
+pub(crate) fn compare(a: u32, b: u32, quiet: bool, flags: &mut u32) -> Option<Ordering> {
+    if is_nan(a) || is_nan(b) {
+        if !quiet || is_signaling(a) || is_signaling(b) {
+            *flags |= NV;
+        }
+        return None;
+    }
+    f32::from_bits(a).partial_cmp(&f32::from_bits(b))
+}
+
+/// `fcvt.w.s` or `fcvt.wu.s`. Out of range values and NaNs saturate and
+/// raise NV.
+pub(crate) fn to_int(a: u32, signed: bool, rm: Rounding, flags: &mut u32) -> u32 {
+    let (min, max) = if signed {
+        (i32::MIN as f64, i32::MAX as f64)
+    } else {
+        (0.0, u32::MAX as f64)
+    };
+    let max_bits = if signed { i32::MAX as u32 } else { u32::MAX };
+    if is_nan(a) {
+        *flags |= NV;
+        return max_bits;
+    }
+    let x = to_f64(a);
+    let r = match rm {
+        Rounding::NearestEven => x.round_ties_even(),
+        Rounding::TowardZero => x.trunc(),
+        Rounding::Down => x.floor(),
+        Rounding::Up => x.ceil(),
+        Rounding::NearestMaxMagnitude => x.round(),
+    };
+    if r < min {
+        *flags |= NV;
+        return min as i32 as u32;
+    }
+    if r > max {
+        *flags |= NV;
+        return max_bits;
+    }
+    if r != x {
+        *flags |= NX;
+    }
+    if signed {
+        r as i32 as u32
+    } else {
+        r as u32
+    }
+}
+
+/// `fcvt.s.w` or `fcvt.s.wu`.
+pub(crate) fn from_int(val: u32, signed: bool, rm: Rounding, flags: &mut u32) -> u32 {
+    let x = if signed {
+        val as i32 as f64
+    } else {
+        val as f64
+    };
+    round(x, Ordering::Equal, rm, flags)
+}
+
+/// The `fclass.s` mask: one bit for which of the ten classes `a` is in.
+pub(crate) fn classify(a: u32) -> u32 {
+    let negative = a & SIGN != 0;
+    let exp = (a >> 23) & 0xff;
+    let frac = a & 0x7f_ffff;
+    let class = match (exp, frac) {
+        (0xff, 0) => 0,
+        (0xff, _) if a & QUIET == 0 => return 1 << 8,
+        (0xff, _) => return 1 << 9,
+        (0, 0) => 3,
+        (0, _) => 2,
+        _ => 1,
+    };
+    if negative {
+        1 << class
+    } else {
+        1 << (7 - class)
+    }
+}
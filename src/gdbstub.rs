@@ -0,0 +1,397 @@
+//! A GDB remote serial protocol stub, so GDB can debug a guest over TCP with
+//! `target remote`.
+
+use crate::core::{Core, StepOutcome};
+use std::convert::TryInto;
+use std::fmt::Write as _;
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+
+/// Steps to run between checks for an interrupt from GDB while continuing
+const POLL_INTERVAL: usize = 10_000;
+
+const SIGINT: u8 = 2;
+const SIGTRAP: u8 = 5;
+
+/// GDB's number for pc. x0-x31 come first, and CSRs start at `FIRST_CSR`.
+const PC: usize = 32;
+const FIRST_CSR: usize = 65;
+
+/// Waits for GDB to connect on `addr`, then serves it until it detaches,
+/// kills the target or goes away.
+pub fn serve(core: &mut Core, addr: impl ToSocketAddrs) -> io::Result<()> {
+    let listener = TcpListener::bind(addr)?;
+    let (stream, _) = listener.accept()?;
+    stream.set_nodelay(true)?;
+    GdbStub { core, stream }.run()
+}
+
+struct GdbStub<'a> {
+    core: &'a mut Core,
+    stream: TcpStream,
+}
+
+fn stop_reply(signal: u8) -> String {
+    format!("S{:02x}", signal)
+}
+
+/// The stop reply for a step that stopped with `outcome`, naming the address
+/// if it was a watchpoint and the exit code if the guest exited.
+fn step_reply(outcome: StepOutcome) -> String {
+    match outcome {
+        StepOutcome::Watchpoint(addr) => format!("T{:02x}watch:{:x};", SIGTRAP, addr),
+        StepOutcome::HtifExit(code) => format!("W{:02x}", code),
+        _ => stop_reply(SIGTRAP),
+    }
+}
+
+fn push_hex_le(out: &mut String, val: u32) {
+    for byte in val.to_le_bytes() {
+        write!(out, "{:02x}", byte).unwrap();
+    }
+}
+
+fn parse_hex_bytes(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+/// A register value as GDB sends it: 8 hex digits, least significant byte
+/// first.
+fn parse_hex_le(hex: &str) -> Option<u32> {
+    let bytes: [u8; 4] = parse_hex_bytes(hex)?.try_into().ok()?;
+    Some(u32::from_le_bytes(bytes))
+}
+
+fn parse_num(hex: &str) -> Option<u32> {
+    u32::from_str_radix(hex, 16).ok()
+}
+
+/// Splits `addr,len`, as used by the memory and breakpoint packets.
+fn parse_range(args: &str) -> Option<(u32, u32)> {
+    let (addr, len) = args.split_once(',')?;
+    Some((parse_num(addr)?, parse_num(len)?))
+}
+
+impl GdbStub<'_> {
+    fn run(&mut self) -> io::Result<()> {
+        while let Some(packet) = self.recv()? {
+            match packet.as_str() {
+                "D" => return self.send("OK"),
+                "k" => return Ok(()),
+                _ => {
+                    let reply = self.handle(&packet)?;
+                    self.send(&reply)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// The reply to `packet`. Anything unsupported gets an empty reply, as
+    /// the protocol asks.
+    fn handle(&mut self, packet: &str) -> io::Result<String> {
+        let (cmd, args) = packet.split_at(packet.chars().next().map_or(0, char::len_utf8));
+        let reply = match cmd {
+            "?" => stop_reply(SIGTRAP),
+            "g" => {
+                let mut out = String::new();
+                for i in 0..=PC {
+                    push_hex_le(&mut out, self.read_register(i).unwrap());
+                }
+                out
+            }
+            "G" => self.write_registers(args),
+            "p" => {
+                let reg = usize::from_str_radix(args, 16).ok();
+                match reg.and_then(|reg| self.read_register(reg)) {
+                    Some(val) => {
+                        let mut out = String::new();
+                        push_hex_le(&mut out, val);
+                        out
+                    }
+                    None => "E01".to_string(),
+                }
+            }
+            "P" => self.write_register(args),
+            "m" => self.read_memory(args),
+            "M" => self.write_memory(args),
+            "s" | "c" => {
+                if let Some(addr) = parse_num(args) {
+                    self.core.set_pc(addr);
+                }
+                if cmd == "s" {
+                    step_reply(self.core.step())
+                } else {
+                    self.resume()?
+                }
+            }
+            "Z" | "z" => self.breakpoint(cmd == "Z", args),
+            "H" => "OK".to_string(),
+            "q" if args.starts_with("Supported") => "PacketSize=4000".to_string(),
+            "q" if args == "Attached" => "1".to_string(),
+            _ => String::new(),
+        };
+        Ok(reply)
+    }
+
+    fn read_register(&self, reg: usize) -> Option<u32> {
+        match reg {
+            0..=31 => Some(self.core.reg(reg)),
+            PC => Some(self.core.pc),
+            _ if (FIRST_CSR..FIRST_CSR + 4096).contains(&reg) => {
+                Some(self.core.read_csr((reg - FIRST_CSR) as u32))
+            }
+            _ => None,
+        }
+    }
+
+    fn set_register(&mut self, reg: usize, val: u32) -> bool {
+        match reg {
+            0..=31 => self.core.set_reg(reg, val),
+            PC => self.core.set_pc(val),
+            _ if (FIRST_CSR..FIRST_CSR + 4096).contains(&reg) => {
+                self.core.write_csr((reg - FIRST_CSR) as u32, val)
+            }
+            _ => return false,
+        }
+        true
+    }
+
+    fn write_registers(&mut self, args: &str) -> String {
+        let vals: Option<Vec<u32>> = (0..=PC)
+            .map(|i| args.get(i * 8..i * 8 + 8).and_then(parse_hex_le))
+            .collect();
+        match vals {
+            Some(vals) => {
+                for (i, val) in vals.into_iter().enumerate() {
+                    self.set_register(i, val);
+                }
+                "OK".to_string()
+            }
+            None => "E01".to_string(),
+        }
+    }
+
+    fn write_register(&mut self, args: &str) -> String {
+        let parsed = args.split_once('=').and_then(|(reg, val)| {
+            Some((usize::from_str_radix(reg, 16).ok()?, parse_hex_le(val)?))
+        });
+        match parsed {
+            Some((reg, val)) if self.set_register(reg, val) => "OK".to_string(),
+            _ => "E01".to_string(),
+        }
+    }
+
+    /// Memory is read and written by physical address, without going
+    /// through the MMU. Only RAM and ROM can be read, and only RAM written,
+    /// so GDB can't disturb devices by looking at them.
+    fn read_memory(&mut self, args: &str) -> String {
+        let (addr, len) = match parse_range(args) {
+            Some(range) => range,
+            None => return "E01".to_string(),
+        };
+        let mut out = String::new();
+        for i in 0..len {
+            let mut byte = [0];
+            match self.core.read_mem(addr.wrapping_add(i), &mut byte) {
+                Ok(()) => write!(out, "{:02x}", byte[0]).unwrap(),
+                // A partial read is fine as long as something was read
+                Err(_) if i > 0 => break,
+                Err(_) => return "E14".to_string(),
+            }
+        }
+        out
+    }
+
+    fn write_memory(&mut self, args: &str) -> String {
+        let parsed = args.split_once(':').and_then(|(range, data)| {
+            let (addr, len) = parse_range(range)?;
+            let data = parse_hex_bytes(data)?;
+            Some((addr, data)).filter(|(_, data)| data.len() == len as usize)
+        });
+        let (addr, data) = match parsed {
+            Some(parsed) => parsed,
+            None => return "E01".to_string(),
+        };
+        match self.core.write_mem(addr, &data) {
+            Ok(()) => "OK".to_string(),
+            Err(_) => "E14".to_string(),
+        }
+    }
+
+    /// Software breakpoints (`Z0`) and write watchpoints (`Z2`) are
+    /// supported. Both are kept by the core rather than patched into memory,
+    /// and watchpoints are on physical addresses.
+    fn breakpoint(&mut self, insert: bool, args: &str) -> String {
+        let (kind, range) = match args.split_once(',') {
+            Some(split) => split,
+            None => return String::new(),
+        };
+        let (addr, len) = match parse_range(range) {
+            Some(range) => range,
+            None => return "E01".to_string(),
+        };
+        match (kind, insert) {
+            ("0", true) => self.core.add_breakpoint(addr),
+            ("0", false) => self.core.remove_breakpoint(addr),
+            ("2", _) => {
+                for byte in (0..len).map(|i| addr.wrapping_add(i)) {
+                    if insert {
+                        self.core.set_watchpoint(byte);
+                    } else {
+                        self.core.clear_watchpoint(byte);
+                    }
+                }
+            }
+            _ => return String::new(),
+        }
+        "OK".to_string()
+    }
+
+    /// Runs until the core stops by itself or GDB interrupts it.
+    fn resume(&mut self) -> io::Result<String> {
+        loop {
+            for _ in 0..POLL_INTERVAL {
+                let outcome = self.core.step();
+                if outcome.stops() {
+                    return Ok(step_reply(outcome));
+                }
+            }
+            if self.interrupted()? {
+                return Ok(stop_reply(SIGINT));
+            }
+        }
+    }
+
+    /// Whether GDB has sent an interrupt (Ctrl-C) or gone away.
+    fn interrupted(&mut self) -> io::Result<bool> {
+        self.stream.set_nonblocking(true)?;
+        let mut byte = [0];
+        let result = self.stream.read(&mut byte);
+        self.stream.set_nonblocking(false)?;
+        match result {
+            Ok(0) => Ok(true),
+            Ok(_) => Ok(byte[0] == 0x03),
+            Err(err) if err.kind() == ErrorKind::WouldBlock => Ok(false),
+            Err(err) => Err(err),
+        }
+    }
+
+    fn read_byte(&mut self) -> io::Result<Option<u8>> {
+        let mut byte = [0];
+        match self.stream.read(&mut byte)? {
+            0 => Ok(None),
+            _ => Ok(Some(byte[0])),
+        }
+    }
+
+    /// Reads the next packet, acknowledging it. Acks, nacks and interrupts
+    /// while stopped are skipped. `None` once GDB disconnects.
+    fn recv(&mut self) -> io::Result<Option<String>> {
+        loop {
+            match self.read_byte()? {
+                None => return Ok(None),
+                Some(b'$') => {}
+                Some(_) => continue,
+            }
+            let mut data = Vec::new();
+            loop {
+                match self.read_byte()? {
+                    None => return Ok(None),
+                    Some(b'#') => break,
+                    Some(byte) => data.push(byte),
+                }
+            }
+            let mut checksum = [0; 2];
+            self.stream.read_exact(&mut checksum)?;
+            let expected = std::str::from_utf8(&checksum)
+                .ok()
+                .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+            let sum = data.iter().fold(0u8, |sum, &b| sum.wrapping_add(b));
+            if expected != Some(sum) {
+                self.stream.write_all(b"-")?;
+                continue;
+            }
+            self.stream.write_all(b"+")?;
+            return Ok(Some(String::from_utf8_lossy(&data).into_owned()));
+        }
+    }
+
+    fn send(&mut self, data: &str) -> io::Result<()> {
+        let sum = data.bytes().fold(0u8, |sum, b| sum.wrapping_add(b));
+        write!(self.stream, "${}#{:02x}", data, sum)?;
+        self.stream.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+    use std::time::Duration;
+
+    /// Reads one `$data#xx` packet, skipping acks, and checks its checksum.
+    fn read_packet(stream: &mut TcpStream) -> String {
+        let mut byte = [0];
+        while byte[0] != b'$' {
+            stream.read_exact(&mut byte).unwrap();
+        }
+        let mut data = Vec::new();
+        loop {
+            stream.read_exact(&mut byte).unwrap();
+            if byte[0] == b'#' {
+                break;
+            }
+            data.push(byte[0]);
+        }
+        let mut checksum = [0; 2];
+        stream.read_exact(&mut checksum).unwrap();
+        let sum = data.iter().fold(0u8, |sum, &b| sum.wrapping_add(b));
+        assert_eq!(
+            std::str::from_utf8(&checksum).unwrap(),
+            format!("{:02x}", sum)
+        );
+        String::from_utf8(data).unwrap()
+    }
+
+    #[test]
+    fn replies_to_g_with_every_register() {
+        // Find a free port; the stub binds it again straight away
+        let addr = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let gdb = thread::spawn(move || {
+            let mut stream = (0..100)
+                .find_map(|_| {
+                    TcpStream::connect(addr)
+                        .map_err(|_| thread::sleep(Duration::from_millis(10)))
+                        .ok()
+                })
+                .expect("stub never started listening");
+            stream.write_all(b"$g#67").unwrap();
+            let regs = read_packet(&mut stream);
+            stream.write_all(b"+$D#44").unwrap();
+            assert_eq!(read_packet(&mut stream), "OK");
+            regs
+        });
+
+        let mut core = Core::new();
+        core.set_reg(10, 0x1234_5678);
+        let pc = core.pc;
+        serve(&mut core, addr).unwrap();
+        let regs = gdb.join().unwrap();
+
+        assert_eq!(regs.len(), 8 * 33);
+        assert!(regs.bytes().all(|b| b.is_ascii_hexdigit()));
+        assert_eq!(&regs[..8], "00000000");
+        assert_eq!(&regs[8 * 10..8 * 11], "78563412");
+        assert_eq!(parse_hex_le(&regs[8 * 32..]), Some(pc));
+    }
+}
@@ -0,0 +1,28 @@
+//! The host-target interface riscv-tests and proxy kernels use to talk to the
+//! host. The guest writes a 64-bit command to the `tohost` word: the device
+//! in the top byte, the command in the next one and a payload below them.
+
+/// A `tohost` command the emulator understands.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Command {
+    /// Device 0 with an odd payload ends the run. `(n << 1) | 1` reports
+    /// test `n` failing, so 1 means every test passed and the code is 0.
+    Exit(u32),
+    /// Device 1, command 1: write the low byte to the console.
+    Putchar(u8),
+}
+
+impl Command {
+    /// Decodes a value written to `tohost`. `None` for anything else, such as
+    /// device 0's proxied syscalls.
+    pub fn decode(val: u64) -> Option<Command> {
+        let device = val >> 56;
+        let cmd = (val >> 48) & 0xff;
+        let payload = val & 0xffff_ffff_ffff;
+        match (device, cmd) {
+            (0, 0) if payload & 1 != 0 => Some(Command::Exit((payload >> 1) as u32)),
+            (1, 1) => Some(Command::Putchar(payload as u8)),
+            _ => None,
+        }
+    }
+}
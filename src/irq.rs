@@ -0,0 +1,159 @@
+use std::cell::Cell;
+use std::rc::Rc;
+
+// Interrupt sources, numbered by their bit in mip
+pub const SSIP: u32 = 1;
+pub const MSIP: u32 = 3;
+pub const STIP: u32 = 5;
+pub const MTIP: u32 = 7;
+pub const SEIP: u32 = 9;
+pub const MEIP: u32 = 11;
+
+/// A hart's interrupt lines. Devices keep a clone and raise or lower lines on
+/// it, and the hart sees every raised line as pending in `mip`, so devices
+/// never need to touch the core's CSRs themselves.
+#[derive(Debug, Clone, Default)]
+pub struct InterruptController {
+    lines: Rc<Cell<u32>>,
+}
+
+impl InterruptController {
+    pub fn raise(&self, source: u32) {
+        self.lines.set(self.lines.get() | (1 << source));
+    }
+
+    pub fn lower(&self, source: u32) {
+        self.lines.set(self.lines.get() & !(1 << source));
+    }
+
+    pub fn is_raised(&self, source: u32) -> bool {
+        self.lines.get() & (1 << source) != 0
+    }
+
+    /// Raised lines as a mask of mip bits.
+    pub fn pending(&self) -> u32 {
+        self.lines.get()
+    }
+
+    /// Lowers every line.
+    pub fn reset(&self) {
+        self.lines.set(0);
+    }
+}
+
+#[derive(Debug)]
+struct TimerRegs {
+    mtime: Cell<u64>,
+    mtimecmp: Cell<u64>,
+}
+
+impl Default for TimerRegs {
+    fn default() -> Self {
+        // Nothing is pending until mtimecmp is programmed
+        TimerRegs {
+            mtime: Cell::new(0),
+            mtimecmp: Cell::new(u64::MAX),
+        }
+    }
+}
+
+/// A hart's `mtime` and `mtimecmp`. The hart advances `mtime` as it runs
+/// and sees MTIP pending whenever it has reached `mtimecmp`; a timer device
+/// such as the CLINT keeps a clone to expose both registers to the guest.
+#[derive(Debug, Clone, Default)]
+pub struct Timer {
+    regs: Rc<TimerRegs>,
+}
+
+impl Timer {
+    pub fn mtime(&self) -> u64 {
+        self.regs.mtime.get()
+    }
+
+    pub fn set_mtime(&self, val: u64) {
+        self.regs.mtime.set(val);
+    }
+
+    pub fn advance(&self, ticks: u64) {
+        self.set_mtime(self.mtime().wrapping_add(ticks));
+    }
+
+    pub fn mtimecmp(&self) -> u64 {
+        self.regs.mtimecmp.get()
+    }
+
+    pub fn set_mtimecmp(&self, val: u64) {
+        self.regs.mtimecmp.set(val);
+    }
+
+    /// Puts `mtime` back to zero and `mtimecmp` back to never firing.
+    pub fn reset(&self) {
+        let regs = TimerRegs::default();
+        self.set_mtime(regs.mtime.get());
+        self.set_mtimecmp(regs.mtimecmp.get());
+    }
+
+    /// Whether MTIP is pending.
+    pub fn is_pending(&self) -> bool {
+        self.mtime() >= self.mtimecmp()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::core::{Core, StepOutcome};
+    use crate::csr;
+    use crate::mem::MmioDevice;
+
+    /// Drives MEIP from its only register: non-zero raises it, zero lowers
+    /// it.
+    struct Doorbell(InterruptController);
+
+    impl MmioDevice for Doorbell {
+        fn read(&mut self, _offset: u32, _width: u32) -> u32 {
+            self.0.is_raised(MEIP) as u32
+        }
+
+        fn write(&mut self, _offset: u32, _width: u32, val: u32) {
+            if val != 0 {
+                self.0.raise(MEIP);
+            } else {
+                self.0.lower(MEIP);
+            }
+        }
+    }
+
+    #[test]
+    fn a_device_line_shows_in_mip_and_interrupts() {
+        // lui t0, 0x20000; li t1, 1; sw t1, 0(t0); csrr a0, mip;
+        // sw zero, 0(t0); csrr a1, mip; sw t1, 0(t0); nop
+        let program: [u32; 8] = [
+            0x2000_02b7,
+            0x0010_0313,
+            0x0062_a023,
+            0x3440_2573,
+            0x0002_a023,
+            0x3440_25f3,
+            0x0062_a023,
+            0x13,
+        ];
+        let mut core = Core::new();
+        core.load_image(program.iter().flat_map(|w| w.to_le_bytes()).collect())
+            .unwrap();
+        let doorbell = Doorbell(core.irq.clone());
+        core.mem.map_device(0x2000_0000, 4, Box::new(doorbell));
+        assert_eq!(core.run_for(6), StepOutcome::Continue);
+        assert_eq!(core.reg(10), 1 << MEIP);
+        assert_eq!(core.reg(11), 0);
+
+        core.csrs[csr::MTVEC as usize] = 0x8000_1000;
+        core.csrs[csr::MIE as usize] = 1 << MEIP;
+        core.csrs[csr::MSTATUS as usize] |= csr::MSTATUS_MIE;
+        core.step();
+        assert_eq!(core.step(), StepOutcome::Interrupt(MEIP));
+        assert_eq!(core.pc, 0x8000_1000);
+        assert_eq!(core.csrs[csr::MCAUSE as usize], csr::CAUSE_INTERRUPT | MEIP);
+        assert_eq!(core.csrs[csr::MEPC as usize], core.reset_pc + 28);
+    }
+}
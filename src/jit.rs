@@ -0,0 +1,129 @@
+use crate::core::{read_imm_i, read_imm_u};
+use crate::mem::Mem;
+use crate::mmu::PAGE_SIZE;
+use std::collections::{HashMap, HashSet};
+use std::rc::Rc;
+
+/// One compiled instruction. It only ever touches the register file.
+type Op = Box<dyn Fn(&mut [u32; 32])>;
+
+/// A run of straight-line register-to-register instructions. The instruction
+/// that ends it (a branch, jump, memory access or anything else not
+/// compiled) is left to the interpreter.
+pub(crate) struct Block {
+    ops: Vec<Op>,
+}
+
+impl Block {
+    /// Number of instructions in the block.
+    pub(crate) fn len(&self) -> usize {
+        self.ops.len()
+    }
+
+    pub(crate) fn run(&self, reg: &mut [u32; 32]) {
+        for op in &self.ops {
+            op(reg);
+        }
+    }
+}
+
+/// Compiled blocks, keyed by the physical address they start at.
+#[derive(Default)]
+pub(crate) struct BlockCache {
+    blocks: HashMap<u32, Rc<Block>>,
+    /// Pages holding compiled code, so stores elsewhere are cheap to ignore
+    code_pages: HashSet<u32>,
+}
+
+impl BlockCache {
+    /// The block starting at `pc`, compiling it from `mem` if needed.
+    pub(crate) fn get(&mut self, mem: &mut Mem, pc: u32) -> Rc<Block> {
+        if let Some(block) = self.blocks.get(&pc) {
+            return block.clone();
+        }
+        let mut ops = Vec::new();
+        let mut addr = pc;
+        while let Some(op) = mem.read(addr, 4).ok().and_then(|inst| compile(inst, addr)) {
+            ops.push(op);
+            addr = addr.wrapping_add(4);
+        }
+        self.code_pages.extend(pc / PAGE_SIZE..=addr / PAGE_SIZE);
+        let block = Rc::new(Block { ops });
+        self.blocks.insert(pc, block.clone());
+        block
+    }
+
+    /// Drops compiled code that a store of `size` bytes at `addr` may have
+    /// overwritten. This is page-granular: any store to a page holding code
+    /// throws away every block.
+    pub(crate) fn invalidate(&mut self, addr: u32, size: u32) {
+        let first = addr / PAGE_SIZE;
+        let last = addr.wrapping_add(size - 1) / PAGE_SIZE;
+        if self.code_pages.contains(&first) || self.code_pages.contains(&last) {
+            self.clear();
+        }
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.blocks.clear();
+        self.code_pages.clear();
+    }
+}
+
+/// Compiles `inst` at `pc` if it's a plain OP, OP-IMM, LUI or AUIPC that
+/// can't trap. Anything else ends the block.
+fn compile(inst: u32, pc: u32) -> Option<Op> {
+    let rd = ((inst >> 7) & 0b11111) as usize;
+    let rs1 = ((inst >> 15) & 0b11111) as usize;
+    let rs2 = ((inst >> 20) & 0b11111) as usize;
+    let funct3 = (inst >> 12) & 0b111;
+    let funct7 = inst >> 25;
+    let imm = read_imm_i(inst);
+
+    let op: Op = match inst & 0b1111111 {
+        0b0110111 => {
+            let val = read_imm_u(inst);
+            Box::new(move |reg| reg[rd] = val)
+        }
+        0b0010111 => {
+            let val = pc.wrapping_add(read_imm_u(inst));
+            Box::new(move |reg| reg[rd] = val)
+        }
+        0b0010011 => match (funct3, funct7) {
+            (0, _) => Box::new(move |reg| reg[rd] = reg[rs1].wrapping_add(imm)),
+            (2, _) => Box::new(move |reg| reg[rd] = ((reg[rs1] as i32) < (imm as i32)) as u32),
+            (3, _) => Box::new(move |reg| reg[rd] = (reg[rs1] < imm) as u32),
+            (4, _) => Box::new(move |reg| reg[rd] = reg[rs1] ^ imm),
+            (6, _) => Box::new(move |reg| reg[rd] = reg[rs1] | imm),
+            (7, _) => Box::new(move |reg| reg[rd] = reg[rs1] & imm),
+            (1, 0) => Box::new(move |reg| reg[rd] = reg[rs1] << (imm & 0b11111)),
+            (5, 0) => Box::new(move |reg| reg[rd] = reg[rs1] >> (imm & 0b11111)),
+            (5, 0b0100000) => {
+                Box::new(move |reg| reg[rd] = ((reg[rs1] as i32) >> (imm & 0b11111)) as u32)
+            }
+            _ => return None,
+        },
+        0b0110011 => match (funct7, funct3) {
+            (0, 0) => Box::new(move |reg| reg[rd] = reg[rs1].wrapping_add(reg[rs2])),
+            (0b0100000, 0) => Box::new(move |reg| reg[rd] = reg[rs1].wrapping_sub(reg[rs2])),
+            (0, 2) => Box::new(move |reg| reg[rd] = ((reg[rs1] as i32) < (reg[rs2] as i32)) as u32),
+            (0, 3) => Box::new(move |reg| reg[rd] = (reg[rs1] < reg[rs2]) as u32),
+            (0, 4) => Box::new(move |reg| reg[rd] = reg[rs1] ^ reg[rs2]),
+            (0, 6) => Box::new(move |reg| reg[rd] = reg[rs1] | reg[rs2]),
+            (0, 7) => Box::new(move |reg| reg[rd] = reg[rs1] & reg[rs2]),
+            (0, 1) => Box::new(move |reg| reg[rd] = reg[rs1] << (reg[rs2] & 0b11111)),
+            (0, 5) => Box::new(move |reg| reg[rd] = reg[rs1] >> (reg[rs2] & 0b11111)),
+            (0b0100000, 5) => {
+                Box::new(move |reg| reg[rd] = ((reg[rs1] as i32) >> (reg[rs2] & 0b11111)) as u32)
+            }
+            // Malformed, so the interpreter can raise the exception
+            _ => return None,
+        },
+        _ => return None,
+    };
+    // Later ops in the block read x0, so it must never be written
+    if rd == 0 {
+        return Some(Box::new(|_| {}));
+    }
+    Some(op)
+}
@@ -0,0 +1,22 @@
+pub mod clint;
+pub mod config;
+pub mod core;
+pub mod csr;
+mod decode;
+pub mod disasm;
+pub mod elf;
+mod fpu;
+pub mod gdbstub;
+pub mod htif;
+pub mod irq;
+#[cfg(feature = "jit")]
+mod jit;
+pub mod machine;
+pub mod mem;
+pub mod mmu;
+mod pmp;
+mod rvc;
+pub mod sbi;
+pub mod symbols;
+pub mod trap;
+pub mod uart;
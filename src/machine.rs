@@ -0,0 +1,331 @@
+use crate::clint::{Clint, CLINT_SIZE};
+use crate::config::Config;
+use crate::core::{Core, StepOutcome};
+use crate::csr;
+use crate::disasm::disassemble;
+use crate::elf::ElfError;
+use crate::mem::{AccessFault, Mem, MmioDevice};
+use crate::symbols::SymbolTable;
+use crate::uart::{Uart, UART_SIZE};
+use std::fmt;
+
+/// A whole system: memory and devices shared by one or more harts.
+///
+/// Harts are `Core`s without memory of their own. While a hart is being
+/// stepped it holds the machine's memory, which is handed back afterwards.
+pub struct Machine {
+    pub mem: Mem,
+    pub harts: Vec<Core>,
+}
+
+/// One hart's instruction in a `Machine::lockstep` trace.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LockstepStep {
+    pub round: usize,
+    pub hart: usize,
+    pub pc: u32,
+    /// The instruction executed, or `None` if the hart took an interrupt
+    /// or fetching it faulted
+    pub inst: Option<u32>,
+    pub outcome: StepOutcome,
+}
+
+impl fmt::Display for LockstepStep {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:>4} hart {}: {:08x}", self.round, self.hart, self.pc)?;
+        match self.inst {
+            Some(inst) => write!(f, "  {}", disassemble(inst, self.pc))?,
+            None if matches!(self.outcome, StepOutcome::Interrupt(_)) => {}
+            None => write!(f, "  <fetch fault>")?,
+        }
+        if self.outcome != StepOutcome::Continue {
+            write!(f, "  => {:?}", self.outcome)?;
+        }
+        Ok(())
+    }
+}
+
+impl Machine {
+    pub fn new(num_harts: usize) -> Machine {
+        let mut harts: Vec<Core> = (0..num_harts).map(|_| Core::detached()).collect();
+        for (id, hart) in harts.iter_mut().enumerate() {
+            hart.csrs[csr::MHARTID as usize] = id as u32;
+        }
+        Machine {
+            mem: Default::default(),
+            harts,
+        }
+    }
+
+    /// A machine laid out as `config` says, which every hart also runs
+    /// with: its RAM, a CLINT and a UART on stdout, both where it puts them.
+    pub fn with_config(num_harts: usize, config: Config) -> Machine {
+        let mut machine = Machine::new(num_harts);
+        machine.mem = Mem::new(config.ram_base, config.ram_size);
+        for hart in &mut machine.harts {
+            hart.config = config.clone();
+            hart.reset_to_entry(config.ram_base);
+        }
+        if let Some(base) = config.clint_base {
+            let clint = Clint::new(&machine.harts);
+            machine.map_device(base, CLINT_SIZE, Box::new(clint));
+        }
+        if let Some(base) = config.uart_base {
+            machine.map_device(base, UART_SIZE, Box::new(Uart::stdout()));
+        }
+        machine
+    }
+
+    pub fn single_hart() -> Machine {
+        Machine::new(1)
+    }
+
+    pub fn map_device(&mut self, base: u32, size: u32, dev: Box<dyn MmioDevice>) {
+        self.mem.map_device(base, size, dev);
+    }
+
+    /// Loads a flat binary as `Core::load_image` does, resetting every hart.
+    pub fn load_image(&mut self, data: &[u8]) -> Result<(), AccessFault> {
+        self.mem.load_image(data)?;
+        for hart in &mut self.harts {
+            hart.reset();
+        }
+        Ok(())
+    }
+
+    /// Copies `data` into RAM at `addr` as `Core::load_image_at` does, so a
+    /// boot ROM image and a payload can be staged separately. The harts
+    /// carry on from where they are.
+    pub fn load_image_at(&mut self, addr: u32, data: &[u8]) -> Result<(), AccessFault> {
+        self.mem.load_image_at(addr, data)?;
+        for hart in &mut self.harts {
+            hart.flush_code();
+        }
+        Ok(())
+    }
+
+    /// Loads an ELF executable as `Core::load_elf` does, starting every hart
+    /// at its entry point with its symbols and `tohost`.
+    pub fn load_elf(&mut self, data: &[u8]) -> Result<(), ElfError> {
+        let entry = self.with_hart(0, |hart| hart.load_elf(data).map(|()| hart.pc))?;
+        let (first, rest) = self.harts.split_first_mut().unwrap();
+        for hart in rest {
+            hart.reset_to_entry(entry);
+            hart.symbols = first.symbols.clone();
+            hart.config.tohost = first.config.tohost;
+        }
+        Ok(())
+    }
+
+    pub fn set_symbols(&mut self, symbols: SymbolTable) {
+        for hart in &mut self.harts {
+            hart.symbols = symbols.clone();
+        }
+    }
+
+    /// Runs `f` on a hart while it holds the machine's memory.
+    fn with_hart<R>(&mut self, id: usize, f: impl FnOnce(&mut Core) -> R) -> R {
+        let hart = &mut self.harts[id];
+        std::mem::swap(&mut self.mem, &mut hart.mem);
+        let ret = f(hart);
+        std::mem::swap(&mut self.mem, &mut hart.mem);
+        ret
+    }
+
+    /// A store by one hart breaks any other hart's reservation on that word.
+    fn snoop_store(&mut self, id: usize) {
+        if let Some((addr, size)) = self.harts[id].last_store {
+            for (other, hart) in self.harts.iter_mut().enumerate() {
+                if other != id {
+                    hart.snoop_store(addr, size);
+                }
+            }
+        }
+    }
+
+    fn step_hart(&mut self, id: usize) -> StepOutcome {
+        let outcome = self.with_hart(id, Core::step);
+        self.snoop_store(id);
+        outcome
+    }
+
+    /// Steps every hart once, in order, or by a whole block for harts with
+    /// `Config::jit` set. Returns the first hart that stopped along with the
+    /// reason.
+    pub fn step_all(&mut self) -> Option<(usize, StepOutcome)> {
+        let mut stopped = None;
+        for id in 0..self.harts.len() {
+            let outcome = self.advance_hart(id);
+            if outcome.stops() && stopped.is_none() {
+                stopped = Some((id, outcome));
+            }
+        }
+        stopped
+    }
+
+    /// Steps a hart once, or by a whole block if it has `Config::jit` set.
+    fn advance_hart(&mut self, id: usize) -> StepOutcome {
+        #[cfg(feature = "jit")]
+        if self.harts[id].config.jit {
+            let outcome = self.with_hart(id, Core::step_block);
+            self.snoop_store(id);
+            return outcome;
+        }
+        self.step_hart(id)
+    }
+
+    /// Runs until every hart has stopped, each sitting out once it has, and
+    /// returns the outcome the last one stopped with.
+    pub fn run_until_halt(&mut self) -> StepOutcome {
+        let mut halted = vec![false; self.harts.len()];
+        let mut last = StepOutcome::Continue;
+        while halted.contains(&false) {
+            for (id, halted) in halted.iter_mut().enumerate() {
+                if *halted {
+                    continue;
+                }
+                let outcome = self.advance_hart(id);
+                if outcome.stops() {
+                    *halted = true;
+                    last = outcome;
+                }
+            }
+        }
+        last
+    }
+
+    /// Advances every hart by one instruction per round, in hart order, for
+    /// `rounds` rounds, and returns the interleaved trace. A hart that stops
+    /// sits out the remaining rounds.
+    pub fn lockstep(&mut self, rounds: usize) -> Vec<LockstepStep> {
+        let mut trace = Vec::new();
+        let mut stopped = vec![false; self.harts.len()];
+        for round in 0..rounds {
+            let runnable: Vec<usize> = (0..self.harts.len()).filter(|&id| !stopped[id]).collect();
+            if runnable.is_empty() {
+                break;
+            }
+            for id in runnable {
+                let pc = self.harts[id].pc;
+                let outcome = self.step_hart(id);
+                let inst = self.harts[id].last_inst;
+                stopped[id] = outcome.stops();
+                trace.push(LockstepStep {
+                    round,
+                    hart: id,
+                    pc,
+                    inst,
+                    outcome,
+                });
+            }
+        }
+        trace
+    }
+
+    pub fn run(&mut self) -> (usize, StepOutcome) {
+        loop {
+            if let Some(stopped) = self.step_all() {
+                return stopped;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::uart::UART_BASE;
+    use std::cell::RefCell;
+    use std::io;
+    use std::rc::Rc;
+
+    /// A writer that keeps everything written to it where the test can see.
+    #[derive(Clone, Default)]
+    struct Captured(Rc<RefCell<Vec<u8>>>);
+
+    impl io::Write for Captured {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    /// `program` as a flat image.
+    fn image(program: &[u32]) -> Vec<u8> {
+        program.iter().flat_map(|w| w.to_le_bytes()).collect()
+    }
+
+    #[test]
+    fn single_hart_prints_through_the_uart() {
+        // lui t0, 0x10000; li t1, 'h'; sb t1, 0(t0); li t1, 'i'; sb t1, 0(t0);
+        // ebreak
+        let program = [
+            0x1000_02b7,
+            0x0680_0313,
+            0x0062_8023,
+            0x0690_0313,
+            0x0062_8023,
+            0x0010_0073,
+        ];
+        let out = Captured::default();
+        let mut machine = Machine::single_hart();
+        machine.map_device(
+            UART_BASE,
+            UART_SIZE,
+            Box::new(Uart::new(Box::new(out.clone()))),
+        );
+        machine.load_image(&image(&program)).unwrap();
+        assert_eq!(machine.run_until_halt(), StepOutcome::Ebreak);
+        assert_eq!(*out.0.borrow(), b"hi");
+        assert_eq!(machine.harts[0].pc, machine.mem.ram_base() + 20);
+    }
+
+    #[test]
+    fn run_until_halt_waits_for_every_hart() {
+        // csrr t0, mhartid; beqz t0, 1f; nop; nop; 1: ebreak
+        let program: [u32; 5] = [0xf140_22f3, 0x0002_8663, 0x13, 0x13, 0x0010_0073];
+        let mut machine = Machine::new(2);
+        machine.load_image(&image(&program)).unwrap();
+        let end = machine.mem.ram_base() + 16;
+        // Hart 1 takes the long way round and stops after hart 0 has
+        machine.harts[1].add_breakpoint(end);
+        assert_eq!(machine.run_until_halt(), StepOutcome::Breakpoint(end));
+        assert_eq!(machine.harts[0].pc, end);
+        assert_eq!(machine.harts[0].cycle_count, 3);
+        assert_eq!(machine.harts[1].pc, end);
+        assert_eq!(machine.harts[1].cycle_count, 4);
+    }
+
+    #[test]
+    fn lockstep_harts_share_an_amo_counter() {
+        // lui a0, 0x80001; li a1, 1; li t0, 5; 1: amoadd.w zero, a1, (a0);
+        // addi t0, t0, -1; bnez t0, 1b; ebreak
+        let program = [
+            0x8000_1537,
+            0x0010_0593,
+            0x0050_0293,
+            0x00b5_202f,
+            0xfff2_8293,
+            0xfe02_9ce3,
+            0x0010_0073,
+        ];
+        let mut machine = Machine::new(2);
+        machine.load_image(&image(&program)).unwrap();
+        let trace = machine.lockstep(100);
+        assert_eq!(machine.mem.lw(0x8000_1000), 10);
+
+        // 3 + 5 * 3 + 1 instructions each, taking turns
+        assert_eq!(trace.len(), 2 * 19);
+        let base = machine.mem.ram_base();
+        for (i, step) in trace.iter().enumerate() {
+            assert_eq!((step.round, step.hart), (i / 2, i % 2));
+            let word = (step.pc - base) as usize / 4;
+            assert_eq!(step.inst, Some(program[word]));
+        }
+        assert_eq!(trace[37].outcome, StepOutcome::Ebreak);
+    }
+}
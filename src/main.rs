@@ -1,13 +1,120 @@
-mod core;
-mod mem;
+use riscv_emu::config::Config;
+use riscv_emu::core::StepOutcome;
+use riscv_emu::elf;
+use riscv_emu::gdbstub;
+use riscv_emu::machine::Machine;
+use riscv_emu::mem::{Mem, MemoryMap};
+use riscv_emu::symbols::SymbolTable;
+use std::io;
 
-use crate::core::Core;
+fn usage() -> ! {
+    eprintln!("usage: riscv-emu [--load-symbols <file>] [--print-memory-map] [--trace] [--log-commits <file>] [--gdb <addr>] [--jit] [image]");
+    std::process::exit(1);
+}
 
 fn main() {
-    let mut core = Core::new();
-    core.load_image(std::fs::read("test/sbi/image.bin").unwrap());
-    core.run();
+    let mut image = "test/sbi/image.bin".to_string();
+    let mut symbols = None;
+    let mut print_memory_map = false;
+    let mut trace = false;
+    let mut log_commits = None;
+    let mut gdb = None;
+    #[cfg(feature = "jit")]
+    let mut jit = false;
+
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--load-symbols" => {
+                let path = args.next().unwrap_or_else(|| usage());
+                match SymbolTable::from_file(&path) {
+                    Ok(table) => symbols = Some(table),
+                    Err(err) => {
+                        eprintln!("error: {}: {}", path, err);
+                        std::process::exit(1);
+                    }
+                }
+            }
+            "--print-memory-map" => print_memory_map = true,
+            "--trace" => trace = true,
+            "--log-commits" => log_commits = Some(args.next().unwrap_or_else(|| usage())),
+            "--gdb" => gdb = Some(args.next().unwrap_or_else(|| usage())),
+            #[cfg(feature = "jit")]
+            "--jit" => jit = true,
+            _ if arg.starts_with("--") => usage(),
+            _ => image = arg,
+        }
+    }
+
+    let config = Config::default();
+    if print_memory_map {
+        print!("{}", MemoryMap::from_config(&config));
+        return;
+    }
+    let mut machine = Machine::with_config(1, config);
+    let data = std::fs::read(image).unwrap();
+    if elf::is_elf(&data) {
+        if let Err(err) = machine.load_elf(&data) {
+            eprintln!("error: {}", err);
+            std::process::exit(1);
+        }
+    } else if machine.load_image(&data).is_err() {
+        eprintln!("error: image doesn't fit in RAM");
+        std::process::exit(1);
+    }
+    if let Some(symbols) = symbols {
+        // riscv-tests report results through HTIF's tohost
+        if let Some(tohost) = symbols.address_of("tohost") {
+            for hart in &mut machine.harts {
+                hart.config.tohost = Some(tohost);
+            }
+        }
+        machine.set_symbols(symbols);
+    }
+    for hart in &mut machine.harts {
+        hart.set_trace(trace);
+    }
+    if let Some(path) = log_commits {
+        let file = std::fs::File::create(path).unwrap();
+        for hart in &mut machine.harts {
+            // Line buffered so the harts' lines don't tear each other
+            let sink = io::LineWriter::new(file.try_clone().unwrap());
+            hart.set_trace_sink(Box::new(sink));
+        }
+    }
+    #[cfg(feature = "jit")]
+    for hart in &mut machine.harts {
+        hart.config.jit = jit;
+    }
+    if let Some(addr) = gdb {
+        // The stub drives a single core, so give hart 0 the machine's memory
+        let mut core = machine.harts.swap_remove(0);
+        core.mem = std::mem::replace(&mut machine.mem, Mem::empty());
+        eprintln!("waiting for gdb on {}", addr);
+        if let Err(err) = gdbstub::serve(&mut core, addr.as_str()) {
+            eprintln!("error: {}", err);
+            std::process::exit(1);
+        }
+        return;
+    }
+    match machine.run_until_halt() {
+        StepOutcome::Ebreak => {
+            println!("Hit EBREAK");
+            for hart in &machine.harts {
+                if let Err(err) = hart.dump_state(&mut io::stdout()) {
+                    eprintln!("error: {}", err);
+                    std::process::exit(1);
+                }
+            }
+        }
+        StepOutcome::HtifExit(0) => println!("PASS"),
+        StepOutcome::HtifExit(test) => {
+            println!("FAIL: test {}", test);
+            std::process::exit(1);
+        }
+        _ => {}
+    }
     // for _ in 0..100 {
-    //     core.step();
+    //     machine.step_all();
     // }
 }
@@ -1,52 +1,705 @@
+use crate::clint::{CLINT_COMPATIBLE, CLINT_SIZE};
+use crate::config::Config;
+use crate::mmu::PAGE_SIZE;
+use crate::uart::{UART_COMPATIBLE, UART_SIZE};
+use std::collections::HashMap;
+use std::convert::TryInto;
+use std::fmt;
+
 pub const MEMORY_SIZE: usize = 16777216; // 16mb
+pub const RAM_BASE: u32 = 0x80000000;
+
+/// An access to an address with nothing mapped at it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AccessFault;
+
+/// A memory-mapped device. Offsets are relative to the base the device was
+/// mapped at.
+pub trait MmioDevice {
+    fn read(&mut self, offset: u32, width: u32) -> u32;
+    fn write(&mut self, offset: u32, width: u32, val: u32);
+
+    /// Whether the register at `offset` is still in its reset state because
+    /// the guest hasn't set the device up yet, for
+    /// `Config::warn_uninit_mmio`.
+    fn is_uninitialized(&self, _offset: u32) -> bool {
+        false
+    }
+
+    /// The device tree `compatible` string for the device, if it has one.
+    fn compatible(&self) -> Option<&'static str> {
+        None
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RegionKind {
+    Ram,
+    Rom,
+    Device,
+}
+
+/// One mapped range of the physical address space.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Region {
+    pub kind: RegionKind,
+    pub base: u32,
+    pub size: u32,
+    /// For devices, their `MmioDevice::compatible` string
+    pub compatible: Option<&'static str>,
+}
+
+/// The physical memory layout, sorted by base address, for anything that
+/// needs to describe the machine to a guest or a user.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MemoryMap {
+    pub regions: Vec<Region>,
+}
+
+impl MemoryMap {
+    /// The layout `config` describes: its RAM, and the UART and CLINT
+    /// wherever it puts them, as `Machine::with_config` maps them.
+    pub fn from_config(config: &Config) -> MemoryMap {
+        let mut regions = vec![Region {
+            kind: RegionKind::Ram,
+            base: config.ram_base,
+            size: config.ram_size as u32,
+            compatible: None,
+        }];
+        let devices = [
+            (config.uart_base, UART_SIZE, UART_COMPATIBLE),
+            (config.clint_base, CLINT_SIZE, CLINT_COMPATIBLE),
+        ];
+        for (base, size, compatible) in devices {
+            if let Some(base) = base {
+                regions.push(Region {
+                    kind: RegionKind::Device,
+                    base,
+                    size,
+                    compatible: Some(compatible),
+                });
+            }
+        }
+        regions.sort_by_key(|region| region.base);
+        MemoryMap { regions }
+    }
+}
+
+impl fmt::Display for MemoryMap {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for region in &self.regions {
+            let end = region.base as u64 + region.size as u64;
+            write!(f, "{:08x}-{:08x} {:?}", region.base, end - 1, region.kind)?;
+            if let Some(compatible) = region.compatible {
+                write!(f, " ({})", compatible)?;
+            }
+            writeln!(f)?;
+        }
+        Ok(())
+    }
+}
+
+/// A copy of RAM that only keeps the pages that aren't all zero, from
+/// `Mem::ram_image`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RamImage {
+    size: usize,
+    /// Page number and contents
+    pages: Vec<(usize, Box<[u8]>)>,
+}
+
+struct MappedDevice {
+    base: u32,
+    size: u32,
+    dev: Box<dyn MmioDevice>,
+}
+
+/// A read-only region, such as a boot ROM.
+struct Rom {
+    base: u32,
+    data: Box<[u8]>,
+}
+
+impl Rom {
+    fn offset(&self, addr: u32, size: u32) -> Option<usize> {
+        let offset = addr.checked_sub(self.base)? as usize;
+        if offset + size as usize <= self.data.len() {
+            Some(offset)
+        } else {
+            None
+        }
+    }
+
+    fn overlaps(&self, addr: u32, size: u32) -> bool {
+        let end = self.base as u64 + self.data.len() as u64;
+        (addr as u64) < end && (self.base as u64) < addr as u64 + size as u64
+    }
+}
+
+/// Backing store for RAM.
+enum Ram {
+    Flat(Box<[u8]>),
+    /// Pages allocated the first time they're written. Untouched ones read
+    /// as zero.
+    Sparse {
+        size: usize,
+        pages: HashMap<usize, Box<[u8]>>,
+    },
+}
+
+impl Ram {
+    fn len(&self) -> usize {
+        match self {
+            Ram::Flat(mem) => mem.len(),
+            Ram::Sparse { size, .. } => *size,
+        }
+    }
+
+    /// Fills `buf` from `offset`, which the caller has bounds checked.
+    fn read(&self, offset: usize, buf: &mut [u8]) {
+        match self {
+            Ram::Flat(mem) => buf.copy_from_slice(&mem[offset..offset + buf.len()]),
+            Ram::Sparse { pages, .. } => {
+                for (i, byte) in buf.iter_mut().enumerate() {
+                    let addr = offset + i;
+                    let page = pages.get(&(addr / PAGE_SIZE as usize));
+                    *byte = page.map_or(0, |page| page[addr % PAGE_SIZE as usize]);
+                }
+            }
+        }
+    }
+
+    /// Copies `data` to `offset`, which the caller has bounds checked.
+    fn write(&mut self, offset: usize, data: &[u8]) {
+        match self {
+            Ram::Flat(mem) => mem[offset..offset + data.len()].copy_from_slice(data),
+            Ram::Sparse { pages, .. } => {
+                for (i, &byte) in data.iter().enumerate() {
+                    let addr = offset + i;
+                    let page = pages
+                        .entry(addr / PAGE_SIZE as usize)
+                        .or_insert_with(|| vec![0; PAGE_SIZE as usize].into_boxed_slice());
+                    page[addr % PAGE_SIZE as usize] = byte;
+                }
+            }
+        }
+    }
+
+    fn clear(&mut self) {
+        match self {
+            Ram::Flat(mem) => mem.fill(0),
+            Ram::Sparse { pages, .. } => pages.clear(),
+        }
+    }
+}
 
 pub struct Mem {
-    pub mem: Box<[u8; MEMORY_SIZE]>,
+    mem: Ram,
+    /// Guest physical address of the first byte of RAM
+    ram_base: u32,
+    devices: Vec<MappedDevice>,
+    roms: Vec<Rom>,
+    /// Whether any device or ROM is mapped over part of RAM. Until one is,
+    /// accesses to flat RAM go straight to the backing store.
+    ram_shadowed: bool,
 }
 
 impl Default for Mem {
     fn default() -> Self {
-        Self {
-            mem: Box::new([0; MEMORY_SIZE]),
-        }
+        Mem::new(RAM_BASE, MEMORY_SIZE)
     }
 }
 
 impl Mem {
-    pub fn lb(&self, addr: u32) -> u8 {
-        if addr & 0x80000000 != 0 {
-            self.mem[addr as usize & 0x7FFFFFFF]
+    /// `size` bytes of RAM starting at guest physical address `base`.
+    pub fn new(base: u32, size: usize) -> Mem {
+        Mem::check_ram_fits(base, size);
+        Mem {
+            mem: Ram::Flat(vec![0; size].into_boxed_slice()),
+            ram_base: base,
+            devices: Vec::new(),
+            roms: Vec::new(),
+            ram_shadowed: false,
+        }
+    }
+
+    fn check_ram_fits(base: u32, size: usize) {
+        assert!(
+            size as u64 <= (u32::MAX - base) as u64 + 1,
+            "RAM can't extend past the end of the address space"
+        );
+    }
+
+    /// Memory with no RAM behind it. Harts owned by a `Machine` hold one of
+    /// these while the machine's memory is lent to another hart.
+    pub fn empty() -> Mem {
+        Mem {
+            mem: Ram::Flat(Box::new([])),
+            ram_base: RAM_BASE,
+            devices: Vec::new(),
+            roms: Vec::new(),
+            ram_shadowed: false,
+        }
+    }
+
+    /// `size` bytes of RAM that only take up host memory for the pages the
+    /// guest writes to, so a large, sparsely used guest address space stays
+    /// cheap. Accesses are slower than with the default flat RAM, and there's
+    /// no contiguous backing store for `ram_slice` or `view` to return.
+    pub fn sparse(size: usize) -> Mem {
+        Mem::sparse_at(RAM_BASE, size)
+    }
+
+    /// Sparse RAM like `sparse`, starting at `base`.
+    pub fn sparse_at(base: u32, size: usize) -> Mem {
+        Mem::check_ram_fits(base, size);
+        Mem {
+            mem: Ram::Sparse {
+                size,
+                pages: HashMap::new(),
+            },
+            ram_base: base,
+            devices: Vec::new(),
+            roms: Vec::new(),
+            ram_shadowed: false,
+        }
+    }
+
+    /// The size of RAM in bytes.
+    pub fn ram_size(&self) -> usize {
+        self.mem.len()
+    }
+
+    /// The guest physical address RAM starts at, `RAM_BASE` by default.
+    pub fn ram_base(&self) -> u32 {
+        self.ram_base
+    }
+
+    /// How many pages of host memory back RAM. For flat RAM that's all of
+    /// it, allocated up front.
+    pub fn allocated_pages(&self) -> usize {
+        match &self.mem {
+            Ram::Flat(mem) => mem.len().div_ceil(PAGE_SIZE as usize),
+            Ram::Sparse { pages, .. } => pages.len(),
+        }
+    }
+
+    /// Zeroes all of RAM.
+    pub fn clear_ram(&mut self) {
+        self.mem.clear();
+    }
+
+    /// Copies the contents of RAM. Devices and ROMs aren't included.
+    pub fn ram_image(&self) -> RamImage {
+        let page_size = PAGE_SIZE as usize;
+        let mut pages = Vec::new();
+        let mut buf = vec![0; page_size];
+        for page in 0..self.mem.len().div_ceil(page_size) {
+            let offset = page * page_size;
+            let len = page_size.min(self.mem.len() - offset);
+            self.mem.read(offset, &mut buf[..len]);
+            if buf[..len].iter().any(|&b| b != 0) {
+                pages.push((page, buf[..len].into()));
+            }
+        }
+        RamImage {
+            size: self.mem.len(),
+            pages,
+        }
+    }
+
+    /// Puts RAM back the way it was when `image` was taken. Sparse RAM only
+    /// allocates the pages that had something in them.
+    pub fn restore_ram(&mut self, image: &RamImage) {
+        assert_eq!(image.size, self.mem.len(), "RAM image is the wrong size");
+        self.mem.clear();
+        for (page, data) in &image.pages {
+            self.mem.write(page * PAGE_SIZE as usize, data);
+        }
+    }
+
+    /// The RAM backing store, unless RAM is sparse. Offset 0 is guest
+    /// physical address `ram_base()`, 0x80000000 by default, so guest address
+    /// `a` is at index `a - ram_base()`. Devices and ROMs mapped over RAM aren't
+    /// reflected here.
+    pub fn ram_slice(&self) -> Option<&[u8]> {
+        match &self.mem {
+            Ram::Flat(mem) => Some(mem),
+            Ram::Sparse { .. } => None,
+        }
+    }
+
+    /// Mutable access to the RAM backing store, laid out as for `ram_slice`.
+    pub fn ram_slice_mut(&mut self) -> Option<&mut [u8]> {
+        match &mut self.mem {
+            Ram::Flat(mem) => Some(mem),
+            Ram::Sparse { .. } => None,
+        }
+    }
+
+    pub fn map_device(&mut self, base: u32, size: u32, dev: Box<dyn MmioDevice>) {
+        self.devices.push(MappedDevice { base, size, dev });
+        self.ram_shadowed |= self.overlaps_ram(base, size as u64);
+    }
+
+    /// Maps `data` as read-only memory at `base`, replacing any ROM already
+    /// there. Reads are served from it ahead of RAM, and any write touching
+    /// it fails.
+    pub fn map_rom(&mut self, base: u32, data: Vec<u8>) {
+        self.roms.retain(|rom| rom.base != base);
+        self.roms.push(Rom {
+            base,
+            data: data.into_boxed_slice(),
+        });
+        self.ram_shadowed |= self.overlaps_ram(base, self.roms.last().unwrap().data.len() as u64);
+    }
+
+    fn overlaps_ram(&self, base: u32, size: u64) -> bool {
+        let ram_end = self.ram_base as u64 + self.mem.len() as u64;
+        (base as u64) < ram_end && (self.ram_base as u64) < base as u64 + size
+    }
+
+    /// Describes what is mapped where.
+    pub fn memory_map(&self) -> MemoryMap {
+        let mut regions = Vec::new();
+        if self.mem.len() != 0 {
+            regions.push(Region {
+                kind: RegionKind::Ram,
+                base: self.ram_base,
+                size: self.mem.len() as u32,
+                compatible: None,
+            });
+        }
+        regions.extend(self.roms.iter().map(|rom| Region {
+            kind: RegionKind::Rom,
+            base: rom.base,
+            size: rom.data.len() as u32,
+            compatible: None,
+        }));
+        regions.extend(self.devices.iter().map(|d| Region {
+            kind: RegionKind::Device,
+            base: d.base,
+            size: d.size,
+            compatible: d.dev.compatible(),
+        }));
+        regions.sort_by_key(|region| region.base);
+        MemoryMap { regions }
+    }
+
+    /// Whether `addr` is a device register its device reports as not yet
+    /// initialized.
+    pub fn is_uninitialized_device(&self, addr: u32) -> bool {
+        self.devices
+            .iter()
+            .find(|d| addr >= d.base && addr - d.base < d.size)
+            .is_some_and(|d| d.dev.is_uninitialized(addr - d.base))
+    }
+
+    fn device_at(&mut self, addr: u32) -> Option<&mut MappedDevice> {
+        self.devices
+            .iter_mut()
+            .find(|d| addr >= d.base && addr - d.base < d.size)
+    }
+
+    /// Clears RAM and copies `data` to the start of it. Fails without
+    /// touching RAM if `data` is bigger.
+    pub fn load_image(&mut self, data: &[u8]) -> Result<(), AccessFault> {
+        if data.len() > self.mem.len() {
+            return Err(AccessFault);
+        }
+        self.mem.clear();
+        self.load_image_at(self.ram_base, data)
+    }
+
+    /// Copies `data` into RAM at `addr`, leaving the rest of memory alone.
+    /// Fails without copying anything if it doesn't fit entirely in RAM.
+    pub fn load_image_at(&mut self, addr: u32, data: &[u8]) -> Result<(), AccessFault> {
+        let offset = addr.checked_sub(self.ram_base).ok_or(AccessFault)? as usize;
+        if offset + data.len() > self.mem.len() {
+            return Err(AccessFault);
+        }
+        self.mem.write(offset, data);
+        Ok(())
+    }
+
+    /// Reads `size` bytes (1, 2 or 4) at `addr`. Fails if the access isn't
+    /// entirely inside RAM, a single ROM or a single device.
+    pub fn read(&mut self, addr: u32, size: u32) -> Result<u32, AccessFault> {
+        if let Some((mem, offset)) = self.flat_ram(addr, size) {
+            let bytes = &mem[offset..offset + size as usize];
+            return Ok(match size {
+                4 => u32::from_le_bytes(bytes.try_into().unwrap()),
+                2 => u16::from_le_bytes(bytes.try_into().unwrap()) as u32,
+                _ => bytes[0] as u32,
+            });
+        }
+        if let Some(d) = self.device_at(addr) {
+            if addr - d.base + size > d.size {
+                return Err(AccessFault);
+            }
+            return Ok(d.dev.read(addr - d.base, size));
+        }
+        let mut bytes = [0; 4];
+        if let Some(rom) = self.roms.iter().find(|rom| rom.overlaps(addr, size)) {
+            let offset = rom.offset(addr, size).ok_or(AccessFault)?;
+            bytes[..size as usize].copy_from_slice(&rom.data[offset..offset + size as usize]);
+            return Ok(u32::from_le_bytes(bytes));
+        }
+        let offset = self.ram_offset(addr, size).ok_or(AccessFault)?;
+        self.mem.read(offset, &mut bytes[..size as usize]);
+        Ok(u32::from_le_bytes(bytes))
+    }
+
+    /// Writes the low `size` bytes (1, 2 or 4) of `val` at `addr`. Fails if
+    /// the access isn't entirely inside RAM or a single device, or touches a
+    /// ROM.
+    pub fn write(&mut self, addr: u32, size: u32, val: u32) -> Result<(), AccessFault> {
+        if let Some((mem, offset)) = self.flat_ram_mut(addr, size) {
+            let bytes = &mut mem[offset..offset + size as usize];
+            match size {
+                4 => bytes.copy_from_slice(&val.to_le_bytes()),
+                2 => bytes.copy_from_slice(&(val as u16).to_le_bytes()),
+                _ => bytes[0] = val as u8,
+            }
+            return Ok(());
+        }
+        if let Some(d) = self.device_at(addr) {
+            if addr - d.base + size > d.size {
+                return Err(AccessFault);
+            }
+            d.dev.write(addr - d.base, size, val);
+            return Ok(());
+        }
+        if self.roms.iter().any(|rom| rom.overlaps(addr, size)) {
+            return Err(AccessFault);
+        }
+        let offset = self.ram_offset(addr, size).ok_or(AccessFault)?;
+        self.mem.write(offset, &val.to_le_bytes()[..size as usize]);
+        Ok(())
+    }
+
+    /// Fills `buf` from RAM and ROM at `addr` without touching any device,
+    /// for host tools that want to look at guest memory without side
+    /// effects. Fails if any of the bytes is a device register or unmapped,
+    /// leaving the bytes before it filled in.
+    pub fn peek(&self, addr: u32, buf: &mut [u8]) -> Result<(), AccessFault> {
+        if self.is_ram(addr, buf.len() as u32) {
+            self.mem.read((addr - self.ram_base) as usize, buf);
+            return Ok(());
+        }
+        for (i, byte) in buf.iter_mut().enumerate() {
+            let addr = addr.checked_add(i as u32).ok_or(AccessFault)?;
+            if let Some(rom) = self.roms.iter().find(|rom| rom.overlaps(addr, 1)) {
+                *byte = rom.data[(addr - rom.base) as usize];
+            } else if self.is_ram(addr, 1) {
+                self.mem
+                    .read((addr - self.ram_base) as usize, std::slice::from_mut(byte));
+            } else {
+                return Err(AccessFault);
+            }
+        }
+        Ok(())
+    }
+
+    /// Copies `data` into RAM at `addr`, the host-side counterpart of
+    /// `peek`. Fails without writing anything unless every byte is plain
+    /// RAM, with no device or ROM mapped over it.
+    pub fn poke(&mut self, addr: u32, data: &[u8]) -> Result<(), AccessFault> {
+        if data.is_empty() {
+            return Ok(());
+        }
+        if !self.is_ram(addr, data.len() as u32) {
+            return Err(AccessFault);
+        }
+        self.mem.write((addr - self.ram_base) as usize, data);
+        Ok(())
+    }
+
+    /// Borrows `len` bytes of RAM starting at `addr`, if they're all plain
+    /// RAM with no device or ROM mapped over any of them. Always `None` for
+    /// sparse RAM.
+    pub fn view(&self, addr: u32, len: u32) -> Option<&[u8]> {
+        if !self.is_ram(addr, len) {
+            return None;
+        }
+        let offset = (addr - self.ram_base) as usize;
+        self.ram_slice()?.get(offset..offset + len as usize)
+    }
+
+    /// Whether the `len` bytes at `addr` are all RAM, with no device or ROM
+    /// mapped over any of them.
+    pub fn is_ram(&self, addr: u32, len: u32) -> bool {
+        let end = addr as u64 + len as u64;
+        let covered =
+            |base: u32, size: u64| (base as u64) < end && (addr as u64) < base as u64 + size;
+        addr >= self.ram_base
+            && end <= self.ram_base as u64 + self.mem.len() as u64
+            && !self.devices.iter().any(|d| covered(d.base, d.size as u64))
+            && !self
+                .roms
+                .iter()
+                .any(|rom| covered(rom.base, rom.data.len() as u64))
+    }
+
+    /// Whether none of the `len` bytes at `addr` has RAM, a ROM or a device
+    /// behind it.
+    pub fn is_unmapped(&self, addr: u32, len: u32) -> bool {
+        let end = addr as u64 + len as u64;
+        let covered =
+            |base: u32, size: u64| (base as u64) < end && (addr as u64) < base as u64 + size;
+        !covered(self.ram_base, self.mem.len() as u64)
+            && !self.devices.iter().any(|d| covered(d.base, d.size as u64))
+            && !self
+                .roms
+                .iter()
+                .any(|rom| covered(rom.base, rom.data.len() as u64))
+    }
+
+    /// The flat backing store and the offset of `addr` in it, if the access
+    /// can skip looking for devices and ROMs because it's all in RAM and
+    /// nothing is mapped over RAM.
+    fn flat_ram(&self, addr: u32, size: u32) -> Option<(&[u8], usize)> {
+        match &self.mem {
+            Ram::Flat(mem) if !self.ram_shadowed => Some((mem, self.ram_offset(addr, size)?)),
+            _ => None,
+        }
+    }
+
+    fn flat_ram_mut(&mut self, addr: u32, size: u32) -> Option<(&mut [u8], usize)> {
+        let offset = self.ram_offset(addr, size)?;
+        match &mut self.mem {
+            Ram::Flat(mem) if !self.ram_shadowed => Some((mem, offset)),
+            _ => None,
+        }
+    }
+
+    fn ram_offset(&self, addr: u32, size: u32) -> Option<usize> {
+        let offset = addr.checked_sub(self.ram_base)? as usize;
+        if offset + size as usize <= self.mem.len() {
+            Some(offset)
         } else {
-            0
+            None
         }
     }
 
+    // The accessors below are for the host's convenience: reads of unmapped
+    // addresses give zero and writes to them are dropped. An access only
+    // partly inside RAM, a ROM or a device counts as unmapped as a whole, so
+    // it never mixes real bytes with zeroes.
+
+    pub fn lb(&mut self, addr: u32) -> u8 {
+        self.read(addr, 1).unwrap_or(0) as u8
+    }
+
     pub fn sb(&mut self, addr: u32, val: u8) {
-        if addr & 0x80000000 != 0 {
-            self.mem[addr as usize & 0x7FFFFFFF] = val;
-        }
+        let _ = self.write(addr, 1, val as u32);
     }
 
-    pub fn lh(&self, addr: u32) -> u16 {
-        ((self.lb(addr + 1) as u16) << 8) | (self.lb(addr) as u16)
+    pub fn lh(&mut self, addr: u32) -> u16 {
+        self.read(addr, 2).unwrap_or(0) as u16
     }
 
-    pub fn lw(&self, addr: u32) -> u32 {
-        ((self.lb(addr + 3) as u32) << 24)
-            | ((self.lb(addr + 2) as u32) << 16)
-            | ((self.lb(addr + 1) as u32) << 8)
-            | (self.lb(addr) as u32)
+    pub fn lw(&mut self, addr: u32) -> u32 {
+        self.read(addr, 4).unwrap_or(0)
     }
 
     pub fn sh(&mut self, addr: u32, val: u16) {
-        self.sb(addr, val as u8);
-        self.sb(addr + 1, (val >> 8) as u8);
+        let _ = self.write(addr, 2, val as u32);
     }
 
     pub fn sw(&mut self, addr: u32, val: u32) {
-        self.sb(addr, val as u8);
-        self.sb(addr + 1, (val >> 8) as u8);
-        self.sb(addr + 2, (val >> 16) as u8);
-        self.sb(addr + 3, (val >> 24) as u8);
+        let _ = self.write(addr, 4, val);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clint::CLINT_BASE;
+    use crate::machine::Machine;
+    use crate::uart::UART_BASE;
+
+    #[test]
+    fn memory_map_from_config() {
+        let config = Config {
+            ram_base: 0x4000_0000,
+            ram_size: 0x10_0000,
+            ..Default::default()
+        };
+        let map = MemoryMap::from_config(&config);
+        let ranges: Vec<_> = map
+            .regions
+            .iter()
+            .map(|region| (region.kind, region.base, region.size, region.compatible))
+            .collect();
+        assert_eq!(
+            ranges,
+            [
+                (
+                    RegionKind::Device,
+                    CLINT_BASE,
+                    CLINT_SIZE,
+                    Some("riscv,clint0")
+                ),
+                (RegionKind::Device, UART_BASE, UART_SIZE, Some("ns16550a")),
+                (RegionKind::Ram, 0x4000_0000, 0x10_0000, None),
+            ]
+        );
+        // It's what a machine built from the config actually maps
+        assert_eq!(Machine::with_config(1, config).mem.memory_map(), map);
+
+        let config = Config {
+            uart_base: None,
+            clint_base: None,
+            ..Default::default()
+        };
+        let map = MemoryMap::from_config(&config);
+        assert_eq!(map.regions.len(), 1);
+        assert_eq!(map.regions[0].base, RAM_BASE);
+    }
+
+    #[test]
+    fn sparse_ram_allocates_pages_on_first_write() {
+        let mut mem = Mem::sparse(1 << 30);
+        assert_eq!(mem.allocated_pages(), 0);
+        let (low, high) = (RAM_BASE + 0x10, RAM_BASE + 0x3000_0000);
+        mem.sw(low, 0x1111_1111);
+        mem.sw(high, 0x2222_2222);
+        assert_eq!(mem.allocated_pages(), 2);
+        assert_eq!(mem.lw(low), 0x1111_1111);
+        assert_eq!(mem.lw(high), 0x2222_2222);
+        // Reading untouched pages doesn't allocate them
+        assert_eq!(mem.lw(RAM_BASE + 0x1000_0000), 0);
+        assert_eq!(mem.lw(high + PAGE_SIZE), 0);
+        assert_eq!(mem.lw(low + 4), 0);
+        assert_eq!(mem.allocated_pages(), 2);
+    }
+
+    #[test]
+    fn ram_size_and_base_come_from_the_config() {
+        let (base, size) = (0x2000_0000, 64 << 20);
+        let config = Config {
+            ram_base: base,
+            ram_size: size,
+            uart_base: None,
+            clint_base: None,
+            ..Default::default()
+        };
+        let mut machine = Machine::with_config(1, config);
+        assert_eq!(machine.harts[0].pc, base);
+        let mem = &mut machine.mem;
+        assert_eq!(mem.ram_base(), base);
+        assert_eq!(mem.ram_size(), size);
+
+        let last = base + size as u32 - 4;
+        mem.sw(base, 0x1111_1111);
+        mem.sw(last, 0x2222_2222);
+        assert_eq!(mem.lw(base), 0x1111_1111);
+        assert_eq!(mem.lw(last), 0x2222_2222);
+        // Nothing is mapped either side of it, or at the default base
+        for addr in [base - 4, last + 4, RAM_BASE] {
+            assert_eq!(mem.read(addr, 4), Err(AccessFault));
+            assert_eq!(mem.lw(addr), 0);
+        }
     }
 }
@@ -0,0 +1,123 @@
+use crate::mem::Mem;
+
+pub const PAGE_SIZE: u32 = 4096;
+
+pub const SATP_MODE_SV32: u32 = 1 << 31;
+const SATP_PPN: u32 = 0x003FFFFF;
+
+pub const PTE_V: u32 = 1 << 0;
+pub const PTE_R: u32 = 1 << 1;
+pub const PTE_W: u32 = 1 << 2;
+pub const PTE_X: u32 = 1 << 3;
+pub const PTE_U: u32 = 1 << 4;
+pub const PTE_G: u32 = 1 << 5;
+pub const PTE_A: u32 = 1 << 6;
+pub const PTE_D: u32 = 1 << 7;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AccessType {
+    Fetch,
+    Load,
+    Store,
+}
+
+/// A successful Sv32 walk.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Leaf {
+    pub paddr: u32,
+    /// Physical address of the leaf PTE
+    pub pte_addr: u32,
+    pub pte: u32,
+    /// 1 for a 4 MiB megapage, 0 for a regular page
+    pub level: u32,
+}
+
+impl Leaf {
+    pub fn readable(&self) -> bool {
+        self.pte & PTE_R != 0
+    }
+
+    pub fn writable(&self) -> bool {
+        self.pte & PTE_W != 0
+    }
+
+    pub fn executable(&self) -> bool {
+        self.pte & PTE_X != 0
+    }
+
+    pub fn user(&self) -> bool {
+        self.pte & PTE_U != 0
+    }
+}
+
+/// Why a walk failed to find a leaf.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WalkFault {
+    /// The PTE at `level` has V clear, or is W without R
+    Invalid { level: u32 },
+    /// Level 0 held a pointer to another table
+    TooDeep,
+    /// A megapage whose PPN[0] isn't zero
+    MisalignedSuperpage,
+}
+
+/// Every PTE a walk looked at, root first, and where it ended up.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PageWalk {
+    pub ptes: Vec<(u32, u32)>,
+    pub result: Result<Leaf, WalkFault>,
+}
+
+/// Walks the Sv32 table rooted at `satp` for `vaddr`. This only follows the
+/// structure of the table; permission checks are up to the caller.
+pub fn walk(mem: &mut Mem, satp: u32, vaddr: u32) -> PageWalk {
+    let vpn = [(vaddr >> 12) & 0x3FF, vaddr >> 22];
+    let mut ptes = Vec::new();
+    // Sv32 physical addresses are 34 bits, anything above 4 GiB wraps.
+    let mut table = (satp & SATP_PPN) << 12;
+    for level in (0..2).rev() {
+        let pte_addr = table.wrapping_add(vpn[level as usize] * 4);
+        let pte = mem.lw(pte_addr);
+        ptes.push((pte_addr, pte));
+
+        if pte & PTE_V == 0 || (pte & PTE_R == 0 && pte & PTE_W != 0) {
+            return PageWalk {
+                ptes,
+                result: Err(WalkFault::Invalid { level }),
+            };
+        }
+        let ppn = pte >> 10;
+        if pte & (PTE_R | PTE_X) == 0 {
+            if level == 0 {
+                return PageWalk {
+                    ptes,
+                    result: Err(WalkFault::TooDeep),
+                };
+            }
+            table = ppn << 12;
+            continue;
+        }
+
+        let paddr = if level == 1 {
+            if ppn & 0x3FF != 0 {
+                return PageWalk {
+                    ptes,
+                    result: Err(WalkFault::MisalignedSuperpage),
+                };
+            }
+            (ppn << 12) | (vaddr & 0x3FFFFF)
+        } else {
+            (ppn << 12) | (vaddr & 0xFFF)
+        };
+        return PageWalk {
+            ptes,
+            result: Ok(Leaf {
+                paddr,
+                pte_addr,
+                pte,
+                level,
+            }),
+        };
+    }
+    unreachable!()
+}
@@ -0,0 +1,114 @@
+//! Physical memory protection, which lets M mode give S and U mode access to
+//! only the regions it sets up in `pmpcfg0`-`pmpcfg3` and
+//! `pmpaddr0`-`pmpaddr15`. Locked entries hold for M mode too.
+//!
+//! Entries have a granularity of 4 bytes, so every `pmpaddr` bit is
+//! writable and NA4 is available.
+
+use crate::core::PrivMode;
+use crate::csr;
+use crate::mmu::AccessType;
+
+pub(crate) const ENTRIES: usize = 16;
+
+// Address-matching modes, from pmpcfg.A
+const TOR: u32 = 1;
+const NA4: u32 = 2;
+const NAPOT: u32 = 3;
+
+/// The bits of a configuration byte that aren't reserved
+const CFG_WRITABLE: u32 = csr::PMP_R | csr::PMP_W | csr::PMP_X | csr::PMP_A | csr::PMP_L;
+
+/// The configuration byte of entry `i`.
+fn cfg(csrs: &[u32; 4096], i: usize) -> u32 {
+    (csrs[csr::PMPCFG0 as usize + i / 4] >> (8 * (i % 4))) & 0xff
+}
+
+fn mode(cfg: u32) -> u32 {
+    (cfg & csr::PMP_A) >> csr::PMP_A_SHIFT
+}
+
+/// The physical addresses entry `i` covers, as a half-open range in the
+/// 34-bit physical address space. `None` if it's off.
+fn range(csrs: &[u32; 4096], i: usize) -> Option<(u64, u64)> {
+    let pmpaddr = |i: usize| csrs[csr::PMPADDR0 as usize + i] as u64;
+    match mode(cfg(csrs, i)) {
+        TOR => {
+            let bottom = if i == 0 { 0 } else { pmpaddr(i - 1) << 2 };
+            Some((bottom, pmpaddr(i) << 2))
+        }
+        NA4 => Some((pmpaddr(i) << 2, (pmpaddr(i) << 2) + 4)),
+        NAPOT => {
+            // The trailing ones give the size, 8 bytes for none
+            let ones = (!pmpaddr(i)).trailing_zeros();
+            let base = (pmpaddr(i) & !((1 << ones) - 1)) << 2;
+            Some((base, base + (8 << ones)))
+        }
+        _ => None,
+    }
+}
+
+/// Whether a locked entry keeps `pmpaddr{i}` from being written. A TOR
+/// entry also locks the address below it, which is its bottom.
+pub(crate) fn addr_locked(csrs: &[u32; 4096], i: usize) -> bool {
+    let locked = |i: usize| cfg(csrs, i) & csr::PMP_L != 0;
+    locked(i) || (i + 1 < ENTRIES && locked(i + 1) && mode(cfg(csrs, i + 1)) == TOR)
+}
+
+/// What a write of `new` to a pmpcfg CSR holding `old` leaves in it. Locked
+/// entries keep their byte, and the reserved W-without-R combination drops
+/// the W.
+pub(crate) fn write_cfg(old: u32, new: u32) -> u32 {
+    let mut val = 0;
+    for byte in 0..4 {
+        let shift = 8 * byte;
+        let old = (old >> shift) & 0xff;
+        let mut cfg = (new >> shift) & CFG_WRITABLE;
+        if old & csr::PMP_L != 0 {
+            cfg = old;
+        } else if cfg & csr::PMP_R == 0 {
+            cfg &= !csr::PMP_W;
+        }
+        val |= cfg << shift;
+    }
+    val
+}
+
+/// Whether an access of `size` bytes at physical address `addr`, made in
+/// `priv_mode`, is allowed. The lowest-numbered entry that matches any of
+/// its bytes decides, and fails the access unless it matches all of them.
+/// M mode is only held to locked entries, and an access no entry matches
+/// only succeeds in M mode.
+pub(crate) fn allows(
+    csrs: &[u32; 4096],
+    addr: u32,
+    size: u32,
+    access: AccessType,
+    priv_mode: PrivMode,
+) -> bool {
+    let start = addr as u64;
+    let end = start + size as u64;
+    for i in 0..ENTRIES {
+        let (bottom, top) = match range(csrs, i) {
+            Some(range) => range,
+            None => continue,
+        };
+        if bottom >= top || end <= bottom || start >= top {
+            continue;
+        }
+        if start < bottom || end > top {
+            return false;
+        }
+        let cfg = cfg(csrs, i);
+        if priv_mode == PrivMode::Machine && cfg & csr::PMP_L == 0 {
+            return true;
+        }
+        let needed = match access {
+            AccessType::Fetch => csr::PMP_X,
+            AccessType::Load => csr::PMP_R,
+            AccessType::Store => csr::PMP_W,
+        };
+        return cfg & needed != 0;
+    }
+    priv_mode == PrivMode::Machine
+}
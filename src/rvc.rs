@@ -0,0 +1,239 @@
+//! Expansion of RV32C compressed instructions into the 32-bit instructions
+//! they stand for, so the rest of the core only has to execute one format.
+
+fn bits(inst: u32, hi: u32, lo: u32) -> u32 {
+    (inst >> lo) & ((1 << (hi - lo + 1)) - 1)
+}
+
+fn sign_extend(val: u32, width: u32) -> u32 {
+    ((val << (32 - width)) as i32 >> (32 - width)) as u32
+}
+
+/// One of the eight registers x8-x15 a 3-bit compressed field names.
+fn creg(field: u32) -> u32 {
+    field + 8
+}
+
+fn i_type(imm: u32, rs1: u32, funct3: u32, rd: u32, opcode: u32) -> u32 {
+    (imm & 0xFFF) << 20 | rs1 << 15 | funct3 << 12 | rd << 7 | opcode
+}
+
+fn s_type(imm: u32, rs2: u32, rs1: u32, funct3: u32, opcode: u32) -> u32 {
+    bits(imm, 11, 5) << 25 | rs2 << 20 | rs1 << 15 | funct3 << 12 | bits(imm, 4, 0) << 7 | opcode
+}
+
+fn b_type(imm: u32, rs2: u32, rs1: u32, funct3: u32) -> u32 {
+    bits(imm, 12, 12) << 31
+        | bits(imm, 10, 5) << 25
+        | rs2 << 20
+        | rs1 << 15
+        | funct3 << 12
+        | bits(imm, 4, 1) << 8
+        | bits(imm, 11, 11) << 7
+        | 0b1100011
+}
+
+fn j_type(imm: u32, rd: u32) -> u32 {
+    bits(imm, 20, 20) << 31
+        | bits(imm, 10, 1) << 21
+        | bits(imm, 11, 11) << 20
+        | bits(imm, 19, 12) << 12
+        | rd << 7
+        | 0b1101111
+}
+
+fn r_type(funct7: u32, rs2: u32, rs1: u32, funct3: u32, rd: u32) -> u32 {
+    funct7 << 25 | rs2 << 20 | rs1 << 15 | funct3 << 12 | rd << 7 | 0b0110011
+}
+
+const OP_IMM: u32 = 0b0010011;
+const LOAD: u32 = 0b0000011;
+const STORE: u32 = 0b0100011;
+const LOAD_FP: u32 = 0b0000111;
+const STORE_FP: u32 = 0b0100111;
+const JALR: u32 = 0b1100111;
+const LUI: u32 = 0b0110111;
+const EBREAK: u32 = 0x00100073;
+
+/// The CI-format immediate of c.addi, c.li and c.andi.
+fn ci_imm(inst: u32) -> u32 {
+    sign_extend(bits(inst, 12, 12) << 5 | bits(inst, 6, 2), 6)
+}
+
+/// The offset of c.j and c.jal.
+fn cj_imm(inst: u32) -> u32 {
+    let imm = bits(inst, 12, 12) << 11
+        | bits(inst, 11, 11) << 4
+        | bits(inst, 10, 9) << 8
+        | bits(inst, 8, 8) << 10
+        | bits(inst, 7, 7) << 6
+        | bits(inst, 6, 6) << 7
+        | bits(inst, 5, 3) << 1
+        | bits(inst, 2, 2) << 5;
+    sign_extend(imm, 12)
+}
+
+/// The offset of c.beqz and c.bnez.
+fn cb_imm(inst: u32) -> u32 {
+    let imm = bits(inst, 12, 12) << 8
+        | bits(inst, 11, 10) << 3
+        | bits(inst, 6, 5) << 6
+        | bits(inst, 4, 3) << 1
+        | bits(inst, 2, 2) << 5;
+    sign_extend(imm, 9)
+}
+
+/// The word offset of c.lw, c.sw, c.flw and c.fsw.
+fn clw_imm(inst: u32) -> u32 {
+    bits(inst, 12, 10) << 3 | bits(inst, 6, 6) << 2 | bits(inst, 5, 5) << 6
+}
+
+/// The word offset of c.lwsp and c.flwsp.
+fn clwsp_imm(inst: u32) -> u32 {
+    bits(inst, 12, 12) << 5 | bits(inst, 6, 4) << 2 | bits(inst, 3, 2) << 6
+}
+
+/// The word offset of c.swsp and c.fswsp.
+fn cswsp_imm(inst: u32) -> u32 {
+    bits(inst, 12, 9) << 2 | bits(inst, 8, 7) << 6
+}
+
+/// Expands the 16-bit instruction `inst` into its 32-bit equivalent. `None`
+/// if it's illegal or reserved, or needs an extension we don't have.
+pub(crate) fn expand(inst: u16) -> Option<u32> {
+    let inst = inst as u32;
+    let funct3 = bits(inst, 15, 13);
+    let rd = bits(inst, 11, 7);
+    let rs2 = bits(inst, 6, 2);
+    let rd_c = creg(bits(inst, 4, 2));
+    let rs1_c = creg(bits(inst, 9, 7));
+
+    let expanded = match (bits(inst, 1, 0), funct3) {
+        // C.ADDI4SPN. All zeros is the defined illegal instruction.
+        (0b00, 0b000) => {
+            let imm = bits(inst, 12, 11) << 4
+                | bits(inst, 10, 7) << 6
+                | bits(inst, 6, 6) << 2
+                | bits(inst, 5, 5) << 3;
+            if imm == 0 {
+                return None;
+            }
+            i_type(imm, 2, 0, rd_c, OP_IMM)
+        }
+        // C.LW
+        (0b00, 0b010) => i_type(clw_imm(inst), rs1_c, 0b010, rd_c, LOAD),
+        // C.FLW
+        (0b00, 0b011) => i_type(clw_imm(inst), rs1_c, 0b010, rd_c, LOAD_FP),
+        // C.SW
+        (0b00, 0b110) => s_type(clw_imm(inst), rd_c, rs1_c, 0b010, STORE),
+        // C.FSW
+        (0b00, 0b111) => s_type(clw_imm(inst), rd_c, rs1_c, 0b010, STORE_FP),
+        // C.ADDI, or C.NOP with rd = 0
+        (0b01, 0b000) => i_type(ci_imm(inst), rd, 0, rd, OP_IMM),
+        // C.JAL
+        (0b01, 0b001) => j_type(cj_imm(inst), 1),
+        // C.LI
+        (0b01, 0b010) => i_type(ci_imm(inst), 0, 0, rd, OP_IMM),
+        // C.ADDI16SP
+        (0b01, 0b011) if rd == 2 => {
+            let imm = bits(inst, 12, 12) << 9
+                | bits(inst, 6, 6) << 4
+                | bits(inst, 5, 5) << 6
+                | bits(inst, 4, 3) << 7
+                | bits(inst, 2, 2) << 5;
+            if imm == 0 {
+                return None;
+            }
+            i_type(sign_extend(imm, 10), 2, 0, 2, OP_IMM)
+        }
+        // C.LUI
+        (0b01, 0b011) => {
+            let imm = ci_imm(inst);
+            if imm == 0 {
+                return None;
+            }
+            (imm << 12) | rd << 7 | LUI
+        }
+        (0b01, 0b100) => {
+            let rd = rs1_c;
+            // RV32 shifts can't have shamt[5] set
+            let shamt = bits(inst, 6, 2);
+            match bits(inst, 11, 10) {
+                0b00 if bits(inst, 12, 12) == 0 => i_type(shamt, rd, 0b101, rd, OP_IMM),
+                0b01 if bits(inst, 12, 12) == 0 => {
+                    i_type(0b0100000 << 5 | shamt, rd, 0b101, rd, OP_IMM)
+                }
+                0b10 => i_type(ci_imm(inst), rd, 0b111, rd, OP_IMM),
+                0b11 if bits(inst, 12, 12) == 0 => match bits(inst, 6, 5) {
+                    0b00 => r_type(0b0100000, rd_c, rd, 0b000, rd),
+                    0b01 => r_type(0, rd_c, rd, 0b100, rd),
+                    0b10 => r_type(0, rd_c, rd, 0b110, rd),
+                    _ => r_type(0, rd_c, rd, 0b111, rd),
+                },
+                _ => return None,
+            }
+        }
+        // C.J
+        (0b01, 0b101) => j_type(cj_imm(inst), 0),
+        // C.BEQZ and C.BNEZ
+        (0b01, 0b110) => b_type(cb_imm(inst), 0, rs1_c, 0b000),
+        (0b01, 0b111) => b_type(cb_imm(inst), 0, rs1_c, 0b001),
+        // C.SLLI
+        (0b10, 0b000) if bits(inst, 12, 12) == 0 => i_type(rs2, rd, 0b001, rd, OP_IMM),
+        // C.LWSP
+        (0b10, 0b010) if rd != 0 => i_type(clwsp_imm(inst), 2, 0b010, rd, LOAD),
+        // C.FLWSP, which unlike C.LWSP can load f0
+        (0b10, 0b011) => i_type(clwsp_imm(inst), 2, 0b010, rd, LOAD_FP),
+        (0b10, 0b100) => match (bits(inst, 12, 12), rd, rs2) {
+            // C.JR
+            (0, 0, 0) => return None,
+            (0, _, 0) => i_type(0, rd, 0, 0, JALR),
+            // C.MV
+            (0, _, _) => r_type(0, rs2, 0, 0, rd),
+            (1, 0, 0) => EBREAK,
+            // C.JALR
+            (1, _, 0) => i_type(0, rd, 0, 1, JALR),
+            // C.ADD
+            _ => r_type(0, rs2, rd, 0, rd),
+        },
+        // C.SWSP
+        (0b10, 0b110) => s_type(cswsp_imm(inst), rs2, 2, 0b010, STORE),
+        // C.FSWSP
+        (0b10, 0b111) => s_type(cswsp_imm(inst), rs2, 2, 0b010, STORE_FP),
+        // The double-precision loads and stores, and the reserved encodings
+        _ => return None,
+    };
+    Some(expanded)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn expands_to_the_32_bit_equivalent() {
+        let cases = [
+            // c.addi ra, 1 and addi ra, ra, 1
+            (0x0085, 0x0010_8093),
+            // c.li a0, -5 and addi a0, zero, -5
+            (0x556d, 0xffb0_0513),
+            // c.lw a1, 4(a0)
+            (0x414c, 0x0045_2583),
+            // c.sw a1, 8(a0)
+            (0xc50c, 0x00b5_2423),
+            // c.jr ra and jalr zero, 0(ra)
+            (0x8082, 0x0000_8067),
+            // c.jalr a5 and jalr ra, 0(a5)
+            (0x9782, 0x0007_80e7),
+            // c.beqz a0, -4 and beq a0, zero, -4
+            (0xdd75, 0xfe05_0ee3),
+            // c.addi4spn a0, sp, 16 and addi a0, sp, 16
+            (0x0808, 0x0101_0513),
+        ];
+        for &(inst, expanded) in &cases {
+            assert_eq!(expand(inst), Some(expanded), "{:#06x}", inst);
+        }
+        // All zeroes is defined to be illegal
+        assert_eq!(expand(0), None);
+    }
+}
@@ -0,0 +1,50 @@
+//! Numbers from the RISC-V Supervisor Binary Interface, for the calls
+//! `Config::sbi` services. The extension goes in `a7` and the function in
+//! `a6`. Calls return an error code in `a0` and a value in `a1`, except the
+//! legacy extensions, which only return `a0`.
+
+// Legacy extensions, one function each
+pub const LEGACY_SET_TIMER: u32 = 0x00;
+pub const LEGACY_CONSOLE_PUTCHAR: u32 = 0x01;
+pub const LEGACY_CONSOLE_GETCHAR: u32 = 0x02;
+pub const LEGACY_SHUTDOWN: u32 = 0x08;
+
+pub const EXT_BASE: u32 = 0x10;
+pub const EXT_TIME: u32 = 0x5449_4D45;
+pub const EXT_SRST: u32 = 0x5352_5354;
+
+// Base extension functions
+pub const BASE_GET_SPEC_VERSION: u32 = 0;
+pub const BASE_GET_IMPL_ID: u32 = 1;
+pub const BASE_GET_IMPL_VERSION: u32 = 2;
+pub const BASE_PROBE_EXTENSION: u32 = 3;
+pub const BASE_GET_MVENDORID: u32 = 4;
+pub const BASE_GET_MARCHID: u32 = 5;
+pub const BASE_GET_MIMPID: u32 = 6;
+
+pub const TIME_SET_TIMER: u32 = 0;
+pub const SRST_SYSTEM_RESET: u32 = 0;
+pub const SRST_TYPE_SHUTDOWN: u32 = 0;
+
+pub const SUCCESS: u32 = 0;
+pub const ERR_NOT_SUPPORTED: u32 = -2i32 as u32;
+
+/// Version 1.0 of the spec, major version in bits 30:24
+pub const SPEC_VERSION: u32 = 1 << 24;
+/// There's no registered implementation ID for this emulator, so it
+/// reports one well clear of them
+pub const IMPL_ID: u32 = 0xFFFF_FFFF;
+
+/// Whether `ext` is one of the extensions implemented here.
+pub fn is_supported(ext: u32) -> bool {
+    matches!(
+        ext,
+        LEGACY_SET_TIMER
+            | LEGACY_CONSOLE_PUTCHAR
+            | LEGACY_CONSOLE_GETCHAR
+            | LEGACY_SHUTDOWN
+            | EXT_BASE
+            | EXT_TIME
+            | EXT_SRST
+    )
+}
@@ -0,0 +1,96 @@
+use std::collections::BTreeMap;
+use std::path::Path;
+
+/// Guest symbol names keyed by address.
+#[derive(Debug, Clone, Default)]
+pub struct SymbolTable {
+    syms: BTreeMap<u32, String>,
+}
+
+impl SymbolTable {
+    /// Parses either `addr name` lines or `nm` output (`addr type name`).
+    /// Addresses are hex, with or without a `0x` prefix. Lines that don't
+    /// fit, like undefined symbols in `nm` output, are skipped.
+    pub fn parse(text: &str) -> SymbolTable {
+        let mut table = SymbolTable::default();
+        for line in text.lines() {
+            let fields: Vec<&str> = line.split_whitespace().collect();
+            let (addr, name) = match fields.as_slice() {
+                [addr, name] | [addr, _, name] => (addr, name),
+                _ => continue,
+            };
+            let addr = addr.trim_start_matches("0x");
+            if let Ok(addr) = u32::from_str_radix(addr, 16) {
+                table.insert(addr, name.to_string());
+            }
+        }
+        table
+    }
+
+    pub fn from_file(path: impl AsRef<Path>) -> std::io::Result<SymbolTable> {
+        Ok(SymbolTable::parse(&std::fs::read_to_string(path)?))
+    }
+
+    pub fn insert(&mut self, addr: u32, name: String) {
+        self.syms.insert(addr, name);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.syms.is_empty()
+    }
+
+    /// The address of the symbol called `name`.
+    pub fn address_of(&self, name: &str) -> Option<u32> {
+        self.syms
+            .iter()
+            .find(|(_, sym)| sym.as_str() == name)
+            .map(|(&addr, _)| addr)
+    }
+
+    /// The closest symbol at or below `addr`, and how far past it `addr` is.
+    pub fn lookup(&self, addr: u32) -> Option<(&str, u32)> {
+        self.syms
+            .range(..=addr)
+            .next_back()
+            .map(|(&base, name)| (name.as_str(), addr - base))
+    }
+
+    /// Renders `addr` as `name` or `name+0x10`, if there's a symbol for it.
+    pub fn describe(&self, addr: u32) -> Option<String> {
+        self.lookup(addr).map(|(name, off)| {
+            if off == 0 {
+                name.to_string()
+            } else {
+                format!("{}+{:#x}", name, off)
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn symbols_from_a_file_resolve_both_ways() {
+        let path = std::env::temp_dir().join(format!("riscv-emu-{}.sym", std::process::id()));
+        std::fs::write(
+            &path,
+            "80000000 T _start\n0x80000100 main\n         U printf\n80001000 D buffer\n",
+        )
+        .unwrap();
+        let table = SymbolTable::from_file(&path);
+        std::fs::remove_file(&path).unwrap();
+
+        let table = table.unwrap();
+        assert_eq!(table.address_of("_start"), Some(0x8000_0000));
+        assert_eq!(table.address_of("main"), Some(0x8000_0100));
+        assert_eq!(table.address_of("buffer"), Some(0x8000_1000));
+        assert_eq!(table.address_of("printf"), None);
+        assert_eq!(table.lookup(0x8000_0100), Some(("main", 0)));
+        assert_eq!(table.lookup(0x8000_0134), Some(("main", 0x34)));
+        assert_eq!(table.describe(0x8000_0004).as_deref(), Some("_start+0x4"));
+        assert_eq!(table.lookup(0x7fff_fffc), None);
+        assert!(SymbolTable::from_file(&path).is_err());
+    }
+}
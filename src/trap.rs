@@ -0,0 +1,18 @@
+/// Synchronous exception causes, as written to `mcause`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Exception {
+    InstructionAddressMisaligned = 0,
+    InstructionAccessFault = 1,
+    IllegalInstruction = 2,
+    Breakpoint = 3,
+    LoadAddressMisaligned = 4,
+    LoadAccessFault = 5,
+    StoreAddressMisaligned = 6,
+    StoreAccessFault = 7,
+    EnvCallFromU = 8,
+    EnvCallFromS = 9,
+    EnvCallFromM = 11,
+    InstructionPageFault = 12,
+    LoadPageFault = 13,
+    StorePageFault = 15,
+}
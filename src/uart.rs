@@ -0,0 +1,64 @@
+use crate::mem::MmioDevice;
+use std::io::Write;
+
+pub const UART_BASE: u32 = 0x10000000;
+pub const UART_SIZE: u32 = 0x100;
+/// The UART's device tree `compatible` string
+pub const UART_COMPATIBLE: &str = "ns16550a";
+
+const THR: u32 = 0;
+const IER: u32 = 1;
+const MCR: u32 = 4;
+const LSR: u32 = 5;
+
+const LSR_THRE: u32 = 1 << 5;
+const LSR_TEMT: u32 = 1 << 6;
+
+/// A minimal 16550-style UART. Bytes written to THR go to `out`.
+pub struct Uart {
+    out: Box<dyn Write>,
+    /// Set once the guest writes any of the configuration registers
+    configured: bool,
+}
+
+impl Uart {
+    pub fn new(out: Box<dyn Write>) -> Uart {
+        Uart {
+            out,
+            configured: false,
+        }
+    }
+
+    pub fn stdout() -> Uart {
+        Uart::new(Box::new(std::io::stdout()))
+    }
+}
+
+impl MmioDevice for Uart {
+    fn read(&mut self, offset: u32, _width: u32) -> u32 {
+        match offset {
+            // The transmitter is always ready
+            LSR => LSR_THRE | LSR_TEMT,
+            _ => 0,
+        }
+    }
+
+    fn write(&mut self, offset: u32, _width: u32, val: u32) {
+        match offset {
+            THR => {
+                let _ = self.out.write_all(&[val as u8]);
+                let _ = self.out.flush();
+            }
+            IER..=MCR => self.configured = true,
+            _ => {}
+        }
+    }
+
+    fn is_uninitialized(&self, _offset: u32) -> bool {
+        !self.configured
+    }
+
+    fn compatible(&self) -> Option<&'static str> {
+        Some(UART_COMPATIBLE)
+    }
+}
@@ -0,0 +1,19 @@
+# rd, rs1 and rs2 all the same register, so each operand has to be read
+# before the result is written
+.globl _start
+_start:
+    li a0, 5
+    add a0, a0, a0
+    li a1, 7
+    sub a1, a1, a1
+    li a2, 0x0f0f
+    and a2, a2, a2
+    li a3, 3
+    sll a3, a3, a3
+    # Only the low five bits of the shift amount count, so 33 << 1
+    li a4, 33
+    sll a4, a4, a4
+    # -63 >> 1 arithmetically
+    li a5, -63
+    sra a5, a5, a5
+    ebreak
@@ -0,0 +1,15 @@
+# Sums 1..=100 and folds in some shifts and logic ops
+.globl _start
+_start:
+    li a0, 0
+    li a1, 100
+    li a2, 0x5a5a
+loop:
+    add a0, a0, a1
+    slli a3, a1, 7
+    srai a4, a2, 3
+    xor a2, a2, a3
+    or a5, a4, a1
+    addi a1, a1, -1
+    bnez a1, loop
+    ebreak
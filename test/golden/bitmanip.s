@@ -0,0 +1,31 @@
+# Each Zba and Zbb instruction once, on operands that make the result easy
+# to check by hand. Results land in their own registers.
+.globl _start
+_start:
+    li a0, 0x80f00e01
+    li a1, 0x00000013       # 19, and a rotate amount
+    li a2, -5
+    sh1add t0, a1, a0       # 0x80f00e27
+    sh2add t1, a1, a0       # 0x80f00e4d
+    sh3add t2, a1, a0       # 0x80f00e99
+    andn s0, a0, a1         # 0x80f00e00
+    orn s1, a1, a0          # 0x7f0ff1ff
+    xnor a3, a0, a1         # 0x7f0ff1ed
+    clz a4, a1              # 27
+    ctz a5, a0              # 0
+    cpop a6, a0             # 9
+    min a7, a1, a2          # -5
+    minu s2, a1, a2         # 19
+    max s3, a1, a2          # 19
+    maxu s4, a1, a2         # 0xfffffffb
+    rol s5, a0, a1          # 0x700c0780
+    ror s6, a0, a1          # 0x01c0301e
+    rori s7, a0, 4          # 0x180f00e0
+    rev8 s8, a0             # 0x010ef080
+    sext.b s9, a0           # 1
+    sext.h s10, a0          # 0x00000e01
+    zext.h s11, a2          # 0x0000fffb
+    orc.b t3, a0            # 0xffffffff
+    orc.b t4, a1            # 0x000000ff
+    sext.b t5, a2           # -5
+    ebreak
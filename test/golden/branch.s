@@ -0,0 +1,33 @@
+# Takes and skips each kind of branch, counting taken ones in a0 and
+# recording which were skipped in a1
+.globl _start
+_start:
+    li a0, 0
+    li a1, 0
+    li t0, -1
+    li t1, 1
+    beq t0, t0, 1f
+    ori a1, a1, 1
+1:  addi a0, a0, 1
+    bne t0, t1, 1f
+    ori a1, a1, 2
+1:  addi a0, a0, 1
+    blt t0, t1, 1f
+    ori a1, a1, 4
+1:  addi a0, a0, 1
+    bge t1, t0, 1f
+    ori a1, a1, 8
+1:  addi a0, a0, 1
+    bltu t1, t0, 1f
+    ori a1, a1, 16
+1:  addi a0, a0, 1
+    bgeu t0, t1, 1f
+    ori a1, a1, 32
+1:  addi a0, a0, 1
+    beq t0, t1, 1f
+    ori a1, a1, 64
+1:  bltu t0, t1, 1f
+    ori a1, a1, 128
+1:  jal ra, 1f
+    ori a1, a1, 256
+1:  ebreak
@@ -0,0 +1,29 @@
+# Run with a CLINT mapped. Programs mtimecmp to a small value and polls mip
+# until MTIP is raised, with interrupts left disabled, then raises and
+# lowers MSIP through the msip register.
+.globl _start
+_start:
+    li s0, 0x02000000       # msip for hart 0
+    li s1, 0x02004000       # mtimecmp for hart 0
+    li s2, 0x0200bff8       # mtime
+    csrr a0, mip            # == 0
+    sw zero, 4(s1)
+    li t0, 50
+    sw t0, 0(s1)
+    lw a1, 0(s1)            # == 50
+1:
+    csrr t1, mip
+    andi t1, t1, 0x80
+    beqz t1, 1b
+    lw a2, 0(s2)            # >= 50
+    csrr a3, mip            # == MTIP
+    li t0, 1
+    sw t0, 0(s0)
+    csrr a4, mip            # == MTIP | MSIP
+    lw a5, 0(s0)            # == 1
+    sw zero, 0(s0)
+    csrr a6, mip            # == MTIP
+    li t0, -1
+    sw t0, 4(s1)            # push mtimecmp far into the future
+    csrr a7, mip            # == 0
+    ebreak
@@ -0,0 +1,30 @@
+# mcycle and minstret count live, and cycle and instret read the same
+# values. An instruction that traps still takes a cycle but doesn't retire,
+# and the instruction that writes a counter doesn't count itself, so the
+# next one reads the value written.
+.globl _start
+_start:
+    la t0, handler
+    csrw mtvec, t0
+    rdcycle s0
+    rdinstret s1
+    nop
+    nop
+    rdcycle s2              # s0 + 4
+    rdinstret s3            # s1 + 4
+    csrw minstret, zero
+    rdinstret s4            # 0
+    .word 0                 # illegal, so doesn't retire
+    rdinstret s5            # 5: the first rdinstret and the handler
+    li t0, 0xffffffff
+    csrw mcycle, t0
+    rdcycle s6              # 0xffffffff
+    rdcycleh s7             # 1: the rdcycle carried into the high half
+    csrr s8, mcycleh        # same again
+    ebreak
+
+handler:
+    csrr t0, mepc
+    addi t0, t0, 4
+    csrw mepc, t0
+    mret
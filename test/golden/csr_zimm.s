@@ -0,0 +1,30 @@
+# The immediate CSR forms take their operand from the 5-bit rs1 field. A set
+# or clear with a zero operand only reads, so it's allowed on a read-only
+# CSR, while csrrwi always writes even with rd = x0. The handler counts
+# illegal-instruction traps in a0 and skips the instruction.
+.globl _start
+_start:
+    la t0, handler
+    csrw mtvec, t0
+    li t0, 0x55
+    csrw mscratch, t0
+    csrrsi s0, mscratch, 0      # s0 = 0x55, mscratch untouched
+    csrrci s1, mscratch, 0      # the same
+    csrr s2, mscratch           # 0x55
+    csrrsi s3, mscratch, 0x1a   # s3 = 0x55, mscratch = 0x5f
+    csrrci s4, mscratch, 0x1f   # s4 = 0x5f, mscratch = 0x40
+    csrrwi s5, mscratch, 31     # s5 = 0x40, mscratch = 31
+    csrrwi zero, mscratch, 7    # writes without reading
+    csrr s6, mscratch           # 7
+    csrrsi s7, mhartid, 0       # read-only, but this doesn't write
+    csrrci zero, mhartid, 0     # nor this
+    csrrwi zero, mhartid, 0     # writes, so traps
+    csrrsi s8, mhartid, 1       # so does this
+    ebreak
+
+handler:
+    addi a0, a0, 1
+    csrr t0, mepc
+    addi t0, t0, 4
+    csrw mepc, t0
+    mret
@@ -0,0 +1,33 @@
+# CSR accesses are checked against the privilege level in bits 9:8 of the
+# CSR number and the read-only encoding in bits 11:10. The handler counts
+# illegal-instruction traps in a0, shifts each mtval into a1-a2 and the mode
+# each came from (mstatus.MPP) into a3-a4, then skips the instruction.
+.globl _start
+_start:
+    la t0, handler
+    csrw mtvec, t0
+    csrw time, zero         # read-only, traps even in M mode
+    csrr s1, time           # reading is fine
+    csrs time, zero         # so is a set with x0, which doesn't write
+    la t0, user
+    csrw mepc, t0
+    li t0, 0x1800
+    csrc mstatus, t0        # MPP = U
+    mret
+user:
+    csrr s2, mstatus        # M-level CSR from U mode traps
+    csrr s3, time           # U-level, so allowed
+    ebreak
+
+handler:
+    addi a0, a0, 1
+    mv a2, a1
+    csrr a1, mtval
+    mv a4, a3
+    csrr a3, mstatus
+    srli a3, a3, 11
+    andi a3, a3, 3
+    csrr t0, mepc
+    addi t0, t0, 4
+    csrw mepc, t0
+    mret
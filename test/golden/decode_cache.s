@@ -0,0 +1,22 @@
+# With the decode cache on, code that has already run is still seen to
+# change when it's stored over, without a fence.i. The loop runs the same
+# addi three times, doubling its immediate in between, so a0 ends up
+# 1 + 2 + 4 = 7.
+.globl _start
+_start:
+    li s0, 3                # passes
+    li s1, 1                # the immediate
+    la t0, patch
+    li t1, 0x00050513       # addi a0, a0, 0
+loop:
+patch:
+    addi a0, a0, 1
+    addi s0, s0, -1
+    beqz s0, done
+    slli s1, s1, 1
+    slli t2, s1, 20
+    or t2, t2, t1
+    sw t2, 0(t0)
+    j loop
+done:
+    ebreak
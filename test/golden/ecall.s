@@ -0,0 +1,14 @@
+# ecall traps to mtvec with mepc on the ecall itself and mcause 11 (from M)
+.globl _start
+_start:
+    la t0, handler
+    csrw mtvec, t0
+    la s0, call
+call:
+    ecall
+    li a0, 1                # not reached
+handler:
+    auipc s1, 0             # == mtvec
+    csrr a1, mepc           # == s0
+    csrr a2, mcause
+    ebreak
@@ -0,0 +1,15 @@
+# Code patched by a store runs as stored once fence.i has synchronized the
+# instruction stream. The placeholder would leave a0 at 1, the patched-in
+# instruction sets it to 42.
+.globl _start
+_start:
+    fence
+    fence.i                 # both just fall through
+    li a0, 1
+    la t0, patch
+    li t1, 0x02a00513       # addi a0, zero, 42
+    sw t1, 0(t0)
+    fence.i
+patch:
+    nop
+    ebreak
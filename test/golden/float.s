@@ -0,0 +1,93 @@
+# Single-precision floating point. FP instructions and CSRs are illegal
+# until mstatus.FS is turned on, and the handler counts those traps in a0.
+# After that results are moved to the integer registers with fmv.x.w, and
+# fsflags reads and clears the flags each operation raised.
+.globl _start
+_start:
+    la t0, handler
+    csrw mtvec, t0
+    fadd.s ft0, ft0, ft0        # traps, FS is off
+    frflags t0                  # so does this
+    li t0, 0x2000               # FS = initial
+    csrs mstatus, t0
+
+    li t0, 0x3fc00000           # 1.5
+    fmv.w.x ft0, t0
+    li t0, 0x40100000           # 2.25
+    fmv.w.x ft1, t0
+    fadd.s ft2, ft0, ft1
+    fmv.x.w s0, ft2             # 0x40700000, 3.75
+    fsflags s1, zero            # 0, exact
+
+    li t0, 1
+    fcvt.s.w ft3, t0
+    li t0, 3
+    fcvt.s.w ft4, t0
+    fdiv.s ft5, ft3, ft4
+    fmv.x.w s2, ft5             # 0x3eaaaaab, 1/3 rounded up
+    fsflags s3, zero            # 0x01, NX
+    fmv.w.x ft6, zero
+    fdiv.s ft5, ft3, ft6
+    fmv.x.w s4, ft5             # 0x7f800000, +inf
+    fsflags s5, zero            # 0x08, DZ
+
+    fsqrt.s ft5, ft1
+    fmv.x.w s6, ft5             # 0x3fc00000, exactly 1.5
+    fneg.s ft5, ft0
+    fsqrt.s ft5, ft5
+    fmv.x.w s7, ft5             # 0x7fc00000, the canonical NaN
+    fsflags t0, zero            # 0x10, NV
+    slli s7, s7, 8
+    or s7, s7, t0               # so 0xc0000010 all told
+
+    fcvt.w.s s8, ft2, rtz       # 3
+    fcvt.w.s s9, ft2            # 4, to nearest
+    fsflags t0, zero            # 0x01, NX
+    slli s9, s9, 8
+    or s9, s9, t0               # 0x401
+
+    flt.s s10, ft0, ft1         # 1
+    fle.s t0, ft1, ft0          # 0
+    slli s10, s10, 1
+    or s10, s10, t0
+    feq.s t0, ft5, ft5          # 0, NaN isn't equal to itself
+    slli s10, s10, 1
+    or s10, s10, t0             # 0b100
+    fsflags t0, zero            # 0, feq is quiet
+    slli s10, s10, 8
+    or s10, s10, t0             # 0x400
+
+    csrwi frm, 1                # round towards zero
+    li t0, 2
+    fcvt.s.w ft5, t0
+    fdiv.s ft5, ft5, ft4
+    fmv.x.w s11, ft5            # 0x3f2aaaaa, 2/3 rounded down
+    fdiv.s ft5, ft5, ft4, rne
+    csrr a7, fcsr               # 0x21, frm = 1 and NX
+
+    csrr a1, mstatus            # FS dirty, so SD is set too
+    fdiv.s ft5, ft0, ft6
+    fneg.s ft5, ft5
+    fclass.s a2, ft5            # 0x001, -inf
+    fmadd.s ft5, ft0, ft1, ft0
+    fmv.x.w a3, ft5             # 0x409c0000, 1.5 * 2.25 + 1.5
+    li t0, -7
+    fcvt.s.w ft5, t0
+    fmv.x.w a4, ft5             # 0xc0e00000
+    la t1, slot
+    fsw ft2, 0(t1)
+    lw a5, 0(t1)                # 0x40700000
+    fneg.s ft7, ft6
+    fmin.s ft7, ft6, ft7
+    fmv.x.w a6, ft7             # 0x80000000, -0 is the smaller zero
+    ebreak
+
+handler:
+    addi a0, a0, 1
+    csrr t0, mepc
+    addi t0, t0, 4
+    csrw mepc, t0
+    mret
+
+slot:
+    .word 0
@@ -0,0 +1,27 @@
+# Prints "ok\n" through the HTIF console, then reports a pass the way
+# riscv-tests do. The runner puts tohost at 0x80001000 and compares the
+# console against htif.out. The emulator clears tohost once it takes a
+# command, so the guest waits for that before sending the next.
+.globl _start
+_start:
+    li s0, 0x80001000
+    la s1, message
+    li s2, 0x01010000       # device 1, command 1: putchar
+1:
+    lbu t0, 0(s1)
+    beqz t0, 3f
+    sw t0, 0(s0)
+    sw s2, 4(s0)
+2:
+    lw t1, 4(s0)
+    bnez t1, 2b
+    addi s1, s1, 1
+    j 1b
+3:
+    li t0, 1                # test 0, passed
+    sw t0, 0(s0)
+    sw zero, 4(s0)
+    j 3b                    # not reached
+
+message:
+    .asciz "ok\n"
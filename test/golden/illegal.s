@@ -0,0 +1,30 @@
+# Undecodable words trap with mcause 2 and the word itself in mtval. The
+# handler shifts each mtval into a1-a3 and each mepc into s1-s3, keeping the
+# last three, counts the traps in a0 and resumes after the bad word.
+.globl _start
+_start:
+    la t0, handler
+    csrw mtvec, t0
+    la t1, zeros
+    jr t1
+    li a0, 100              # not reached
+handler:
+    addi a0, a0, 1
+    csrr a4, mcause         # == 2
+    mv a3, a2
+    mv a2, a1
+    csrr a1, mtval
+    mv s3, s2
+    mv s2, s1
+    csrr s1, mepc
+    addi t0, s1, 4
+    csrw mepc, t0
+    mret
+zeros:
+    .word 0                 # all zeros is defined to be illegal
+    .word 0xffffffff        # no such opcode
+    .word 0x00200073        # SYSTEM with an unknown funct12
+    .word 0x00007003        # a load with no such width
+    .word 0x00005023        # a store with no such width
+    .word 0x00002063        # a branch with no such condition
+    ebreak
@@ -0,0 +1,24 @@
+# Branch and jump offsets at and beyond the edges of the B-type range, where
+# a misplaced immediate bit would land somewhere else entirely. Each stop
+# adds its own bit to s0, so 15 means every one was reached in order.
+.globl _start
+_start:
+    li s0, 0
+    beq zero, zero, fwd     # +4092, the largest 4-byte aligned B offset
+back:
+    addi s0, s0, 2
+    jal ra, far             # +0x1bf4, with imm[11] and imm[12] set
+fin:
+    addi s0, s0, 8
+    ebreak
+
+.org 0x1000
+fwd:
+    addi s0, s0, 1
+    nop
+    beq zero, zero, back    # -4096, the most negative B offset
+
+.org 0x1c00
+far:
+    addi s0, s0, 4
+    jal zero, fin           # -0x1bf4
@@ -0,0 +1,25 @@
+# Jumps whose link register is also their base, so the target has to be
+# computed from the old value before the link is written
+.globl _start
+_start:
+    la ra, 1f
+    jalr ra, 0(ra)          # to 1f, ra = link1
+link1:
+    li a0, 1                # skipped
+1:
+    mv s0, ra
+    la s1, link1            # s0 == s1
+    la t0, 2f - 8
+    jalr t0, 8(t0)          # to 2f, t0 = link2
+link2:
+    li a0, 2                # skipped
+2:
+    mv s2, t0
+    la s3, link2            # s2 == s3
+    jal ra, 3f              # ra = link3
+link3:
+    li a0, 3                # skipped
+3:
+    mv s4, ra
+    la s5, link3            # s4 == s5
+    ebreak
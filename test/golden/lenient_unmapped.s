@@ -0,0 +1,25 @@
+# Run with Config::lenient_unmapped. A load with nothing mapped at the
+# address reads zero and a store there is dropped, but a load that is only
+# partly outside RAM still faults. The handler counts faults in a0, records
+# the last mcause and mtval in a1 and s1, and skips the instruction.
+.globl _start
+_start:
+    la t0, handler
+    csrw mtvec, t0
+    li t1, 0x1234
+    li a2, -1
+    lw a2, 0(t1)            # == 0
+    sw a2, 4(t1)
+    li t1, 0x81000000       # end of RAM
+    li a3, -1
+    lw a3, -2(t1)           # faults, a3 is left alone
+    ebreak
+
+handler:
+    addi a0, a0, 1
+    csrr a1, mcause
+    csrr s1, mtval
+    csrr t0, mepc
+    addi t0, t0, 4
+    csrw mepc, t0
+    mret
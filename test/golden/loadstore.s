@@ -0,0 +1,19 @@
+# Stores words, halves and bytes, then loads them back with and without
+# sign extension
+.globl _start
+_start:
+    lui s0, 0x80001
+    li t0, 0x8badf00d
+    sw t0, 0(s0)
+    li t1, -2
+    sh t1, 4(s0)
+    li t2, 0x80
+    sb t2, 6(s0)
+    lw a0, 0(s0)
+    lh a1, 4(s0)
+    lhu a2, 4(s0)
+    lb a3, 6(s0)
+    lbu a4, 6(s0)
+    lb a5, 1(s0)
+    lw a6, 4(s0)
+    ebreak
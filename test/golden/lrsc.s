@@ -0,0 +1,23 @@
+# An uncontended LR/SC pair succeeds, and a store to the reserved word in
+# between makes the SC fail without writing
+.globl _start
+_start:
+    li a0, 0x80001000
+    li t0, 5
+    sw t0, 0(a0)
+    lr.w a1, (a0)
+    addi t1, a1, 1
+    sc.w a2, t1, (a0)       # succeeds: a2 = 0, word = 6
+    lw a3, 0(a0)
+    lr.w a4, (a0)
+    sw zero, 0(a0)
+    sc.w a5, t0, (a0)       # fails: a5 = 1, word stays 0
+    lw a6, 0(a0)
+    # A store elsewhere doesn't break the reservation
+    lr.w s2, (a0)
+    sw t0, 8(a0)
+    sc.w s3, t1, (a0)       # succeeds: s3 = 0
+    lw s4, 0(a0)
+    # Nor does a failed SC leave one behind
+    sc.w s5, t1, (a0)       # fails: s5 = 1
+    ebreak
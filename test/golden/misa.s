@@ -0,0 +1,14 @@
+# misa reports a 32-bit hart with I, M, A, F, S and U, and ignores writes,
+# whether they try to clear extensions or set ones that aren't there.
+# A lone core is hart 0.
+.globl _start
+_start:
+    csrr s0, misa           # 0x40141121
+    csrw misa, zero
+    csrr s1, misa           # unchanged
+    li t0, -1
+    csrw misa, t0
+    csrr s3, misa           # still unchanged
+    li s2, -1
+    csrr s2, mhartid        # 0
+    ebreak
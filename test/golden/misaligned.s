@@ -0,0 +1,43 @@
+# Run with MisalignedAccess::Trap. Aligned accesses go through, a misaligned
+# store traps with mcause 6, a load with mcause 4, and an mret to a
+# misaligned mepc takes an instruction-address-misaligned trap (mcause 0) on
+# the fetch. mtval holds the bad address each time. The handler shifts each
+# mcause into a1-a3 and mtval into s1-s3, and resumes at s11.
+.globl _start
+_start:
+    la t0, handler
+    csrw mtvec, t0
+    li t0, 0x1800           # keep mret in M mode
+    csrs mstatus, t0
+    la s0, data
+    li t1, 0x12345678
+    sw t1, 4(s0)
+    lw a0, 4(s0)            # == 0x12345678
+
+    la s11, 1f
+    sw t1, 2(s0)
+1:
+    la s11, 2f
+    lw t2, 1(s0)
+2:
+    la s11, 3f
+    la t0, 3f
+    addi t0, t0, 2
+    csrw mepc, t0
+    mret
+3:
+    ebreak
+
+handler:
+    mv a3, a2
+    mv a2, a1
+    csrr a1, mcause
+    mv s3, s2
+    mv s2, s1
+    csrr s1, mtval
+    csrw mepc, s11
+    mret
+
+.align 2
+data:
+    .word 0, 0
@@ -0,0 +1,15 @@
+# Accesses a device the runner maps over part of RAM at 0x80001000. It
+# returns the complement of the last value written to it, so loads from its
+# range come back different from what was stored, while RAM just past it
+# doesn't.
+.globl _start
+_start:
+    li s0, 0x80001000
+    li t0, 0x12345678
+    sw t0, 0(s0)
+    lw a0, 0(s0)            # 0xedcba987 from the device
+    sb t0, 4(s0)
+    lbu a1, 4(s0)           # 0x87, the device's read is byte-sized too
+    sw t0, 16(s0)           # just past the device, so plain RAM
+    lw a2, 16(s0)           # 0x12345678
+    ebreak
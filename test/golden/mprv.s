@@ -0,0 +1,36 @@
+# Only the implemented mstatus fields are writable, MPRV makes M-mode loads
+# translate as MPP would, and returning to a lower mode clears MPRV.
+# RAM is identity-mapped by a single user megapage.
+.globl _start
+_start:
+    la t0, handler
+    csrw mtvec, t0
+    li s0, 0
+    li t0, 0x80002800       # root table entry for 0x80000000
+    li t1, 0x200000df       # V R W X U A D, PPN 0x80000
+    sw t1, 0(t0)
+    li t0, 0x80080002       # Sv32, root table at 0x80002000
+    csrw satp, t0
+    li t0, -1
+    csrw mstatus, t0
+    csrr a0, mstatus        # 0x803e79aa, with SD following FS
+    li t0, 0x20800          # MPRV, MPP = S
+    csrw mstatus, t0
+    li t0, 0x80001000
+    lw a1, 0(t0)            # S can't read a user page without SUM
+    ebreak                  # not reached
+handler:
+    bnez s0, 1f
+    csrr a2, mcause         # 13, a load page fault
+    csrr a3, mtval          # 0x80001000
+    li s0, 1
+    li t0, 0x20000          # MPRV, MPP = U
+    csrw mstatus, t0
+    la t0, user
+    csrw mepc, t0
+    mret
+user:
+    ecall
+1:
+    csrr a4, mstatus        # 0: MRET to U cleared MPRV
+    ebreak
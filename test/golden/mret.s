@@ -0,0 +1,24 @@
+# mret returns to mepc, restores MIE from MPIE, sets MPIE and drops to the
+# privilege mode in MPP, leaving MPP as U
+.globl _start
+_start:
+    la t0, handler
+    csrw mtvec, t0
+    li t0, 0x1880           # MPP = M, MPIE
+    csrw mstatus, t0
+    la t0, 1f
+    csrw mepc, t0
+    mret
+1:
+    csrr a0, mstatus        # 0x88: MIE and MPIE, MPP = U
+    li t0, 0x80             # MPP = U, MPIE
+    csrw mstatus, t0
+    la t0, user
+    csrw mepc, t0
+    mret
+user:
+    ecall                   # from U, so mcause 8
+handler:
+    csrr a1, mcause
+    csrr a2, mstatus        # 0x80: MPIE from MIE, MPP = U
+    ebreak
@@ -0,0 +1,24 @@
+# RV32M, including the edge cases the spec defines instead of trapping
+.globl _start
+_start:
+    li t0, -7
+    li t1, 3
+    li t2, 0x80000000
+    li t3, -1
+    mul a0, t0, t1          # -21
+    mulh a1, t0, t1         # -1
+    mulhu a2, t3, t3        # 0xfffffffe
+    mulhsu a3, t3, t3       # -1 * 0xffffffff: high word -1
+    div a4, t0, t1          # -2, rounding toward zero
+    rem a5, t0, t1          # -1
+    divu a6, t0, t1
+    remu a7, t0, t1
+    # Division by zero
+    div s2, t0, zero        # -1
+    divu s3, t0, zero       # 0xffffffff
+    rem s4, t0, zero        # -7
+    remu s5, t0, zero       # -7
+    # Signed overflow
+    div s6, t2, t3          # 0x80000000
+    rem s7, t2, t3          # 0
+    ebreak
@@ -0,0 +1,52 @@
+# PMP limits U mode to what the entries allow, leaves M mode alone unless
+# an entry is locked, and a locked entry ignores writes. Entry 0 makes one
+# page read-only and entry 1 allows everything else. The handler shifts
+# each access fault's mcause into a2-a3 and mtval into a4-a5, then skips
+# the instruction.
+.globl _start
+_start:
+    la t0, handler
+    csrw mtvec, t0
+    li s0, 0x80001000
+    li t0, 0x1234
+    sw t0, 0(s0)
+    li t0, 0x200005ff       # NAPOT, the 4 KiB at 0x80001000
+    csrw pmpaddr0, t0
+    li t0, -1               # NAPOT, all of memory
+    csrw pmpaddr1, t0
+    li t0, 0x1f19           # entry 0 R, entry 1 RWX, both NAPOT
+    csrw pmpcfg0, t0
+    la t0, user
+    csrw mepc, t0
+    mret                    # to U, as MPP is still 0
+
+user:
+    lw a0, 0(s0)            # 0x1234
+    sw a0, 0(s0)            # faults, mcause 7
+    ecall
+
+handler:
+    csrr t0, mcause
+    li t1, 8
+    beq t0, t1, machine
+    mv a3, a2
+    csrr a2, mcause
+    mv a5, a4
+    csrr a4, mtval
+    csrr t0, mepc
+    addi t0, t0, 4
+    csrw mepc, t0
+    mret
+
+machine:
+    li t1, 0x55
+    sw t1, 0(s0)            # M mode isn't held to an unlocked entry
+    lw s1, 0(s0)            # 0x55
+    li t0, 0x80
+    csrs pmpcfg0, t0        # lock entry 0
+    csrw pmpaddr0, zero     # ignored
+    csrr s2, pmpaddr0       # 0x200005ff
+    csrw pmpcfg0, zero      # only clears entry 1
+    csrr s3, pmpcfg0        # 0x99
+    sw t1, 4(s0)            # faults now that the read-only entry is locked
+    ebreak
@@ -0,0 +1,34 @@
+# RV64I and M on a 64-bit hart: full-width arithmetic, the word forms, which
+# sign-extend, and doubleword loads and stores.
+.globl _start
+_start:
+    li a0, 1
+    slli a0, a0, 32         # 0x1_0000_0000
+    addi a0, a0, -1         # 0xffff_ffff, which RV32 couldn't tell from -1
+    addiw a1, a0, 0         # -1, sign-extended from the low word
+    addw a2, a0, a0         # 0xffff_fffe as a word: -2
+    srai a3, a1, 40         # still -1
+    srli a4, a1, 60         # 0xf
+    li t0, 0x12345678
+    slli t0, t0, 32
+    ori t0, t0, 0x7ff       # 0x1234_5678_0000_07ff
+    la t1, data
+    sd t0, 0(t1)
+    ld a5, 0(t1)            # round trip
+    lw a6, 4(t1)            # 0x12345678
+    lwu a7, 0(t1)           # 0x7ff
+    li t2, -8
+    sw t2, 8(t1)
+    lw s0, 8(t1)            # -8, sign-extended
+    lwu s1, 8(t1)           # 0xffff_fff8
+    mulhu s2, a1, a1        # 0xffff_ffff_ffff_fffe
+    divw s3, t2, t2         # 1
+    sltu s4, a0, a1         # 1
+    blt a1, zero, 1f        # taken: a1 is negative at 64 bits
+    li s5, 1
+1:
+    csrr s6, misa           # MXL = 2 above I, M and U: 0x8000_0000_0010_1100
+    ebreak
+.p2align 3
+data:
+    .dword 0, 0
@@ -0,0 +1,41 @@
+# With the emulator acting as SBI firmware, ecalls from S mode are serviced
+# rather than trapping. Prints "A\n" through the legacy console, probes a
+# few extensions and shuts down through SRST. The runner compares the
+# console against sbi.out.
+.globl _start
+_start:
+    la t0, supervisor
+    csrw mepc, t0
+    li t0, 0x800
+    csrs mstatus, t0        # MPP = S
+    mret
+
+supervisor:
+    li a7, 0x01             # legacy console_putchar
+    li a0, 'A'
+    ecall
+    li a0, '\n'
+    ecall
+    mv s0, a0               # 0, success
+    li a7, 0x10             # base
+    li a6, 0                # get_spec_version
+    ecall
+    mv s1, a1               # 0x01000000, v1.0
+    li a6, 3                # probe_extension
+    li a0, 0x54494d45       # TIME
+    ecall
+    mv s2, a1               # 1
+    li a0, 0x12345678       # not an extension
+    ecall
+    mv s3, a1               # 0
+    li a7, 0x12345678
+    ecall
+    mv s4, a0               # -2, not supported
+    li a7, 0x53525354       # SRST
+    li a6, 0                # system_reset
+    li a0, 1                # cold reboot, not supported
+    ecall
+    mv s5, a0               # -2
+    li a0, 0                # shutdown
+    ecall
+    j .                     # not reached
@@ -0,0 +1,55 @@
+# SFENCE.VMA is a no-op in M and S mode, but illegal in U mode, and
+# mstatus.TVM makes it and satp illegal in S mode too. The handler counts
+# illegal-instruction traps in a0 and skips the instruction. Any other trap
+# is an ecall, which carries on in M mode at s0.
+.globl _start
+_start:
+    la t0, handler
+    csrw mtvec, t0
+    sfence.vma              # fine in M mode
+    sfence.vma a0, a1
+    la s0, tvm
+    li t0, 0x800            # MPP = S
+    csrw mstatus, t0
+    la t0, super
+    csrw mepc, t0
+    mret
+super:
+    sfence.vma              # fine in S mode
+    csrw satp, zero         # and so is satp
+    ecall
+tvm:
+    li t0, 0x100800         # TVM, MPP = S
+    csrw mstatus, t0
+    la s0, user
+    la t0, super_tvm
+    csrw mepc, t0
+    mret
+super_tvm:
+    sfence.vma              # traps
+    li s1, -1
+    csrr s1, satp           # traps, leaving s1 alone
+    ecall
+user:
+    csrw mstatus, zero      # MPP = U
+    la s0, done
+    la t0, user_code
+    csrw mepc, t0
+    mret
+user_code:
+    sfence.vma              # traps
+    ecall
+done:
+    ebreak                  # a0 = 3
+
+handler:
+    csrr t0, mcause
+    li t1, 2
+    bne t0, t1, 1f
+    addi a0, a0, 1
+    csrr t0, mepc
+    addi t0, t0, 4
+    csrw mepc, t0
+    mret
+1:
+    jr s0
@@ -0,0 +1,23 @@
+# A word load hanging off either end of RAM faults as a whole, with mcause 5
+# and its own address in mtval, rather than reading the RAM half and zeroes.
+# The handler shifts each mtval into s1-s2, then skips the instruction.
+.globl _start
+_start:
+    la t0, handler
+    csrw mtvec, t0
+    li a0, -1
+    li a1, -1
+    li t1, 0x7ffffffe
+    lw a0, 0(t1)            # faults, a0 is left alone
+    li t1, 0x80fffffe       # the last halfword of RAM
+    lw a1, 0(t1)            # faults, a1 is left alone
+    csrr a2, mcause
+    ebreak
+
+handler:
+    mv s2, s1
+    csrr s1, mtval
+    csrr t0, mepc
+    addi t0, t0, 4
+    csrw mepc, t0
+    mret
@@ -0,0 +1,41 @@
+# Run with a CLINT mapped. With mstatus.MIE and mie.MTIE set, the timer
+# interrupt is taken as soon as mtime reaches mtimecmp: mcause has bit 31
+# set and code 7, mepc is the instruction that would have run next, and
+# vectored mode sends it to mtvec + 4 * 7. The handler quiets the timer by
+# pushing mtimecmp out, counts the interrupt in a0 and returns to the loop.
+.globl _start
+_start:
+    la t0, vectors
+    ori t0, t0, 1           # vectored
+    csrw mtvec, t0
+    li s1, 0x02004000       # mtimecmp for hart 0
+    sw zero, 4(s1)
+    li t0, 40
+    sw t0, 0(s1)
+    li t0, 0x80             # MTIE
+    csrs mie, t0
+    csrsi mstatus, 8        # MIE
+1:
+    addi s0, s0, 1
+    beqz a0, 1b
+    csrr a4, mip            # == 0, MTIP went away with the new mtimecmp
+    ebreak
+
+.align 2
+vectors:
+    .rept 7
+    j unexpected
+    .endr
+    j timer
+unexpected:
+    ebreak
+
+timer:
+    auipc s2, 0
+    csrr a1, mcause         # == 0x80000007
+    csrr a2, mepc           # in the loop
+    csrr a3, mstatus        # MPIE set, MIE clear
+    li t0, -1
+    sw t0, 4(s1)
+    addi a0, a0, 1
+    mret
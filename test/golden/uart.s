@@ -0,0 +1,21 @@
+# Prints "Hi\n" through the UART's transmit register, checking LSR for room
+# before each byte. The runner compares the output against uart.out.
+.globl _start
+_start:
+    li s0, 0x10000000
+    la s1, message
+1:
+    lbu t0, 0(s1)
+    beqz t0, 3f
+2:
+    lbu a0, 5(s0)           # LSR
+    andi t1, a0, 0x20       # THRE
+    beqz t1, 2b
+    sb t0, 0(s0)
+    addi s1, s1, 1
+    j 1b
+3:
+    ebreak
+
+message:
+    .asciz "Hi\n"
@@ -0,0 +1,22 @@
+# A load or store with nothing mapped at the address takes an access fault,
+# mcause 5 or 7, with the address in mtval. The handler shifts each mcause
+# into a1-a2 and mtval into s1-s2, then skips the instruction.
+.globl _start
+_start:
+    la t0, handler
+    csrw mtvec, t0
+    li t1, 0x1234
+    li a0, -1
+    lw a0, 0(t1)            # faults, a0 is left alone
+    sw a0, 4(t1)
+    ebreak
+
+handler:
+    mv a2, a1
+    csrr a1, mcause
+    mv s2, s1
+    csrr s1, mtval
+    csrr t0, mepc
+    addi t0, t0, 4
+    csrw mepc, t0
+    mret
@@ -0,0 +1,39 @@
+# wfi with nothing to wait for carries on, waiting on the timer jumps mtime
+# ahead to mtimecmp (the runner turns on Config::wfi_fast_forward and maps
+# a CLINT), and a pending timer makes it fall straight through. With
+# mstatus.TW set it's illegal below M mode, counted by the handler in a0.
+.globl _start
+_start:
+    la t0, handler
+    csrw mtvec, t0
+    wfi                     # nothing enabled in mie
+    li s0, 1
+    li t0, 0x02004000       # mtimecmp
+    li t1, 1000
+    sw t1, 0(t0)
+    sw zero, 4(t0)
+    li t1, 0x80             # MTIE, with mstatus.MIE still clear
+    csrw mie, t1
+    wfi                     # mtime = 1000
+    csrr s1, time           # 1001, counting the wfi's own cycle
+    wfi                     # already pending, so no change
+    csrr s2, time           # s1 + 2
+    li t1, 0x200000         # TW
+    csrs mstatus, t1
+    wfi                     # fine in M mode
+    csrw mie, zero          # or the timer would be taken in U mode
+    la t0, user
+    csrw mepc, t0
+    li t0, 0x1800
+    csrc mstatus, t0        # MPP = U
+    mret
+user:
+    wfi                     # illegal
+    ebreak
+
+handler:
+    addi a0, a0, 1
+    csrr t0, mepc
+    addi t0, t0, 4
+    csrw mepc, t0
+    mret
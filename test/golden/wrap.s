@@ -0,0 +1,21 @@
+# Address and ALU arithmetic wraps around instead of overflowing. Run the
+# golden example in a debug build to catch overflow panics.
+.globl _start
+_start:
+    la t0, handler
+    csrw mtvec, t0
+    li a0, -16
+    lw a1, 32(a0)           # wraps to 0x10, which is unmapped: load fault
+    li a2, 0x7fffffff
+    addi a2, a2, 1          # 0x80000000
+    li a3, -1
+    add a3, a3, a3          # 0xfffffffe
+    sub a6, zero, a3        # 2
+    ebreak
+handler:
+    csrr a4, mcause
+    csrr a5, mtval
+    csrr t1, mepc
+    addi t1, t1, 4
+    csrw mepc, t1
+    mret